@@ -0,0 +1,464 @@
+use rvec::RVec;
+
+/// Upper limit on the number of cells per point, to keep the memory use
+/// bounded for sparse point sets with a small cutoff.
+const MAX_CELLS_PER_POINT: usize = 8;
+
+/// Points binned into a grid of cubic cells, for finding all pairs of points
+/// within a cutoff without comparing every pair.
+///
+/// The cells have a side of at least the cutoff, so pairs within the cutoff are
+/// in the same or adjacent cells. The cells of a list created with `new` span
+/// the bounding box of the points and distances are not periodic. The cells of
+/// a list created with `new_periodic` span a periodic box and distances follow
+/// the minimum image convention.
+pub struct CellList {
+    min: RVec,
+    cell_size: RVec,
+    shape: [usize; 3],
+    cells: Vec<Vec<usize>>,
+    box_size: Option<RVec>,
+}
+
+impl CellList {
+    /// Bin points into cells with a side of at least the cutoff.
+    ///
+    /// Directions along which the extent is not finite, as for points with an infinite
+    /// coordinate, are not divided into cells.
+    pub fn new(points: &[RVec], cutoff: f64) -> CellList {
+        let (min, max) = bounding_box(points).unwrap_or_default();
+
+        let extent = max - min;
+        let max_cells = MAX_CELLS_PER_POINT * points.len().max(1);
+        let mut cell_size = if cutoff > 0.0 { cutoff } else { 1.0 };
+
+        let shape = loop {
+            let num_cells = |length: f64| finite_or_one((length / cell_size).floor() + 1.0);
+            let shape = [
+                num_cells(extent.x),
+                num_cells(extent.y),
+                num_cells(extent.z),
+            ];
+
+            if let Some(shape) = get_shape_within(shape, max_cells) {
+                break shape;
+            }
+
+            cell_size *= 2.0;
+        };
+
+        let cell_size = RVec {
+            x: cell_size,
+            y: cell_size,
+            z: cell_size,
+        };
+
+        CellList::with_points(points, min, cell_size, shape, None)
+    }
+
+    /// Bin points into cells spanning a periodic box, with a side of at least the
+    /// cutoff. Points outside of the box are binned as their periodic image inside it.
+    ///
+    /// The box size must be positive along every direction. Directions with an infinite
+    /// size are not divided into cells.
+    pub fn new_periodic(points: &[RVec], box_size: RVec, cutoff: f64) -> CellList {
+        let max_cells = MAX_CELLS_PER_POINT * points.len().max(1);
+        let mut min_size = if cutoff > 0.0 { cutoff } else { 1.0 };
+
+        let shape = loop {
+            let num_cells = |length: f64| finite_or_one((length / min_size).floor().max(1.0));
+            let shape = [
+                num_cells(box_size.x),
+                num_cells(box_size.y),
+                num_cells(box_size.z),
+            ];
+
+            if let Some(shape) = get_shape_within(shape, max_cells) {
+                break shape;
+            }
+
+            min_size *= 2.0;
+        };
+
+        let cell_size = RVec {
+            x: box_size.x / shape[0] as f64,
+            y: box_size.y / shape[1] as f64,
+            z: box_size.z / shape[2] as f64,
+        };
+
+        CellList::with_points(points, RVec::default(), cell_size, shape, Some(box_size))
+    }
+
+    fn with_points(
+        points: &[RVec],
+        min: RVec,
+        cell_size: RVec,
+        shape: [usize; 3],
+        box_size: Option<RVec>,
+    ) -> CellList {
+        let mut cell_list = CellList {
+            min,
+            cell_size,
+            shape,
+            cells: vec![Vec::new(); shape.iter().product()],
+            box_size,
+        };
+
+        for (i, &point) in points.iter().enumerate() {
+            let index = cell_list.flat_index(cell_list.cell_of(point));
+            cell_list.cells[index].push(i);
+        }
+
+        cell_list
+    }
+
+    /// Return all pairs `(i, j)` with `i < j` of points closer than the cutoff,
+    /// along with their distance. The pairs are sorted by their indices.
+    ///
+    /// The points must be the same as those used to construct the list, and the
+    /// cutoff at most the one it was constructed with.
+    pub fn pairs_within(&self, points: &[RVec], cutoff: f64) -> Vec<(usize, usize, f64)> {
+        let mut pairs = Vec::new();
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let [ix, iy, iz] = self.unflatten_index(index);
+
+            for neighbour in self.neighbour_cells([ix, iy, iz]) {
+                for &i in cell {
+                    for &j in &self.cells[neighbour] {
+                        if i >= j {
+                            continue;
+                        }
+
+                        let distance = self.distance(&points[i], &points[j]);
+
+                        if distance < cutoff {
+                            pairs.push((i, j, distance));
+                        }
+                    }
+                }
+            }
+        }
+
+        pairs.sort_by_key(|&(i, j, _)| (i, j));
+
+        pairs
+    }
+
+    /// Return whether any point is within the cutoff of a query point, which does
+    /// not have to be one of the points.
+    ///
+    /// The points must be the same as those used to construct the list, and the
+    /// cutoff at most the one it was constructed with.
+    pub fn any_within(&self, points: &[RVec], query: RVec, cutoff: f64) -> bool {
+        self.neighbour_cells(self.cell_of(query))
+            .into_iter()
+            .any(|index| {
+                self.cells[index]
+                    .iter()
+                    .any(|&i| self.distance(&query, &points[i]) <= cutoff)
+            })
+    }
+
+    fn distance(&self, point1: &RVec, point2: &RVec) -> f64 {
+        match self.box_size {
+            Some(box_size) => point1.distance_pbc(point2, &box_size),
+            None => point1.distance(point2),
+        }
+    }
+
+    fn cell_of(&self, point: RVec) -> [usize; 3] {
+        let point = match self.box_size {
+            Some(box_size) => RVec {
+                x: point.x.rem_euclid(box_size.x),
+                y: point.y.rem_euclid(box_size.y),
+                z: point.z.rem_euclid(box_size.z),
+            },
+            None => point,
+        };
+
+        let index =
+            |x: f64, x0: f64, size: f64, n: usize| (((x - x0) / size).max(0.0) as usize).min(n - 1);
+
+        [
+            index(point.x, self.min.x, self.cell_size.x, self.shape[0]),
+            index(point.y, self.min.y, self.cell_size.y, self.shape[1]),
+            index(point.z, self.min.z, self.cell_size.z, self.shape[2]),
+        ]
+    }
+
+    fn flat_index(&self, [ix, iy, iz]: [usize; 3]) -> usize {
+        (ix * self.shape[1] + iy) * self.shape[2] + iz
+    }
+
+    fn unflatten_index(&self, index: usize) -> [usize; 3] {
+        let iz = index % self.shape[2];
+        let iy = (index / self.shape[2]) % self.shape[1];
+        let ix = index / (self.shape[1] * self.shape[2]);
+
+        [ix, iy, iz]
+    }
+
+    /// Return the flat indices of the cell and its adjacent cells inside the grid,
+    /// which for a periodic grid wraps around the edges. Every cell is listed once.
+    fn neighbour_cells(&self, [ix, iy, iz]: [usize; 3]) -> Vec<usize> {
+        let periodic = self.box_size.is_some();
+
+        let range = |i: usize, n: usize| -> Vec<usize> {
+            if periodic {
+                let mut indices = vec![(i + n - 1) % n, i, (i + 1) % n];
+                indices.sort_unstable();
+                indices.dedup();
+
+                indices
+            } else {
+                (i.saturating_sub(1)..(i + 2).min(n)).collect()
+            }
+        };
+
+        let mut neighbours = Vec::with_capacity(27);
+
+        for jx in range(ix, self.shape[0]) {
+            for &jy in &range(iy, self.shape[1]) {
+                for &jz in &range(iz, self.shape[2]) {
+                    neighbours.push(self.flat_index([jx, jy, jz]));
+                }
+            }
+        }
+
+        neighbours
+    }
+}
+
+fn bounding_box(points: &[RVec]) -> Option<(RVec, RVec)> {
+    let first = *points.first()?;
+
+    Some(points.iter().fold((first, first), |(min, max), point| {
+        (
+            RVec {
+                x: min.x.min(point.x),
+                y: min.y.min(point.y),
+                z: min.z.min(point.z),
+            },
+            RVec {
+                x: max.x.max(point.x),
+                y: max.y.max(point.y),
+                z: max.z.max(point.z),
+            },
+        )
+    }))
+}
+
+/// Return the number of cells along a direction if it is finite, or else a single cell.
+/// Without this the cell size would be doubled forever for an infinite extent.
+fn finite_or_one(num_cells: f64) -> f64 {
+    if num_cells.is_finite() {
+        num_cells
+    } else {
+        1.0
+    }
+}
+
+/// Return the grid shape from the number of cells along each direction if the total
+/// number of cells is at most the maximum. The count is computed with floats, since
+/// a small cell size can give more cells than fit in a `usize`.
+fn get_shape_within(shape: [f64; 3], max_cells: usize) -> Option<[usize; 3]> {
+    if shape.iter().product::<f64>() <= max_cells as f64 {
+        Some([shape[0] as usize, shape[1] as usize, shape[2] as usize])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_within_cutoff_match_those_found_by_comparing_all_pairs() {
+        let points = (0..60)
+            .map(|i| {
+                let t = i as f64;
+
+                RVec {
+                    x: (t * 0.37).sin() * 3.0,
+                    y: (t * 0.91).cos() * 2.0,
+                    z: t * 0.05,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for &cutoff in &[0.01, 0.5, 1.2, 10.0] {
+            let mut expected = Vec::new();
+
+            for i in 0..points.len() {
+                for j in (i + 1)..points.len() {
+                    let distance = points[i].distance(&points[j]);
+
+                    if distance < cutoff {
+                        expected.push((i, j, distance));
+                    }
+                }
+            }
+
+            let pairs = CellList::new(&points, cutoff).pairs_within(&points, cutoff);
+            assert_eq!(pairs, expected);
+        }
+
+        assert!(CellList::new(&[], 1.0).pairs_within(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn tiny_cutoff_compared_to_extent_gives_a_bounded_grid() {
+        let points = vec![
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 10.0,
+                y: 10.0,
+                z: 10.0,
+            },
+            RVec {
+                x: 10.0,
+                y: 10.0,
+                z: 10.0 + 1e-7,
+            },
+        ];
+
+        let cell_list = CellList::new(&points, 1e-6);
+        assert!(cell_list.cells.len() <= MAX_CELLS_PER_POINT * points.len());
+
+        let pairs = cell_list.pairs_within(&points, 1e-6);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (1, 2));
+
+        let box_size = RVec {
+            x: 10.0,
+            y: 10.0,
+            z: 20.0,
+        };
+
+        let cell_list = CellList::new_periodic(&points, box_size, 1e-6);
+        assert!(cell_list.cells.len() <= MAX_CELLS_PER_POINT * points.len());
+
+        let pairs = cell_list.pairs_within(&points, 1e-6);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (1, 2));
+    }
+
+    #[test]
+    fn infinite_coordinate_or_box_size_gives_a_single_cell_along_it() {
+        let points = vec![
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 0.5,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: f64::INFINITY,
+                y: 0.0,
+                z: 0.0,
+            },
+        ];
+
+        let cell_list = CellList::new(&points, 1.0);
+        assert_eq!(cell_list.shape, [1, 1, 1]);
+
+        let pairs = cell_list.pairs_within(&points, 1.0);
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (0, 1));
+
+        let box_size = RVec {
+            x: f64::INFINITY,
+            y: 2.0,
+            z: 2.0,
+        };
+
+        let cell_list = CellList::new_periodic(&points[..2], box_size, 1.0);
+        assert_eq!(cell_list.shape, [1, 2, 2]);
+    }
+
+    #[test]
+    fn any_point_within_cutoff_of_query_point() {
+        let points = vec![
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 2.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        ];
+
+        let query = RVec {
+            x: 2.7,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        let cell_list = CellList::new(&points, 1.0);
+        assert!(cell_list.any_within(&points, query, 1.0));
+        assert!(!cell_list.any_within(&points, query, 0.5));
+
+        // Across the periodic boundary the query is close to the first point
+        let box_size = RVec {
+            x: 3.0,
+            y: 3.0,
+            z: 3.0,
+        };
+
+        let cell_list = CellList::new_periodic(&points, box_size, 0.5);
+        assert!(cell_list.any_within(&points, query, 0.5));
+    }
+
+    #[test]
+    fn periodic_pairs_within_cutoff_match_those_found_by_comparing_all_pairs() {
+        let box_size = RVec {
+            x: 3.0,
+            y: 2.0,
+            z: 4.0,
+        };
+
+        // Some points are placed outside of the box
+        let points = (0..60)
+            .map(|i| {
+                let t = i as f64;
+
+                RVec {
+                    x: (t * 0.37).sin() * 2.0 + 1.0,
+                    y: (t * 0.91).cos() * 1.5 + 1.0,
+                    z: t * 0.08 - 0.5,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for &cutoff in &[0.01, 0.5, 0.9] {
+            let mut expected = Vec::new();
+
+            for i in 0..points.len() {
+                for j in (i + 1)..points.len() {
+                    let distance = points[i].distance_pbc(&points[j], &box_size);
+
+                    if distance < cutoff {
+                        expected.push((i, j, distance));
+                    }
+                }
+            }
+
+            let pairs =
+                CellList::new_periodic(&points, box_size, cutoff).pairs_within(&points, cutoff);
+            assert_eq!(pairs, expected);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use conf::{get_or_insert_atom_and_residue, Atom, Conf};
+use error;
+use rvec::RVec;
+
+use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
+
+/// CHARMM files use Ångström, we use nm.
+const ANGSTROM_PER_NM: f64 = 10.0;
+
+#[derive(Debug, Fail)]
+pub enum ReadError {
+    #[fail(display = "Could not read line {}: invalid UTF-8", _0)]
+    Utf8Error(usize),
+    #[fail(display = "Could not parse number of atoms entry at line {}", _0)]
+    NumAtomsError(usize),
+    #[fail(display = "Expected an atom entry at line {}", _0)]
+    MissingAtomLine(usize),
+    #[fail(display = "Could not parse atom entry at line {}", _0)]
+    LineError(usize),
+}
+
+/// Column ranges of the residue name, atom name and coordinates of an atom line.
+struct Columns {
+    residue_name: Range<usize>,
+    atom_name: Range<usize>,
+    position: Range<usize>,
+    width: usize,
+}
+
+const STANDARD_COLUMNS: Columns = Columns {
+    residue_name: 11..15,
+    atom_name: 16..20,
+    position: 20..50,
+    width: 10,
+};
+
+const EXTENDED_COLUMNS: Columns = Columns {
+    residue_name: 22..30,
+    atom_name: 32..40,
+    position: 40..100,
+    width: 20,
+};
+
+/// Read a configuration from a CHARMM coordinate file in the CARD format.
+///
+/// The title is read from the leading lines which begin with `*`. Both the standard
+/// and the expanded (`EXT`) formats are read. The format has no box, which is
+/// left empty.
+pub fn read_charmm_crd<R: Read>(reader: R) -> Result<Conf, error::ReadError> {
+    read_crd(reader).map_err(error::ReadError::Charmm)
+}
+
+fn read_crd<R: Read>(reader: R) -> Result<Conf, ReadError> {
+    let mut lines = BufReader::new(reader).lines().enumerate();
+
+    let mut titles = Vec::new();
+
+    let (count_line_num, count_line) = loop {
+        let (i, line) = lines.next().ok_or(ReadError::NumAtomsError(1))?;
+        let line = line.map_err(|_| ReadError::Utf8Error(i + 1))?;
+
+        match line.strip_prefix('*').map(|title| title.trim()) {
+            Some("") => (),
+            Some(title) => titles.push(title.to_string()),
+            None => break (i + 1, line),
+        }
+    };
+
+    let mut count_fields = count_line.split_whitespace();
+    let num_atoms = count_fields
+        .next()
+        .and_then(|s| s.parse::<usize>().ok())
+        .ok_or(ReadError::NumAtomsError(count_line_num))?;
+
+    let columns = match count_fields.next() {
+        Some("EXT") => &EXTENDED_COLUMNS,
+        _ => &STANDARD_COLUMNS,
+    };
+
+    let mut residues = Vec::new();
+    let mut atoms = Vec::new();
+
+    for _ in 0..num_atoms {
+        let (i, line) = lines
+            .next()
+            .ok_or(ReadError::MissingAtomLine(count_line_num + atoms.len() + 1))?;
+        let line = line.map_err(|_| ReadError::Utf8Error(i + 1))?;
+
+        let (residue_name, atom_name, position) =
+            parse_atom_line(&line, columns).ok_or(ReadError::LineError(i + 1))?;
+
+        let (residue, atom) =
+            get_or_insert_atom_and_residue(residue_name, atom_name, &mut residues)
+                .map_err(|_| ReadError::LineError(i + 1))?;
+
+        atoms.push(Atom {
+            name: atom,
+            residue,
+            position,
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        });
+    }
+
+    Ok(Conf {
+        title: titles.join(" "),
+        origin: RVec::default(),
+        size: RVec::default(),
+        residues,
+        atoms,
+    })
+}
+
+fn parse_atom_line<'a>(line: &'a str, columns: &Columns) -> Option<(&'a str, &'a str, RVec)> {
+    let residue_name = line.get(columns.residue_name.clone())?.trim();
+    let atom_name = line.get(columns.atom_name.clone())?.trim();
+    let position = RVec::from_fixed(line.get(columns.position.clone())?, columns.width).ok()?;
+
+    if residue_name.is_empty() || atom_name.is_empty() {
+        return None;
+    }
+
+    Some((residue_name, atom_name, position / ANGSTROM_PER_NM))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn read_charmm_card_file_with_two_residues() {
+        let content = "\
+* A water and an ion
+* in a title
+*
+    4
+    1    1 TIP3 OH2    1.00000   2.00000   3.00000 WAT  1      0.00000
+    2    1 TIP3 H1     4.00000   5.00000   6.00000 WAT  1      0.00000
+    3    1 TIP3 H2     7.00000   8.00000   9.00000 WAT  1      0.00000
+    4    2 SOD  SOD   10.00000  11.00000  12.00000 ION  2      0.00000
+";
+
+        let conf = read_charmm_crd(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.title, "A water and an ion in a title");
+        assert_eq!(conf.size, RVec::default());
+        assert_eq!(conf.atoms.len(), 4);
+
+        assert_eq!(conf.residues.len(), 2);
+        assert_eq!(*conf.residues[0].borrow().name.borrow(), "TIP3");
+        assert_eq!(*conf.residues[1].borrow().name.borrow(), "SOD");
+        assert_eq!(conf.residues[0].borrow().atoms.len(), 3);
+        assert_eq!(&*conf.residues[0].borrow().atoms[2].borrow(), "H2");
+        assert!(Rc::ptr_eq(&conf.atoms[3].residue, &conf.residues[1]));
+        assert!(conf.iter_residues().all(|residue| residue.is_ok()));
+
+        let position = conf.atoms[1].position;
+        assert!((position.x - 0.4).abs() < 1e-9);
+        assert!((position.y - 0.5).abs() < 1e-9);
+        assert!((position.z - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn read_charmm_expanded_card_file() {
+        let content = "\
+* Expanded format
+*
+         1  EXT
+         1         1  TIP3      OH2           1.0000000000        2.0000000000        3.0000000000  WAT       1               0.0000000000
+";
+
+        let conf = read_charmm_crd(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.atoms.len(), 1);
+        assert!(conf.atoms[0].cmp_name("OH2"));
+        assert!(conf.atoms[0].cmp_residue_name("TIP3"));
+        assert!((conf.atoms[0].position.z - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn read_incorrect_charmm_file_returns_error() {
+        assert!(read_charmm_crd("* Title\n".as_bytes()).is_err());
+        assert!(read_charmm_crd("* Title\n    s\n".as_bytes()).is_err());
+
+        let atom_line = "    1    1 TIP3 OH2    1.00000   2.00000   3.00000";
+        let content = format!("* Title\n    2\n{}\n", atom_line);
+        assert!(read_charmm_crd(content.as_bytes()).is_err());
+
+        let atom_line = "    1    1 TIP3 OH2    1.00000   2.0s000   3.00000";
+        let content = format!("* Title\n    1\n{}\n", atom_line);
+        assert!(read_charmm_crd(content.as_bytes()).is_err());
+
+        // Errors are wrapped in the crate error, like those of the other formats
+        match read_charmm_crd("* Title\n    s\n".as_bytes()) {
+            Err(error::ReadError::Charmm(ReadError::NumAtomsError(2))) => (),
+            _ => panic!("expected a number of atoms error"),
+        }
+    }
+}
@@ -1,14 +1,36 @@
+use cells::CellList;
+use charmm;
+use element::guess_element;
 use error::{ReadError, WriteError};
+use format::{ConfFormat, Gromos87, Pdb, Xyz};
 use gromos87;
-use rvec::RVec;
+use hull;
+use linalg;
+use rvec::{Direction, RVec};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::f64::consts::PI;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, Write};
 // use std::ops::Deref;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
+/// Factor which converts velocities from nm/ps to m/s, for use with `Conf::scale_velocities`.
+pub const NM_PER_PS_TO_M_PER_S: f64 = 1000.0;
+
+/// Factor which converts velocities from nm/ps to Å/ps.
+pub const NM_PER_PS_TO_ANGSTROM_PER_PS: f64 = 10.0;
+
+/// Factor which converts velocities from nm/ps to Å/fs.
+pub const NM_PER_PS_TO_ANGSTROM_PER_FS: f64 = 0.01;
+
 /// A system configuration.
 #[derive(Clone, Debug)]
 pub struct Conf {
@@ -46,434 +68,4900 @@ impl Conf {
         gromos87::read_gromos87_conf(&mut reader).map_err(|err| ReadError::Gromos87(err))
     }
 
-    /// Group atoms as their residues and iterate over them.
-    pub fn iter_residues(&self) -> ResidueIter {
-        ResidueIter {
-            index: 0,
-            atoms: &self.atoms,
-        }
+    /// Read a configuration from a `Gromos87` formatted string.
+    pub fn from_gromos87_str(content: &str) -> Result<Conf, ReadError> {
+        gromos87::read_gromos87_conf(content.as_bytes()).map_err(ReadError::Gromos87)
     }
 
-    /// Extend the configuration along each direction by copying and translating the atoms.
-    pub fn pbc_multiply(&self, nx: usize, ny: usize, nz: usize) -> Conf {
-        let mut conf = Conf {
-            title: self.title.clone(),
-            origin: self.origin.clone(),
-            size: self.size.pbc_multiply(nx, ny, nz),
-            residues: self.residues.clone(),
-            atoms: Vec::new(),
-        };
-
-        for ix in 1..(nx + 1) {
-            for iy in 1..(ny + 1) {
-                for iz in 1..(nz + 1) {
-                    let dr = self.size.pbc_multiply(ix - 1, iy - 1, iz - 1);
+    /// Read a configuration from a file, with the format determined by the file extension.
+    ///
+    /// The supported extensions are `.gro` (GROMOS87), `.pdb`, `.xyz`, `.gro.gz`
+    /// (gzip compressed GROMOS87) and `.crd` (CHARMM).
+    pub fn load(path: &Path) -> Result<Conf, ReadError> {
+        match get_file_format(path) {
+            Some(FileFormat::Gromos87) => Conf::read_format::<Gromos87>(path),
+            Some(FileFormat::Gromos87Gz) => {
+                let file = File::open(path)?;
+                let reader = GzDecoder::new(BufReader::new(file));
 
-                    self.atoms.iter().for_each(|atom| {
-                        conf.atoms.push(Atom {
-                            name: Rc::clone(&atom.name),
-                            residue: Rc::clone(&atom.residue),
-                            position: atom.position + dr,
-                            velocity: atom.velocity.clone(),
-                        });
-                    });
-                }
+                Gromos87::read(reader)
             }
+            Some(FileFormat::Pdb) => Conf::read_format::<Pdb>(path),
+            Some(FileFormat::Xyz) => Conf::read_format::<Xyz>(path),
+            Some(FileFormat::Charmm) => {
+                let file = File::open(path)?;
+                charmm::read_charmm_crd(file)
+            }
+            None => Err(ReadError::UnknownFormat(path.display().to_string())),
         }
+    }
 
-        conf
+    /// Read a configuration from a file of the given format.
+    pub fn read_format<F: ConfFormat>(path: &Path) -> Result<Conf, ReadError> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+
+        F::read(reader)
     }
 
-    /// Write the configuration to a GROMOS87 formatted file.
-    pub fn write_gromos87(&self, path: &Path) -> Result<(), WriteError> {
-        let file = File::create(path)?;
-        let mut writer = BufWriter::new(file);
+    /// Iterate over the atoms as owned `(residue name, atom name, position, velocity)` records.
+    pub fn atom_records(&self) -> impl Iterator<Item = (String, String, RVec, Option<RVec>)> + '_ {
+        self.atoms.iter().map(|atom| {
+            (
+                atom.residue.borrow().name.borrow().clone(),
+                atom.name.borrow().clone(),
+                atom.position,
+                atom.velocity,
+            )
+        })
+    }
 
-        gromos87::write_gromos87_conf(self, &mut writer).map_err(|err| WriteError::Gromos87(err))
+    /// Return the index and position of every atom with the input name, in all residues.
+    pub fn atoms_named(&self, atom_name: &str) -> Vec<(usize, RVec)> {
+        self.atoms
+            .iter()
+            .enumerate()
+            .filter(|(_, atom)| atom.cmp_name(atom_name))
+            .map(|(i, atom)| (i, atom.position))
+            .collect()
     }
-}
 
-/// Error from iterating over residues.
-#[derive(Debug, Fail)]
-#[fail(display = "Bad residue starting at index {}", index)]
-pub struct ResidueError {
-    index: usize,
-}
+    /// Return the distinct residue names of the configuration, in the order of their
+    /// first appearance in `residues`. Residues which share a name are listed once.
+    pub fn residue_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
 
-/// An iterator over residues of a collection of `Atom`s.
-#[derive(Debug)]
-pub struct ResidueIter<'a> {
-    index: usize,
-    atoms: &'a [Atom],
-}
+        for residue in &self.residues {
+            let name = residue.borrow().name.borrow().clone();
 
-impl<'a> ResidueIter<'a> {
-    fn get_iter_error(&mut self, i: usize) -> ResidueError {
-        self.index += i;
-        ResidueError {
-            index: self.index - i,
+            if !names.contains(&name) {
+                names.push(name);
+            }
         }
+
+        names
     }
-}
 
-impl<'a> Iterator for ResidueIter<'a> {
-    type Item = Result<Vec<Atom>, ResidueError>;
+    /// Return the name and ordered atom names of every residue in the configuration.
+    pub fn residue_templates(&self) -> Vec<(String, Vec<String>)> {
+        self.residues
+            .iter()
+            .map(|residue| {
+                let residue = residue.borrow();
+                let atom_names = residue
+                    .atoms
+                    .iter()
+                    .map(|name| name.borrow().clone())
+                    .collect();
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let atom1 = self.atoms.get(self.index)?.clone();
+                let name = residue.name.borrow().clone();
 
-        let residue = atom1.residue.clone();
-        let residue_len = residue.borrow().atoms.len();
+                (name, atom_names)
+            })
+            .collect()
+    }
 
-        // If the first atom is wrong, return an error and skip it
-        if !Rc::ptr_eq(&atom1.name, &residue.borrow().atoms[0]) {
-            return Some(Err(self.get_iter_error(1)));
+    /// Group atoms as their residues and iterate over them.
+    pub fn iter_residues(&self) -> ResidueIter {
+        ResidueIter {
+            index: 0,
+            atoms: &self.atoms,
         }
+    }
 
-        let mut atom_list = Vec::new();
-        atom_list.push(atom1);
+    /// Iterate over the complete residues of the configuration, skipping bad residues.
+    pub fn iter_valid_residues(&self) -> impl Iterator<Item = Vec<Atom>> + '_ {
+        self.iter_residues().filter_map(Result::ok)
+    }
 
-        for i in 1..residue_len {
-            match self.atoms.get(i + self.index) {
-                Some(atom) => {
-                    if !Rc::ptr_eq(&atom.name, &residue.borrow().atoms[i]) {
-                        return Some(Err(self.get_iter_error(i)));
-                    }
+    /// Count the atoms of the configuration by their element, as guessed from
+    /// their atom and residue names. Atoms of unknown elements are counted under `"?"`.
+    pub fn element_composition(&self) -> BTreeMap<String, usize> {
+        let mut composition = BTreeMap::new();
 
-                    atom_list.push(atom.clone());
-                }
-                None => {
-                    return Some(Err(self.get_iter_error(i)));
-                }
-            }
+        for atom in &self.atoms {
+            let residue_name = atom.residue.borrow().name.borrow().clone();
+            let element = guess_element(&atom.name.borrow(), &residue_name).unwrap_or("?");
+            *composition.entry(element.to_string()).or_insert(0) += 1;
         }
 
-        self.index += residue_len;
-
-        Some(Ok(atom_list))
+        composition
     }
-}
-
-/// Information about a residue.
-#[derive(Clone, Debug)]
-pub struct Residue {
-    /// The residue name.
-    pub name: Rc<RefCell<String>>,
-    /// Atoms which belong to the residue.
-    pub atoms: Vec<Rc<RefCell<String>>>,
-}
 
-impl Residue {
-    /// Compare the residue's name to an input.
-    pub fn cmp_name(&self, to_name: &str) -> bool {
-        &*self.name.borrow() == to_name
-    }
+    /// Return the minimum and maximum distance between pairs of atoms within each
+    /// complete residue, along with the residue index in the order of `iter_residues`.
+    ///
+    /// Residues with a single atom report both distances as zero.
+    pub fn residue_extent(&self) -> Vec<(usize, f64, f64)> {
+        self.iter_residues()
+            .enumerate()
+            .filter_map(|(i, residue)| residue.ok().map(|atoms| (i, atoms)))
+            .map(|(i, atoms)| {
+                let distances = atoms
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(j, atom1)| {
+                        atoms[j + 1..]
+                            .iter()
+                            .map(move |atom2| atom1.position.distance(&atom2.position))
+                    })
+                    .collect::<Vec<_>>();
 
-    fn get_or_insert_atom(&mut self, atom_name: &str) -> Rc<RefCell<String>> {
-        self.atoms
-            .iter()
-            .find(|name| &*name.borrow() == &atom_name)
-            .cloned()
-            .unwrap_or_else(|| {
-                let atom = Rc::new(RefCell::new(String::from(atom_name)));
-                self.atoms.push(atom.clone());
+                if distances.is_empty() {
+                    (i, 0.0, 0.0)
+                } else {
+                    let min = distances.iter().cloned().fold(f64::INFINITY, f64::min);
+                    let max = distances.iter().cloned().fold(0.0, f64::max);
 
-                atom
+                    (i, min, max)
+                }
             })
+            .collect()
     }
-}
 
-/// A single atom belonging to a residue in the configuration.
-#[derive(Clone, Debug)]
-pub struct Atom {
-    /// A reference to the atom name. Should point to an atom in the `residue`.
-    pub name: Rc<RefCell<String>>,
-    /// A reference to the residue which owns the atom. Will typicall point to a residue
-    /// in the `Conf` in which this atom exists.
-    pub residue: Rc<RefCell<Residue>>,
-    /// The atom position in configuration-relative coordinates.
-    pub position: RVec,
-    /// The atom velocity, if it has one.
-    pub velocity: Option<RVec>,
-}
+    /// Return the handle and geometric center of every complete residue, in the order
+    /// of `iter_residues`. Incomplete residues are skipped.
+    pub fn residue_centers(&self) -> Vec<(Rc<RefCell<Residue>>, RVec)> {
+        self.iter_valid_residues()
+            .map(|atoms| {
+                let sum = atoms
+                    .iter()
+                    .fold(RVec::default(), |acc, atom| acc + atom.position);
 
-impl Atom {
-    /// Compare the atom's name to an input.
-    pub fn cmp_name(&self, to_name: &str) -> bool {
-        &*self.name.borrow() == to_name
+                (Rc::clone(&atoms[0].residue), sum / atoms.len() as f64)
+            })
+            .collect()
     }
 
-    /// Compare the atom's parent residue name to an input.
-    pub fn cmp_residue_name(&self, to_name: &str) -> bool {
-        &*self.residue.borrow().name.borrow() == to_name
-    }
-}
+    /// Return the summed velocity of the atoms of every complete residue, along with
+    /// the residue index in the order of `iter_residues`.
+    ///
+    /// The velocity is `None` if any atom of the residue has no velocity.
+    pub fn residue_velocities(&self) -> Vec<(usize, Option<RVec>)> {
+        self.iter_residues()
+            .enumerate()
+            .filter_map(|(i, residue)| residue.ok().map(|atoms| (i, atoms)))
+            .map(|(i, atoms)| {
+                let velocity = atoms.iter().try_fold(RVec::default(), |acc, atom| {
+                    atom.velocity.map(|velocity| acc + velocity)
+                });
 
-fn get_or_insert_residue(
-    name: &str,
-    residues: &mut Vec<Rc<RefCell<Residue>>>,
-) -> Rc<RefCell<Residue>> {
-    residues
-        .iter()
-        .find(|res| *res.borrow().name.borrow() == name)
-        .cloned()
-        .unwrap_or_else(|| {
-            let res = Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new(String::from(name))),
-                atoms: Vec::new(),
-            }));
+                (i, velocity)
+            })
+            .collect()
+    }
 
-            residues.push(res.clone());
-            res
-        })
-}
+    /// Create a coarse grained configuration with a single bead for every complete
+    /// residue, placed at its geometric center. Incomplete residues are skipped.
+    ///
+    /// The beads are named after their residue and every residue type becomes
+    /// a new residue with that single bead. The beads have no velocities. The title
+    /// and box are kept.
+    pub fn coarse_grain(&self) -> Conf {
+        let mut residues = Vec::new();
 
-pub fn get_or_insert_atom_and_residue(
-    residue_name: &str,
-    atom_name: &str,
-    residues: &mut Vec<Rc<RefCell<Residue>>>,
-) -> Result<(Rc<RefCell<Residue>>, Rc<RefCell<String>>), String> {
-    let residue = get_or_insert_residue(residue_name, residues);
-    let atom = residue.borrow_mut().get_or_insert_atom(atom_name);
+        let atoms = self
+            .residue_centers()
+            .into_iter()
+            .map(|(residue, center)| {
+                let name = residue.borrow().name.borrow().clone();
+                let residue = get_or_insert_residue(&name, &mut residues);
+                let atom = residue.borrow_mut().get_or_insert_atom(&name);
 
-    Ok((residue, atom))
-}
+                Atom {
+                    name: atom,
+                    residue,
+                    position: center,
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                }
+            })
+            .collect();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env::temp_dir;
+        Conf {
+            title: self.title.clone(),
+            origin: self.origin,
+            size: self.size,
+            residues,
+            atoms,
+        }
+    }
 
-    #[test]
-    fn get_or_insert_residue_from_list() {
-        let mut residues = Vec::new();
+    /// Verify that all atoms of the configuration are grouped into complete residues.
+    ///
+    /// Returns the error of the first bad residue, if any.
+    pub fn validate_residues(&self) -> Result<(), ResidueError> {
+        for residue in self.iter_residues() {
+            residue?;
+        }
 
-        let res1_name = "RES1";
-        let res1 = get_or_insert_residue(res1_name, &mut residues);
+        Ok(())
+    }
 
-        assert_eq!(*res1.borrow().name.borrow(), res1_name);
-        assert!(&res1.borrow().atoms.is_empty());
+    /// Verify that every atom links to a name of its residue and to a residue of the
+    /// configuration, by reference.
+    ///
+    /// This catches configurations which were constructed by hand with broken links,
+    /// which otherwise show up as confusing errors from `iter_residues`. The error
+    /// describes the first broken link.
+    pub fn assert_residue_atom_consistency(&self) -> Result<(), String> {
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let residue = atom.residue.borrow();
 
-        assert_eq!(residues.len(), 1);
-        assert!(Rc::ptr_eq(&res1, &residues[0]));
+            if !residue
+                .atoms
+                .iter()
+                .any(|name| Rc::ptr_eq(name, &atom.name))
+            {
+                return Err(format!(
+                    "atom {} ('{}') is not an atom of its residue '{}'",
+                    i,
+                    atom.name.borrow(),
+                    residue.name.borrow()
+                ));
+            }
 
-        let res1_again = get_or_insert_residue(res1_name, &mut residues);
-        assert!(Rc::ptr_eq(&res1, &res1_again));
+            if !self
+                .residues
+                .iter()
+                .any(|other| Rc::ptr_eq(other, &atom.residue))
+            {
+                return Err(format!(
+                    "the residue '{}' of atom {} ('{}') is not in the configuration",
+                    residue.name.borrow(),
+                    i,
+                    atom.name.borrow()
+                ));
+            }
+        }
 
-        let res2_name = "RES2";
-        let res2 = get_or_insert_residue(res2_name, &mut residues);
+        Ok(())
+    }
 
-        assert_eq!(*res2.borrow().name.borrow(), res2_name);
-        assert!(&res2.borrow().atoms.is_empty());
-        assert!(!Rc::ptr_eq(&res1, &res2));
+    /// Check that all residues with the same name have the same atoms, in the same order.
+    ///
+    /// Residues are unified by their name when they are read, so residues of different
+    /// molecules which share a name are merged into a single residue with the atoms of
+    /// both. This check finds such collisions by splitting the atoms into residues
+    /// as in `iter_residues`, where the atoms of an incomplete residue are those which
+    /// are skipped over along with its error. An error which describes the first
+    /// residue whose atoms differ from an earlier residue of the same name is returned.
+    pub fn check_residue_template_consistency(&self) -> Result<(), String> {
+        // The index of the first atom and atom names of the residues, by residue name
+        let mut templates: HashMap<String, (usize, Vec<String>)> = HashMap::new();
 
-        assert_eq!(residues.len(), 2);
-        assert!(Rc::ptr_eq(&res2, &residues[1]));
-    }
+        let mut check_residue = |start: usize, atoms: &[Atom]| -> Result<(), String> {
+            let name = atoms[0].residue.borrow().name.borrow().clone();
+            let atom_names = atoms
+                .iter()
+                .map(|atom| atom.name.borrow().clone())
+                .collect::<Vec<_>>();
 
-    #[test]
-    fn get_or_insert_atom_from_residue() {
-        let mut residue = Residue {
-            name: Rc::new(RefCell::new(String::from("RES"))),
-            atoms: Vec::new(),
+            match templates.get(&name) {
+                Some((first, template)) if *template != atom_names => Err(format!(
+                    "residue '{}' starting at atom {} has atoms {:?}, \
+                     but the residue starting at atom {} has atoms {:?}",
+                    name, start, atom_names, first, template
+                )),
+                Some(_) => Ok(()),
+                None => {
+                    templates.insert(name, (start, atom_names));
+                    Ok(())
+                }
+            }
         };
 
-        let atom1_name = "ATOM1";
-        let atom1 = residue.get_or_insert_atom(atom1_name);
+        for range in get_residue_ranges(self) {
+            let range = range.unwrap_or_else(|(range, _)| range);
+            check_residue(range.start, &self.atoms[range])?;
+        }
 
-        assert_eq!(&*atom1.borrow(), atom1_name);
-        assert!(Rc::ptr_eq(&atom1, &residue.atoms[0]));
+        Ok(())
+    }
 
-        let atom1_again = residue.get_or_insert_atom(atom1_name);
-        assert!(Rc::ptr_eq(&atom1_again, &atom1));
+    /// Assign residue and atom numbers to all atoms, counting up from the input numbers.
+    ///
+    /// Residues are split as in `check_residue_template_consistency`, so incomplete
+    /// residues are also numbered. The numbers wrap at 100000 like those written to
+    /// GROMOS87 files. They are only written with the `write_stored_numbers` option
+    /// and are not updated by methods which derive new configurations, so renumber
+    /// the final configuration before writing it.
+    pub fn renumber_from(&mut self, residue_start: usize, atom_start: usize) {
+        for (i, range) in get_residue_ranges(self).into_iter().enumerate() {
+            let range = range.unwrap_or_else(|(range, _)| range);
 
-        let atom2_name = "ATOM2";
-        let atom2 = residue.get_or_insert_atom(atom2_name);
+            for atom in self.atoms[range].iter_mut() {
+                atom.residue_number = Some((residue_start + i) % 100_000);
+            }
+        }
 
-        assert_eq!(&*atom2.borrow(), atom2_name);
-        assert!(Rc::ptr_eq(&atom2, &residue.atoms[1]));
-        assert!(!Rc::ptr_eq(&atom1, &atom2));
+        for (i, atom) in self.atoms.iter_mut().enumerate() {
+            atom.atom_number = Some((atom_start + i) % 100_000);
+        }
     }
 
-    #[test]
-    fn get_atom_and_residue_from_list() {
-        let mut residues = Vec::new();
+    /// Rename every residue of the given name, returning the number of renamed residues.
+    ///
+    /// Since the residue names are shared, all atoms of the residues see the new name.
+    pub fn rename_residue(&mut self, old: &str, new: &str) -> usize {
+        let mut num_renamed = 0;
 
-        let res1_name = "RES1";
-        let atom1_name = "AT1";
+        for residue in &self.residues {
+            let residue = residue.borrow();
 
-        let (res1, atom1) =
-            get_or_insert_atom_and_residue(res1_name, atom1_name, &mut residues).unwrap();
+            if residue.cmp_name(old) {
+                *residue.name.borrow_mut() = new.to_string();
+                num_renamed += 1;
+            }
+        }
 
-        assert_eq!(*res1.borrow().name.borrow(), res1_name);
-        assert_eq!(&*atom1.borrow(), &atom1_name);
-        assert!(Rc::ptr_eq(&atom1, &res1.borrow().atoms[0]));
+        num_renamed
+    }
 
-        let atom2_name = "AT2";
-        let (res1_again, atom2) =
-            get_or_insert_atom_and_residue(res1_name, atom2_name, &mut residues).unwrap();
+    /// Rename an atom of every residue of the given name, returning the number
+    /// of renamed atoms.
+    ///
+    /// Atoms of the same name in other residues are not renamed.
+    pub fn rename_atom(&mut self, residue_name: &str, old_atom: &str, new_atom: &str) -> usize {
+        let mut num_renamed = 0;
 
-        assert!(Rc::ptr_eq(&res1, &res1_again));
-        assert_eq!(&*atom2.borrow(), &atom2_name);
+        for residue in self.residues.iter().map(|residue| residue.borrow()) {
+            if residue.cmp_name(residue_name) {
+                for atom in residue
+                    .atoms
+                    .iter()
+                    .filter(|atom| *atom.borrow() == old_atom)
+                {
+                    *atom.borrow_mut() = new_atom.to_string();
+                    num_renamed += 1;
+                }
+            }
+        }
 
-        let res2_name = "RES2";
-        let atom3_name = "AT3";
+        num_renamed
+    }
 
-        let (res2, atom3) =
-            get_or_insert_atom_and_residue(res2_name, atom3_name, &mut residues).unwrap();
+    /// Set the charge of every atom using the input function.
+    pub fn set_charges(&mut self, charge_of: impl Fn(&Atom) -> f64) {
+        for atom in self.atoms.iter_mut() {
+            atom.charge = Some(charge_of(atom));
+        }
+    }
 
-        assert!(!Rc::ptr_eq(&res1, &res2));
-        assert_eq!(*res2.borrow().name.borrow(), res2_name);
-        assert_eq!(&*atom3.borrow(), &atom3_name);
+    /// Return the total charge of the configuration. Atoms without a charge do not contribute.
+    pub fn total_charge(&self) -> f64 {
+        self.atoms.iter().filter_map(|atom| atom.charge).sum()
+    }
+
+    /// Return the dipole moment of the configuration, in units of charge times nm.
+    ///
+    /// The moment is calculated from the atom positions as they are, ie. relative to
+    /// the origin of the coordinate system. For a configuration which is not neutral
+    /// the result thus depends on where it is placed. Atoms without a charge do not
+    /// contribute.
+    pub fn dipole_moment(&self) -> RVec {
+        self.atoms
+            .iter()
+            .filter_map(|atom| atom.charge.map(|charge| atom.position * charge))
+            .fold(RVec::default(), |acc, moment| acc + moment)
+    }
+
+    /// Combine the atoms of this and another configuration into a new configuration.
+    ///
+    /// The residues of the other configuration are unified with those of this one
+    /// by their names, so configurations read from separate files share residues
+    /// of the same type. The title and box are taken from this configuration.
+    ///
+    /// Atom names of the other configuration which are missing from a residue are
+    /// added to it in the merged configuration, whose residues are copies so that
+    /// this configuration is not modified.
+    pub fn merge_dedup_by_name(&self, other: &Conf) -> Conf {
+        let Conf {
+            mut residues,
+            mut atoms,
+            ..
+        } = self.deep_clone();
+
+        for atom in &other.atoms {
+            let residue_name = atom.residue.borrow().name.borrow().clone();
+            let residue = get_or_insert_residue(&residue_name, &mut residues);
+            let name = residue.borrow_mut().get_or_insert_atom(&atom.name.borrow());
+
+            atoms.push(Atom {
+                name,
+                residue,
+                position: atom.position,
+                velocity: atom.velocity,
+                charge: atom.charge,
+                occupancy: atom.occupancy,
+                b_factor: atom.b_factor,
+                residue_number: None,
+                atom_number: None,
+            });
+        }
+
+        Conf {
+            title: self.title.clone(),
+            origin: self.origin,
+            size: self.size,
+            residues,
+            atoms,
+        }
+    }
+
+    /// Extend the configuration along each direction by copying and translating the atoms.
+    pub fn pbc_multiply(&self, nx: usize, ny: usize, nz: usize) -> Conf {
+        Conf {
+            title: self.title.clone(),
+            origin: self.origin,
+            size: self.size.pbc_multiply(nx, ny, nz),
+            residues: self.residues.clone(),
+            atoms: self.pbc_images(nx, ny, nz).collect(),
+        }
+    }
+
+    /// Extend the configuration with `pbc_multiply` until its box is at least as large
+    /// as the target along each direction.
+    ///
+    /// The box is not cropped to the target, since that would cut through molecules.
+    /// Use `crop` on the result to get the exact size. Directions where the box size
+    /// is zero are not extended.
+    pub fn tile_to_size(&self, target: RVec) -> Conf {
+        let multiplier = |size: f64, target: f64| {
+            if size > 0.0 {
+                (target / size).ceil().max(1.0) as usize
+            } else {
+                1
+            }
+        };
+
+        self.pbc_multiply(
+            multiplier(self.size.x, target.x),
+            multiplier(self.size.y, target.y),
+            multiplier(self.size.z, target.z),
+        )
+    }
+
+    /// Iterate over the atoms of the periodic images which `pbc_multiply` creates,
+    /// in the same order, without collecting them into a configuration.
+    ///
+    /// The images are ordered by their index along z, then y, then x.
+    pub fn pbc_images(&self, nx: usize, ny: usize, nz: usize) -> impl Iterator<Item = Atom> + '_ {
+        let images = (0..nx)
+            .flat_map(move |ix| (0..ny).flat_map(move |iy| (0..nz).map(move |iz| (ix, iy, iz))));
+
+        images.flat_map(move |(ix, iy, iz)| {
+            let dr = self.size.pbc_multiply(ix, iy, iz);
+
+            self.atoms.iter().map(move |atom| Atom {
+                position: atom.position + dr,
+                ..atom.clone()
+            })
+        })
+    }
+
+    /// Return the mean squared displacement of the atoms between this and another
+    /// configuration, whose atoms are in the same order.
+    pub fn msd(&self, other: &Conf) -> Result<f64, String> {
+        self.mean_over_atom_pairs(other, |atom1, atom2| {
+            atom1.position.distance_squared(&atom2.position)
+        })
+    }
+
+    /// Return the mean squared displacement of the atoms between this and another
+    /// configuration, using the minimum image convention in a periodic box of
+    /// the input size.
+    pub fn msd_pbc(&self, other: &Conf, box_size: RVec) -> Result<f64, String> {
+        self.mean_over_atom_pairs(other, |atom1, atom2| {
+            let dr = atom1.position.displacement_pbc(&atom2.position, &box_size);
+            dr.x.powi(2) + dr.y.powi(2) + dr.z.powi(2)
+        })
+    }
+
+    /// Return the root mean square deviation between the atoms of complete residues
+    /// in this and another configuration, whose residues may be in a different order.
+    ///
+    /// Residues are matched by their name and order of appearance, ie. the n'th
+    /// residue of a name is matched to the n'th residue of that name in the other
+    /// configuration. Atoms are matched by their order inside the residues. An error
+    /// is returned if the residues cannot all be matched.
+    pub fn rmsd_by_residue_name(&self, other: &Conf) -> Result<f64, String> {
+        let mut other_residues: HashMap<String, VecDeque<Vec<Atom>>> = HashMap::new();
+
+        for atoms in other.iter_valid_residues() {
+            let name = atoms[0].residue.borrow().name.borrow().clone();
+            other_residues.entry(name).or_default().push_back(atoms);
+        }
+
+        let mut sum = 0.0;
+        let mut num_atoms = 0;
+
+        for atoms in self.iter_valid_residues() {
+            let name = atoms[0].residue.borrow().name.borrow().clone();
+
+            let matched = other_residues
+                .get_mut(&name)
+                .and_then(|residues| residues.pop_front())
+                .ok_or_else(|| format!("no residue '{}' to match in other configuration", name))?;
+
+            if matched.len() != atoms.len() {
+                return Err(format!(
+                    "matched residues '{}' have different numbers of atoms ({} and {})",
+                    name,
+                    atoms.len(),
+                    matched.len()
+                ));
+            }
+
+            for (atom1, atom2) in atoms.iter().zip(matched.iter()) {
+                sum += atom1.position.distance_squared(&atom2.position);
+                num_atoms += 1;
+            }
+        }
+
+        if let Some((name, _)) = other_residues
+            .iter()
+            .find(|(_, residues)| !residues.is_empty())
+        {
+            return Err(format!(
+                "unmatched residue '{}' in other configuration",
+                name
+            ));
+        }
+
+        if num_atoms == 0 {
+            return Ok(0.0);
+        }
+
+        Ok((sum / num_atoms as f64).sqrt())
+    }
+
+    /// Average a function over all corresponding pairs of atoms in two configurations.
+    fn mean_over_atom_pairs(
+        &self,
+        other: &Conf,
+        func: impl Fn(&Atom, &Atom) -> f64,
+    ) -> Result<f64, String> {
+        if self.atoms.len() != other.atoms.len() {
+            return Err(format!(
+                "configurations have different numbers of atoms ({} and {})",
+                self.atoms.len(),
+                other.atoms.len()
+            ));
+        }
+
+        if self.atoms.is_empty() {
+            return Ok(0.0);
+        }
+
+        let sum: f64 = self
+            .atoms
+            .iter()
+            .zip(other.atoms.iter())
+            .map(|(atom1, atom2)| func(atom1, atom2))
+            .sum();
+
+        Ok(sum / self.atoms.len() as f64)
+    }
+
+    /// Return the index of and distance to the atom closest to a point, or `None`
+    /// if the configuration has no atoms.
+    pub fn nearest_atom(&self, point: RVec) -> Option<(usize, f64)> {
+        self.find_nearest_atom(|position| position.distance_squared(&point))
+    }
+
+    /// Return the index of and distance to the atom closest to a point, using
+    /// the minimum image convention in the periodic box of the configuration.
+    pub fn nearest_atom_pbc(&self, point: RVec) -> Option<(usize, f64)> {
+        self.find_nearest_atom(|position| {
+            let dr = position.displacement_pbc(&point, &self.size);
+            dr.x.powi(2) + dr.y.powi(2) + dr.z.powi(2)
+        })
+    }
+
+    /// Find the atom with the smallest squared distance from the input function.
+    fn find_nearest_atom(&self, distance_squared: impl Fn(&RVec) -> f64) -> Option<(usize, f64)> {
+        self.atoms
+            .iter()
+            .map(|atom| distance_squared(&atom.position))
+            .enumerate()
+            .fold(None, |nearest, (i, dr2)| match nearest {
+                Some((_, min_dr2)) if min_dr2 <= dr2 => nearest,
+                _ => Some((i, dr2)),
+            })
+            .map(|(i, dr2)| (i, dr2.sqrt()))
+    }
+
+    /// Return the box as a matrix whose rows are the box vectors. The box of
+    /// a configuration is rectangular, so the matrix is diagonal.
+    pub fn box_matrix(&self) -> [[f64; 3]; 3] {
+        [
+            [self.size.x, 0.0, 0.0],
+            [0.0, self.size.y, 0.0],
+            [0.0, 0.0, self.size.z],
+        ]
+    }
+
+    /// Return the volume of the box, computed from its matrix with `box_volume`.
+    pub fn volume(&self) -> f64 {
+        box_volume(&self.box_matrix())
+    }
+
+    /// Return whether the box is orthorhombic, checked on its matrix with
+    /// `box_is_orthorhombic`.
+    pub fn box_is_orthorhombic(&self, tolerance: f64) -> bool {
+        box_is_orthorhombic(&self.box_matrix(), tolerance)
+    }
+
+    /// Return the ratios `(y / x, z / x)` of the box size. The ratios are not finite
+    /// if the box has no size along x.
+    pub fn aspect_ratio(&self) -> (f64, f64) {
+        (self.size.y / self.size.x, self.size.z / self.size.x)
+    }
+
+    /// Return the minimum and maximum corners of the box which bounds all atom
+    /// positions, or `None` if the configuration has no atoms.
+    pub fn bounding_box(&self) -> Option<(RVec, RVec)> {
+        let mut positions = self.atoms.iter().map(|atom| atom.position);
+        let first = positions.next()?;
+
+        Some(positions.fold((first, first), |(min, max), position| {
+            (
+                RVec {
+                    x: min.x.min(position.x),
+                    y: min.y.min(position.y),
+                    z: min.z.min(position.z),
+                },
+                RVec {
+                    x: max.x.max(position.x),
+                    y: max.y.max(position.y),
+                    z: max.z.max(position.z),
+                },
+            )
+        }))
+    }
+
+    /// Count the atoms in slabs of equal width along a direction of the box.
+    ///
+    /// Atoms outside of the box along the direction are counted in the closest
+    /// edge slab. If the box has no size along the direction, all atoms are
+    /// counted in the first slab.
+    pub fn density_profile(&self, dir: Direction, bins: usize) -> Vec<usize> {
+        let mut profile = vec![0; bins];
+
+        if bins == 0 {
+            return profile;
+        }
+
+        for atom in &self.atoms {
+            profile[self.get_bin(atom.position, dir, bins)] += 1;
+        }
+
+        profile
+    }
+
+    /// Return the indices of the atoms in every cell of a grid which divides the box
+    /// into `nx * ny * nz` cells of equal size. Atoms outside of the box are put in
+    /// the closest edge cell. Cells without atoms are not included.
+    ///
+    /// A number of cells of 0 along a direction is treated as 1.
+    pub fn bucket_by_cell(
+        &self,
+        nx: usize,
+        ny: usize,
+        nz: usize,
+    ) -> HashMap<(usize, usize, usize), Vec<usize>> {
+        let (nx, ny, nz) = (nx.max(1), ny.max(1), nz.max(1));
+        let mut cells: HashMap<_, Vec<usize>> = HashMap::new();
+
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let cell = (
+                self.get_bin(atom.position, Direction::X, nx),
+                self.get_bin(atom.position, Direction::Y, ny),
+                self.get_bin(atom.position, Direction::Z, nz),
+            );
+
+            cells.entry(cell).or_default().push(i);
+        }
+
+        cells
+    }
+
+    /// Return the number of atoms in every cell of a grid which divides the box into
+    /// `nx * ny * nz` cells of equal size, as a flattened grid where the count of
+    /// cell `(ix, iy, iz)` is at index `(iz * ny + iy) * nx + ix`.
+    ///
+    /// Atoms outside of the box are counted in the closest edge cell. The counts can
+    /// be divided by the cell volume to get the number density. The grid is empty if
+    /// any number of cells is 0.
+    pub fn density_grid(&self, nx: usize, ny: usize, nz: usize) -> Vec<f64> {
+        let mut grid = vec![0.0; nx * ny * nz];
+
+        if grid.is_empty() {
+            return grid;
+        }
+
+        for atom in &self.atoms {
+            let ix = self.get_bin(atom.position, Direction::X, nx);
+            let iy = self.get_bin(atom.position, Direction::Y, ny);
+            let iz = self.get_bin(atom.position, Direction::Z, nz);
+
+            grid[(iz * ny + iy) * nx + ix] += 1.0;
+        }
+
+        grid
+    }
+
+    /// Return the bin of a position along a direction, when the box is divided into
+    /// a number of bins of equal size. Positions outside of the box are put in the
+    /// closest edge bin. The number of bins must be positive.
+    fn get_bin(&self, position: RVec, dir: Direction, bins: usize) -> usize {
+        let x0 = self.origin.get(dir);
+        let dx = self.size.get(dir);
+
+        if dx > 0.0 {
+            let x = (position.get(dir) - x0) / dx;
+            ((x * bins as f64).floor().max(0.0) as usize).min(bins - 1)
+        } else {
+            0
+        }
+    }
+
+    /// Return the indices of all atoms which lie outside of the box.
+    ///
+    /// The box spans from the origin up to, but not including, the origin plus
+    /// its size. Dimensions of zero size are not checked.
+    pub fn atoms_outside_box(&self) -> Vec<usize> {
+        let outside = |x: f64, x0: f64, dx: f64| dx > 0.0 && (x < x0 || x >= x0 + dx);
+
+        self.atoms
+            .iter()
+            .enumerate()
+            .filter(|(_, atom)| {
+                let r = atom.position;
+
+                outside(r.x, self.origin.x, self.size.x)
+                    || outside(r.y, self.origin.y, self.size.y)
+                    || outside(r.z, self.origin.z, self.size.z)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Return all pairs of atoms closer to each other than a cutoff, along with
+    /// their distance.
+    ///
+    /// The pairs are given as indices `(i, j)` with `i < j`, sorted by the indices.
+    /// Distances are not periodic. A cell list is used to search for the pairs,
+    /// so this scales to large configurations.
+    pub fn bonds_within(&self, cutoff: f64) -> Vec<(usize, usize, f64)> {
+        let positions = self
+            .atoms
+            .iter()
+            .map(|atom| atom.position)
+            .collect::<Vec<_>>();
+
+        CellList::new(&positions, cutoff).pairs_within(&positions, cutoff)
+    }
+
+    /// Estimate the volume of the box which is farther than a probe radius from
+    /// every atom, using the minimum image convention.
+    ///
+    /// The box is divided into cubic grid cells of the input spacing, and the volume
+    /// of the cells whose centers are free is summed. Parts of the box which do not
+    /// fit a whole grid cell are not sampled. Zero is returned if the spacing or any
+    /// box size is not positive.
+    pub fn grid_free_volume(&self, probe_radius: f64, spacing: f64) -> f64 {
+        let has_volume = self.size.x > 0.0 && self.size.y > 0.0 && self.size.z > 0.0;
+
+        if !has_volume || spacing <= 0.0 {
+            return 0.0;
+        }
+
+        let positions = self
+            .atoms
+            .iter()
+            .map(|atom| atom.position)
+            .collect::<Vec<_>>();
+
+        let cell_list = CellList::new_periodic(&positions, self.size, probe_radius);
+
+        let num_points = |length: f64| (length / spacing) as usize;
+        let (nx, ny, nz) = (
+            num_points(self.size.x),
+            num_points(self.size.y),
+            num_points(self.size.z),
+        );
+
+        let coordinate = |i: usize| (i as f64 + 0.5) * spacing;
+
+        let mut num_free = 0;
+
+        for ix in 0..nx {
+            for iy in 0..ny {
+                for iz in 0..nz {
+                    let point = RVec {
+                        x: coordinate(ix),
+                        y: coordinate(iy),
+                        z: coordinate(iz),
+                    };
+
+                    if !cell_list.any_within(&positions, point, probe_radius) {
+                        num_free += 1;
+                    }
+                }
+            }
+        }
+
+        num_free as f64 * spacing.powi(3)
+    }
+
+    /// Return the radial distribution function g(r) of all atoms, using the minimum
+    /// image convention in the periodic box of the configuration.
+    ///
+    /// Bin `i` covers the distances from `i * bin_width` to `(i + 1) * bin_width`,
+    /// up to `max_r`. Every bin is normalized by the volume of its shell and the
+    /// number density of the configuration. Distances beyond half of the box size
+    /// are not fully sampled with the minimum image convention, so `max_r` should
+    /// not be larger than that. An empty distribution is returned if the bin width
+    /// is not positive and all bins are zero if the box has no volume.
+    pub fn rdf(&self, bin_width: f64, max_r: f64) -> Vec<f64> {
+        if bin_width <= 0.0 {
+            return Vec::new();
+        }
+
+        let num_bins = (max_r / bin_width).max(0.0) as usize;
+        let mut rdf = vec![0.0; num_bins];
+
+        let has_volume = self.size.x > 0.0 && self.size.y > 0.0 && self.size.z > 0.0;
+
+        if !has_volume || self.atoms.is_empty() {
+            return rdf;
+        }
+
+        let positions = self
+            .atoms
+            .iter()
+            .map(|atom| atom.position)
+            .collect::<Vec<_>>();
+
+        let cutoff = num_bins as f64 * bin_width;
+        let pairs =
+            CellList::new_periodic(&positions, self.size, cutoff).pairs_within(&positions, cutoff);
+
+        // Every pair is counted for both of its atoms
+        for (_, _, distance) in pairs {
+            let bin = (distance / bin_width) as usize;
+
+            if bin < num_bins {
+                rdf[bin] += 2.0;
+            }
+        }
+
+        let num_atoms = self.atoms.len() as f64;
+        let density = num_atoms / (self.size.x * self.size.y * self.size.z);
+
+        for (i, value) in rdf.iter_mut().enumerate() {
+            let r0 = i as f64 * bin_width;
+            let r1 = r0 + bin_width;
+            let shell_volume = 4.0 / 3.0 * PI * (r1.powi(3) - r0.powi(3));
+
+            *value /= num_atoms * density * shell_volume;
+        }
+
+        rdf
+    }
+
+    /// Count the pairs of atoms within a cutoff of each other, where one atom belongs
+    /// to a residue named `group_a` and the other to one named `group_b`.
+    ///
+    /// Atoms of incomplete residues are not counted. Distances are not periodic.
+    /// If the group names are equal, every pair of atoms in the group is counted once.
+    pub fn contacts_between(&self, group_a: &str, group_b: &str, cutoff: f64) -> usize {
+        let mut positions = Vec::new();
+        let mut in_group = Vec::new();
+
+        for residue in self.iter_valid_residues() {
+            let (is_a, is_b) = {
+                let residue = residue[0].residue.borrow();
+                (residue.cmp_name(group_a), residue.cmp_name(group_b))
+            };
+
+            if is_a || is_b {
+                for atom in residue {
+                    positions.push(atom.position);
+                    in_group.push((is_a, is_b));
+                }
+            }
+        }
+
+        CellList::new(&positions, cutoff)
+            .pairs_within(&positions, cutoff)
+            .iter()
+            .filter(|&&(i, j, _)| {
+                let ((a1, b1), (a2, b2)) = (in_group[i], in_group[j]);
+                (a1 && b2) || (b1 && a2)
+            })
+            .count()
+    }
+
+    /// Count the solvent residues with at least one atom within a shell distance of
+    /// an atom in any solute residue, using the minimum image convention in the box.
+    ///
+    /// Solute residues are those whose name is in the input set. Only complete
+    /// residues are considered. If the box has no volume the distances are not periodic.
+    pub fn solvation_count(
+        &self,
+        solute_names: &HashSet<String>,
+        solvent_name: &str,
+        shell: f64,
+    ) -> usize {
+        let mut positions = Vec::new();
+        // The index of the solvent residue of every atom, or `None` for solute atoms
+        let mut solvent_residue = Vec::new();
+
+        for (i, residue) in self.iter_valid_residues().enumerate() {
+            let (is_solute, is_solvent) = {
+                let residue = residue[0].residue.borrow();
+                let name = residue.name.borrow();
+
+                (solute_names.contains(&*name), *name == solvent_name)
+            };
+
+            if is_solute || is_solvent {
+                for atom in residue {
+                    positions.push(atom.position);
+                    solvent_residue.push(if is_solute { None } else { Some(i) });
+                }
+            }
+        }
+
+        let has_volume = self.size.x > 0.0 && self.size.y > 0.0 && self.size.z > 0.0;
+
+        let cell_list = if has_volume {
+            CellList::new_periodic(&positions, self.size, shell)
+        } else {
+            CellList::new(&positions, shell)
+        };
+
+        cell_list
+            .pairs_within(&positions, shell)
+            .iter()
+            .filter_map(
+                |&(i, j, _)| match (solvent_residue[i], solvent_residue[j]) {
+                    (None, Some(residue)) | (Some(residue), None) => Some(residue),
+                    _ => None,
+                },
+            )
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Count the hydrogen bonds by a geometric criterion. A bond is counted for every
+    /// donor, hydrogen and acceptor triple in which the donor and acceptor are within
+    /// the distance cutoff and the donor-hydrogen-acceptor angle is larger than
+    /// the angle cutoff (in radians).
+    ///
+    /// Donors, acceptors and hydrogens are identified by their atom names. Every hydrogen
+    /// is bonded to the closest donor of its residue, and donors and acceptors must be
+    /// in different residues. Atoms of incomplete residues are not counted and distances
+    /// are not periodic.
+    pub fn count_hbonds(
+        &self,
+        donor_names: &HashSet<String>,
+        acceptor_names: &HashSet<String>,
+        hydrogen_names: &HashSet<String>,
+        dist_cutoff: f64,
+        angle_cutoff: f64,
+    ) -> usize {
+        let mut positions = Vec::new();
+        // The residue index and whether the atom is an acceptor of every donor
+        // or acceptor atom, along with the hydrogens bonded to it if it is a donor
+        let mut residue_index = Vec::new();
+        let mut is_acceptor = Vec::new();
+        let mut hydrogens: Vec<Vec<RVec>> = Vec::new();
+
+        for (i, residue) in self.iter_valid_residues().enumerate() {
+            let mut donors = Vec::new();
+            let mut residue_hydrogens = Vec::new();
+
+            for atom in residue {
+                let (donor, acceptor, hydrogen) = {
+                    let name = atom.name.borrow();
+
+                    (
+                        donor_names.contains(&*name),
+                        acceptor_names.contains(&*name),
+                        hydrogen_names.contains(&*name),
+                    )
+                };
+
+                if donor || acceptor {
+                    if donor {
+                        donors.push(positions.len());
+                    }
+
+                    positions.push(atom.position);
+                    residue_index.push(i);
+                    is_acceptor.push(acceptor);
+                    hydrogens.push(Vec::new());
+                } else if hydrogen {
+                    residue_hydrogens.push(atom.position);
+                }
+            }
+
+            for hydrogen in residue_hydrogens {
+                let closest_donor = donors.iter().cloned().min_by(|&j, &k| {
+                    let dist_j = positions[j].distance_squared(&hydrogen);
+                    let dist_k = positions[k].distance_squared(&hydrogen);
+                    dist_j.total_cmp(&dist_k)
+                });
+
+                if let Some(j) = closest_donor {
+                    hydrogens[j].push(hydrogen);
+                }
+            }
+        }
+
+        let count_bonds = |donor: usize, acceptor: usize| -> usize {
+            if !is_acceptor[acceptor] {
+                return 0;
+            }
+
+            let (donor_position, acceptor_position) = (positions[donor], positions[acceptor]);
+
+            hydrogens[donor]
+                .iter()
+                .filter(|&&hydrogen| {
+                    let to_donor = donor_position - hydrogen;
+                    let to_acceptor = acceptor_position - hydrogen;
+                    to_donor.angle(&to_acceptor) > angle_cutoff
+                })
+                .count()
+        };
+
+        CellList::new(&positions, dist_cutoff)
+            .pairs_within(&positions, dist_cutoff)
+            .iter()
+            .filter(|&&(i, j, _)| residue_index[i] != residue_index[j])
+            .map(|&(i, j, _)| count_bonds(i, j) + count_bonds(j, i))
+            .sum()
+    }
+
+    /// Return the volume of the convex hull of the atom positions.
+    ///
+    /// The volume is zero if there are fewer than four atoms which are not coplanar.
+    pub fn convex_hull_volume(&self) -> f64 {
+        let positions = self
+            .atoms
+            .iter()
+            .map(|atom| atom.position)
+            .collect::<Vec<_>>();
+
+        hull::convex_hull_volume(&positions)
+    }
+
+    /// Translate all atoms so that the minimum corner of their bounding box is
+    /// at the origin. The box size is not changed.
+    pub fn recenter_to_origin(&mut self) {
+        if let Some((min, _)) = self.bounding_box() {
+            self.atoms.iter_mut().for_each(|atom| atom.position -= min);
+        }
+    }
+
+    /// Set the box to the bounding box of the atoms, extended by a padding on all sides.
+    ///
+    /// The origin is set to the minimum corner of the bounding box minus the padding
+    /// and the size to the extent of the atoms plus twice the padding. The atoms are
+    /// not moved. If the configuration has no atoms the box is not changed.
+    pub fn fit_box(&mut self, padding: RVec) {
+        if let Some((min, max)) = self.bounding_box() {
+            self.origin = min - padding;
+            self.size = max - min + padding * 2.0;
+        }
+    }
+
+    /// Return a configuration with the atoms for which the predicate is true.
+    ///
+    /// Only the residues which are referenced by the kept atoms are kept. The title
+    /// and box are not changed.
+    pub fn select_atoms(&self, predicate: impl Fn(&Atom) -> bool) -> Conf {
+        let atoms = self
+            .atoms
+            .iter()
+            .filter(|atom| predicate(atom))
+            .cloned()
+            .collect();
+
+        self.with_atoms(atoms)
+    }
+
+    /// Return a configuration with the atoms inside a region, given by its lower
+    /// and upper corners. A position is inside the region if it is at or above
+    /// the lower corner and below the upper corner along every direction.
+    ///
+    /// The atoms are shifted so that the lower corner becomes the origin and the
+    /// box size is set to the size of the region. Since atoms of a residue may
+    /// be cropped while others are kept, `iter_residues` may yield errors for
+    /// the new configuration.
+    pub fn crop(&self, lower: RVec, upper: RVec) -> Conf {
+        let inside = |x: f64, x0: f64, x1: f64| x >= x0 && x < x1;
+
+        let mut conf = self.select_atoms(|atom| {
+            let r = atom.position;
+
+            inside(r.x, lower.x, upper.x)
+                && inside(r.y, lower.y, upper.y)
+                && inside(r.z, lower.z, upper.z)
+        });
+
+        conf.atoms
+            .iter_mut()
+            .for_each(|atom| atom.position -= lower);
+        conf.origin = RVec::default();
+        conf.size = upper - lower;
+
+        conf
+    }
+
+    /// Return a configuration with every `step`th atom of this one, starting with
+    /// the first. A `step` of 0 is treated as 1.
+    ///
+    /// Only the residues which are referenced by the kept atoms are kept. Since
+    /// atoms of the same residue may be dropped, `iter_residues` will usually
+    /// yield errors for the new configuration.
+    pub fn stride_atoms(&self, step: usize) -> Conf {
+        let atoms = self.atoms.iter().step_by(step.max(1)).cloned().collect();
+
+        self.with_atoms(atoms)
+    }
+
+    /// Remove the atoms at the input indices and the residues which are no longer
+    /// referenced by any atom. Indices outside of the configuration are ignored.
+    ///
+    /// Since atoms of a residue may be removed while others are kept, `iter_residues`
+    /// may yield errors for the modified configuration.
+    pub fn remove_atoms(&mut self, indices: &HashSet<usize>) {
+        let atoms = self
+            .atoms
+            .drain(..)
+            .enumerate()
+            .filter(|(i, _)| !indices.contains(i))
+            .map(|(_, atom)| atom)
+            .collect::<Vec<_>>();
+
+        self.atoms = atoms;
+
+        let atoms = &self.atoms;
+        self.residues
+            .retain(|residue| atoms.iter().any(|atom| Rc::ptr_eq(&atom.residue, residue)));
+    }
+
+    /// Split the configuration into one with the residues whose names are in the
+    /// input set and one with the rest, as `(matching, rest)`.
+    ///
+    /// The configurations are constructed from `iter_residues`, so atoms of bad
+    /// residues are not included in either.
+    pub fn partition_by_names(&self, names: &HashSet<String>) -> (Conf, Conf) {
+        let (matching, rest): (Vec<_>, Vec<_>) = self
+            .iter_valid_residues()
+            .partition(|atoms| names.contains(&*atoms[0].residue.borrow().name.borrow()));
+
+        (
+            self.with_atoms(matching.into_iter().flatten().collect()),
+            self.with_atoms(rest.into_iter().flatten().collect()),
+        )
+    }
+
+    /// Return a copy of the configuration which shares no residues or names with this one.
+    ///
+    /// The default `clone` copies the reference counted residues and names by reference,
+    /// so renaming a residue in the clone also renames it in the original. Here every
+    /// residue and name is copied and the atoms of the copy reference the new objects,
+    /// with the same grouping as in this configuration.
+    pub fn deep_clone(&self) -> Conf {
+        let copy_residue = |residue: &Rc<RefCell<Residue>>| {
+            let residue = residue.borrow();
+            let name = residue.name.borrow().clone();
+            let atoms = residue
+                .atoms
+                .iter()
+                .map(|name| Rc::new(RefCell::new(name.borrow().clone())))
+                .collect();
+
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new(name)),
+                atoms,
+            }))
+        };
+
+        let residues = self.residues.iter().map(copy_residue).collect::<Vec<_>>();
+
+        // Atoms may reference residues which are not in the list, these are copied as well
+        let mut old_residues = self.residues.clone();
+        let mut new_residues = residues.clone();
+
+        // Look up the index of residues by their address instead of comparing every pair
+        let mut residue_indices: HashMap<*const RefCell<Residue>, usize> = HashMap::new();
+
+        for (i, residue) in old_residues.iter().enumerate() {
+            residue_indices.entry(Rc::as_ptr(residue)).or_insert(i);
+        }
+
+        let atoms = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                let index = *residue_indices
+                    .entry(Rc::as_ptr(&atom.residue))
+                    .or_insert_with(|| {
+                        old_residues.push(Rc::clone(&atom.residue));
+                        new_residues.push(copy_residue(&atom.residue));
+                        old_residues.len() - 1
+                    });
+
+                let residue = Rc::clone(&new_residues[index]);
+
+                let name_index = old_residues[index]
+                    .borrow()
+                    .atoms
+                    .iter()
+                    .position(|name| Rc::ptr_eq(name, &atom.name));
+
+                let name = match name_index {
+                    Some(i) => Rc::clone(&residue.borrow().atoms[i]),
+                    None => Rc::new(RefCell::new(atom.name.borrow().clone())),
+                };
+
+                Atom {
+                    name,
+                    residue,
+                    ..atom.clone()
+                }
+            })
+            .collect();
+
+        Conf {
+            title: self.title.clone(),
+            origin: self.origin,
+            size: self.size,
+            residues,
+            atoms,
+        }
+    }
+
+    /// Construct a configuration with the input atoms, keeping the title and box
+    /// of this configuration and only the residues which the atoms reference.
+    fn with_atoms(&self, atoms: Vec<Atom>) -> Conf {
+        let residues = self
+            .residues
+            .iter()
+            .filter(|residue| atoms.iter().any(|atom| Rc::ptr_eq(&atom.residue, residue)))
+            .cloned()
+            .collect();
+
+        Conf {
+            title: self.title.clone(),
+            origin: self.origin,
+            size: self.size,
+            residues,
+            atoms,
+        }
+    }
+
+    /// Insert the atoms of a molecule with its center of geometry at a position,
+    /// if none of them are closer than a cutoff to the atoms of this configuration.
+    ///
+    /// Distances are measured using the minimum image convention in the box of
+    /// this configuration. The residues of the molecule are added to this
+    /// configuration if they are not already in it. If the molecule does not fit
+    /// an error is returned and the configuration is not modified.
+    #[allow(clippy::result_unit_err)]
+    pub fn try_insert(&mut self, molecule: &Conf, cutoff: f64, position: RVec) -> Result<(), ()> {
+        if molecule.atoms.is_empty() {
+            return Ok(());
+        }
+
+        let center = molecule
+            .atoms
+            .iter()
+            .fold(RVec::default(), |acc, atom| acc + atom.position)
+            / molecule.atoms.len() as f64;
+        let shift = position - center;
+
+        let positions = molecule
+            .atoms
+            .iter()
+            .map(|atom| atom.position + shift)
+            .collect::<Vec<_>>();
+
+        let overlaps = positions.iter().any(|position| {
+            self.atoms
+                .iter()
+                .any(|atom| atom.position.distance_pbc(position, &self.size) < cutoff)
+        });
+
+        if overlaps {
+            return Err(());
+        }
+
+        for residue in &molecule.residues {
+            if !self.residues.iter().any(|other| Rc::ptr_eq(other, residue)) {
+                self.residues.push(Rc::clone(residue));
+            }
+        }
+
+        for (atom, position) in molecule.atoms.iter().zip(positions) {
+            self.atoms.push(Atom {
+                position,
+                ..atom.clone()
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Create a configuration from atoms, collecting the residues which they reference.
+    ///
+    /// The residues are added in the order in which they are first referenced by
+    /// the atoms. Atoms with residues which are equal by value but not the same object
+    /// reference separate residues. The origin of the box is set to zero.
+    pub fn from_atoms(title: String, size: RVec, atoms: impl IntoIterator<Item = Atom>) -> Conf {
+        let atoms = atoms.into_iter().collect::<Vec<_>>();
+        let mut residues: Vec<Rc<RefCell<Residue>>> = Vec::new();
+
+        for atom in atoms.iter() {
+            if !residues
+                .iter()
+                .any(|residue| Rc::ptr_eq(residue, &atom.residue))
+            {
+                residues.push(Rc::clone(&atom.residue));
+            }
+        }
+
+        Conf {
+            title,
+            origin: RVec::default(),
+            size,
+            residues,
+            atoms,
+        }
+    }
+
+    /// Create a configuration by placing copies of a template on a regular grid.
+    ///
+    /// The copies are translated by `spacing` along each direction and share
+    /// the residues of the template. The box size is set to cover the full grid.
+    pub fn from_template_lattice(
+        template: &Conf,
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        spacing: RVec,
+    ) -> Conf {
+        let mut conf = Conf {
+            title: template.title.clone(),
+            origin: template.origin,
+            size: spacing.pbc_multiply(nx, ny, nz),
+            residues: template.residues.clone(),
+            atoms: Vec::new(),
+        };
+
+        for ix in 0..nx {
+            for iy in 0..ny {
+                for iz in 0..nz {
+                    let dr = spacing.pbc_multiply(ix, iy, iz);
+
+                    conf.atoms.extend(template.atoms.iter().map(|atom| Atom {
+                        name: Rc::clone(&atom.name),
+                        residue: Rc::clone(&atom.residue),
+                        position: atom.position + dr,
+                        velocity: atom.velocity,
+                        charge: atom.charge,
+                        occupancy: atom.occupancy,
+                        b_factor: atom.b_factor,
+                        residue_number: None,
+                        atom_number: None,
+                    }));
+                }
+            }
+        }
+
+        conf
+    }
+
+    /// Return the spacing of the lattice on which the atoms are placed, if they are.
+    ///
+    /// The spacing along a direction is the smallest shift which maps every atom
+    /// onto an atom of the same name, within the tolerance, except for atoms which
+    /// are shifted past the last layer. This detects lattices of multi-atom units,
+    /// such as those created by `from_template_lattice`. The spacing is zero along
+    /// directions with a single layer. If the atoms do not form a lattice along any
+    /// direction, or the tolerance is not positive, `None` is returned.
+    pub fn detect_lattice_spacing(&self, tolerance: f64) -> Option<RVec> {
+        let (_, max) = self.bounding_box()?;
+
+        if tolerance <= 0.0 {
+            return None;
+        }
+
+        // Bin the atoms to quickly find them by position
+        let mut bins: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+
+        for (i, atom) in self.atoms.iter().enumerate() {
+            bins.entry(atom.position.quantized(tolerance))
+                .or_default()
+                .push(i);
+        }
+
+        let has_atom_at = |position: RVec, name: &str| {
+            let (x, y, z) = position.quantized(tolerance);
+
+            (-1..=1).any(|dx| {
+                (-1..=1).any(|dy| {
+                    (-1..=1).any(|dz| {
+                        bins.get(&(x + dx, y + dy, z + dz)).is_some_and(|indices| {
+                            indices.iter().any(|&i| {
+                                let atom = &self.atoms[i];
+                                atom.position.distance(&position) <= tolerance
+                                    && atom.cmp_name(name)
+                            })
+                        })
+                    })
+                })
+            })
+        };
+
+        let shift_along = |dir: Direction, d: f64| match dir {
+            Direction::X => RVec {
+                x: d,
+                ..RVec::default()
+            },
+            Direction::Y => RVec {
+                y: d,
+                ..RVec::default()
+            },
+            Direction::Z => RVec {
+                z: d,
+                ..RVec::default()
+            },
+        };
+
+        // Candidate spacings are the distances from the first atom to those in line with it
+        let first = self.atoms[0].position;
+
+        let spacing_along = |dir: Direction| {
+            let mut candidates = self
+                .atoms
+                .iter()
+                .map(|atom| atom.position - first)
+                .filter(|dr| dr.get(dir) > tolerance)
+                .filter(|dr| (*dr - shift_along(dir, dr.get(dir))).norm() <= tolerance)
+                .map(|dr| dr.get(dir))
+                .collect::<Vec<_>>();
+
+            candidates.sort_by(|a, b| a.total_cmp(b));
+
+            candidates
+                .into_iter()
+                .find(|&d| {
+                    self.atoms.iter().all(|atom| {
+                        let position = atom.position + shift_along(dir, d);
+
+                        position.get(dir) > max.get(dir) + tolerance
+                            || has_atom_at(position, &atom.name.borrow())
+                    })
+                })
+                .unwrap_or(0.0)
+        };
+
+        let spacing = RVec {
+            x: spacing_along(Direction::X),
+            y: spacing_along(Direction::Y),
+            z: spacing_along(Direction::Z),
+        };
+
+        if spacing == RVec::default() {
+            None
+        } else {
+            Some(spacing)
+        }
+    }
+
+    /// Write the configuration to a GROMOS87 formatted file.
+    ///
+    /// The configuration is formatted in memory before the file is created, so a
+    /// configuration which cannot be written never overwrites an existing file.
+    ///
+    /// GROMOS87 files are written with a check of the box size, so a configuration
+    /// without a box, as read from an XYZ file or a PDB file without a `CRYST1` record,
+    /// must first have one set, for example with `fit_box`.
+    pub fn write_gromos87(&self, path: &Path) -> Result<(), WriteError> {
+        let mut buf = Vec::new();
+        gromos87::write_gromos87_conf(self, &mut buf).map_err(|err| WriteError::Gromos87(err))?;
+
+        let mut file = File::create(path)?;
+        file.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Write frames to numbered GROMOS87 files `prefix_00001.gro`, `prefix_00002.gro`
+    /// and so on in a directory, which must exist. The paths of the written files
+    /// are returned in order.
+    ///
+    /// Writing stops at the first frame which could not be written.
+    pub fn write_gromos87_series(
+        frames: &[Conf],
+        dir: &Path,
+        prefix: &str,
+    ) -> Result<Vec<PathBuf>, WriteError> {
+        let mut paths = Vec::with_capacity(frames.len());
+
+        for (i, frame) in frames.iter().enumerate() {
+            let path = dir.join(format!("{}_{:05}.gro", prefix, i + 1));
+            frame.write_gromos87(&path)?;
+
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Write the configuration in the GROMOS87 format to a string.
+    pub fn to_gromos87_string(&self) -> Result<String, WriteError> {
+        let mut buf = Vec::new();
+        gromos87::write_gromos87_conf(self, &mut buf).map_err(WriteError::Gromos87)?;
+
+        String::from_utf8(buf).map_err(WriteError::Utf8Error)
+    }
+
+    /// Return the total momentum of the configuration, using the input function
+    /// to get the mass of each atom. Atoms without a velocity do not contribute.
+    pub fn total_momentum(&self, mass_of: impl Fn(&Atom) -> f64) -> RVec {
+        self.atoms
+            .iter()
+            .filter_map(|atom| atom.velocity.map(|velocity| velocity * mass_of(atom)))
+            .fold(RVec::default(), |acc, momentum| acc + momentum)
+    }
+
+    /// Return an iterator over mutable references to the atom positions.
+    pub fn positions_mut(&mut self) -> impl Iterator<Item = &mut RVec> + '_ {
+        self.atoms.iter_mut().map(|atom| &mut atom.position)
+    }
+
+    /// Return an iterator over mutable references to the atom velocities.
+    pub fn velocities_mut(&mut self) -> impl Iterator<Item = &mut Option<RVec>> + '_ {
+        self.atoms.iter_mut().map(|atom| &mut atom.velocity)
+    }
+
+    /// Return the atom positions as a flat array of `[x0, y0, z0, x1, y1, z1, ...]`,
+    /// for passing them to other languages.
+    pub fn positions_flat(&self) -> Vec<f64> {
+        let mut positions = Vec::with_capacity(3 * self.atoms.len());
+
+        for atom in &self.atoms {
+            positions.extend_from_slice(&[atom.position.x, atom.position.y, atom.position.z]);
+        }
+
+        positions
+    }
+
+    /// Return the atom velocities as a flat array of `[x0, y0, z0, x1, y1, z1, ...]`,
+    /// for passing them to other languages. Atoms without a velocity have zero velocity.
+    pub fn velocities_flat(&self) -> Vec<f64> {
+        let mut velocities = Vec::with_capacity(3 * self.atoms.len());
+
+        for atom in &self.atoms {
+            let velocity = atom.velocity.unwrap_or_default();
+            velocities.extend_from_slice(&[velocity.x, velocity.y, velocity.z]);
+        }
+
+        velocities
+    }
+
+    /// Set the atom positions from a flat array of `[x0, y0, z0, x1, y1, z1, ...]`.
+    ///
+    /// The array must contain three values for every atom, otherwise an error is
+    /// returned and the positions are not modified.
+    pub fn set_positions_flat(&mut self, positions: &[f64]) -> Result<(), String> {
+        if positions.len() != 3 * self.atoms.len() {
+            return Err(format!(
+                "expected {} values for the positions of {} atoms, got {}",
+                3 * self.atoms.len(),
+                self.atoms.len(),
+                positions.len()
+            ));
+        }
+
+        for (atom, position) in self.atoms.iter_mut().zip(positions.chunks(3)) {
+            atom.position = RVec {
+                x: position[0],
+                y: position[1],
+                z: position[2],
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Multiply the velocity of every atom by a factor, for example to convert it
+    /// between units. Atoms without a velocity are not modified.
+    pub fn scale_velocities(&mut self, factor: f64) {
+        for atom in self.atoms.iter_mut() {
+            atom.velocity = atom.velocity.map(|velocity| velocity * factor);
+        }
+    }
+
+    /// Subtract the velocity of a moving reference frame from the velocity of every atom,
+    /// giving the velocities in that frame. Atoms without a velocity are not modified.
+    pub fn boost_velocities(&mut self, frame_velocity: RVec) {
+        for atom in self.atoms.iter_mut() {
+            atom.velocity = atom.velocity.map(|velocity| velocity - frame_velocity);
+        }
+    }
+
+    /// Set the velocity of atoms without one to the input velocity, if any atom
+    /// of the configuration has a velocity. Otherwise no velocities are set.
+    ///
+    /// This ensures that either all or no atoms have velocities.
+    pub fn normalize_velocities(&mut self, fill: RVec) {
+        if self.atoms.iter().any(|atom| atom.velocity.is_some()) {
+            for atom in self.atoms.iter_mut() {
+                atom.velocity = atom.velocity.or(Some(fill));
+            }
+        }
+    }
+
+    /// Return the instantaneous temperature of the configuration from the kinetic
+    /// energy of its atoms, using the input function to get the mass of each atom
+    /// and the input Boltzmann constant. Atoms without a velocity are not counted.
+    pub fn temperature(&self, mass_of: impl Fn(&Atom) -> f64, kb: f64) -> f64 {
+        let (kinetic_energy, num_atoms) = self
+            .atoms
+            .iter()
+            .filter_map(|atom| atom.velocity.map(|v| (atom, v)))
+            .fold((0.0, 0), |(energy, num), (atom, v)| {
+                let v2 = v.x.powi(2) + v.y.powi(2) + v.z.powi(2);
+                (energy + 0.5 * mass_of(atom) * v2, num + 1)
+            });
+
+        if num_atoms == 0 {
+            return 0.0;
+        }
+
+        2.0 * kinetic_energy / (3.0 * num_atoms as f64 * kb)
+    }
+
+    /// Return the center of mass of the configuration, using the input function
+    /// to get the mass of each atom.
+    ///
+    /// The components are NaN if the total mass is zero, which includes configurations
+    /// without atoms. Methods which use the center of mass, like `inertia_tensor`
+    /// and `principal_axes`, then also give NaN values.
+    pub fn center_of_mass(&self, mass_of: impl Fn(&Atom) -> f64) -> RVec {
+        let mut weighted_sum = RVec::default();
+        let mut total_mass = 0.0;
+
+        for atom in &self.atoms {
+            let mass = mass_of(atom);
+
+            weighted_sum += atom.position * mass;
+            total_mass += mass;
+        }
+
+        weighted_sum / total_mass
+    }
+
+    /// Return the moment of inertia tensor of the configuration about its center
+    /// of mass, using the input function to get the mass of each atom.
+    pub fn inertia_tensor(&self, mass_of: impl Fn(&Atom) -> f64) -> [[f64; 3]; 3] {
+        let center = self.center_of_mass(&mass_of);
+        let mut tensor = [[0.0; 3]; 3];
+
+        for atom in &self.atoms {
+            let mass = mass_of(atom);
+            let dr = atom.position - center;
+            let r = [dr.x, dr.y, dr.z];
+            let r2 = dr.x.powi(2) + dr.y.powi(2) + dr.z.powi(2);
+
+            for i in 0..3 {
+                for j in 0..3 {
+                    let delta = if i == j { r2 } else { 0.0 };
+                    tensor[i][j] += mass * (delta - r[i] * r[j]);
+                }
+            }
+        }
+
+        tensor
+    }
+
+    /// Return the gyration tensor of the atom positions about their geometric center.
+    ///
+    /// The atoms are not weighted by their mass. The tensor is zero for a
+    /// configuration without atoms.
+    pub fn gyration_tensor(&self) -> [[f64; 3]; 3] {
+        self.weighted_gyration_tensor(|_| 1.0)
+    }
+
+    /// Return the mass weighted gyration tensor of the atom positions about their
+    /// center of mass. The tensor is zero if the total mass is not positive.
+    fn weighted_gyration_tensor(&self, mass_of: impl Fn(&Atom) -> f64) -> [[f64; 3]; 3] {
+        let mut tensor = [[0.0; 3]; 3];
+
+        let total_mass: f64 = self.atoms.iter().map(&mass_of).sum();
+
+        if total_mass <= 0.0 {
+            return tensor;
+        }
+
+        let center = self.center_of_mass(&mass_of);
+
+        for atom in &self.atoms {
+            let weight = mass_of(atom) / total_mass;
+            let dr = atom.position - center;
+            let r = [dr.x, dr.y, dr.z];
+
+            for (row, ri) in tensor.iter_mut().zip(r.iter()) {
+                for (value, rj) in row.iter_mut().zip(r.iter()) {
+                    *value += weight * ri * rj;
+                }
+            }
+        }
+
+        tensor
+    }
+
+    /// Return the asphericity of the configuration from the eigenvalues of its
+    /// gyration tensor, as `λ3 - (λ1 + λ2) / 2` where `λ1 <= λ2 <= λ3`.
+    ///
+    /// The asphericity is zero for spherically symmetric configurations, such as
+    /// atoms on the corners of a cube, and grows as the configuration is elongated.
+    pub fn asphericity(&self) -> f64 {
+        let (values, _) = linalg::symmetric_eigen(&self.gyration_tensor());
+
+        values[2] - 0.5 * (values[0] + values[1])
+    }
+
+    /// Return the normal of the plane which best fits the atom positions, as the
+    /// eigenvector of the mass weighted gyration tensor with the smallest eigenvalue.
+    pub fn best_fit_plane_normal(&self, mass_of: impl Fn(&Atom) -> f64) -> RVec {
+        let (_, vectors) = linalg::symmetric_eigen(&self.weighted_gyration_tensor(mass_of));
+
+        RVec {
+            x: vectors[0][0],
+            y: vectors[1][0],
+            z: vectors[2][0],
+        }
+    }
+
+    /// Rotate the configuration around the center of mass to align the normal of
+    /// its best fitting plane (see `best_fit_plane_normal`) with a target normal.
+    ///
+    /// Since the plane has no direction the normal is aligned to either the target
+    /// or its opposite, whichever requires the smallest rotation.
+    pub fn align_plane_to(&mut self, target_normal: RVec, mass_of: impl Fn(&Atom) -> f64) {
+        if self.atoms.is_empty() {
+            return;
+        }
+
+        let mut normal = self.best_fit_plane_normal(&mass_of);
+
+        if normal.dot(&target_normal) < 0.0 {
+            normal = -normal;
+        }
+
+        let axis = normal.cross(&target_normal);
+        let angle = normal.angle(&target_normal);
+        let center = self.center_of_mass(&mass_of);
+
+        self.rotate(axis, angle, center);
+    }
+
+    /// Rotate the atom positions by an angle in radians around an axis through
+    /// a center point, see `RVec::rotate`. Velocities are rotated with the positions.
+    pub fn rotate(&mut self, axis: RVec, angle: f64, center: RVec) {
+        for atom in self.atoms.iter_mut() {
+            atom.position = center + (atom.position - center).rotate(&axis, angle);
+
+            if let Some(velocity) = atom.velocity.as_mut() {
+                *velocity = velocity.rotate(&axis, angle);
+            }
+        }
+    }
+
+    /// Return the principal axes of the configuration, using the input function
+    /// to get the mass of each atom.
+    ///
+    /// The axes are the normalized eigenvectors of the inertia tensor, ordered by
+    /// increasing moment of inertia.
+    pub fn principal_axes(&self, mass_of: impl Fn(&Atom) -> f64) -> [RVec; 3] {
+        let (_, vectors) = linalg::symmetric_eigen(&self.inertia_tensor(mass_of));
+
+        let axis = |i: usize| RVec {
+            x: vectors[0][i],
+            y: vectors[1][i],
+            z: vectors[2][i],
+        };
+
+        [axis(0), axis(1), axis(2)]
+    }
+
+    /// Return the root mean square deviation of the atoms between this and another
+    /// configuration, after optimally superimposing them.
+    ///
+    /// Both configurations are centered on their center of mass and the deviation is
+    /// minimized over all rotations with the Kabsch algorithm, weighting each atom by
+    /// its mass. The minimized deviation follows directly from the singular values of
+    /// the covariance matrix of the positions, so the rotation itself is not computed.
+    /// The atoms must be in the same order in both configurations.
+    pub fn rmsd_aligned(
+        &self,
+        other: &Conf,
+        mass_of: impl Fn(&Atom) -> f64,
+    ) -> Result<f64, String> {
+        if self.atoms.len() != other.atoms.len() {
+            return Err(format!(
+                "configurations have different numbers of atoms ({} and {})",
+                self.atoms.len(),
+                other.atoms.len()
+            ));
+        }
+
+        let total_mass: f64 = self.atoms.iter().map(&mass_of).sum();
+
+        if total_mass <= 0.0 {
+            return Err("configurations have no mass".to_string());
+        }
+
+        let center1 = self.center_of_mass(&mass_of);
+        let center2 = other.center_of_mass(&mass_of);
+
+        let mut covariance = [[0.0; 3]; 3];
+        let mut sum_squared = 0.0;
+
+        for (atom1, atom2) in self.atoms.iter().zip(other.atoms.iter()) {
+            let mass = mass_of(atom1);
+            let r1 = atom1.position - center1;
+            let r2 = atom2.position - center2;
+
+            sum_squared += mass * (r1.dot(&r1) + r2.dot(&r2));
+
+            for (row, a) in covariance.iter_mut().zip([r1.x, r1.y, r1.z].iter()) {
+                for (value, b) in row.iter_mut().zip([r2.x, r2.y, r2.z].iter()) {
+                    *value += mass * a * b;
+                }
+            }
+        }
+
+        // The singular values are the square roots of the eigenvalues of H^T H
+        let mut product = [[0.0; 3]; 3];
+
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = covariance.iter().map(|h| h[i] * h[j]).sum();
+            }
+        }
+
+        let (eigenvalues, _) = linalg::symmetric_eigen(&product);
+        let singular_values = eigenvalues
+            .iter()
+            .map(|value| value.max(0.0).sqrt())
+            .collect::<Vec<_>>();
+
+        // If the optimal orthogonal transformation is a reflection, the best rotation
+        // is found by flipping the direction of the smallest singular value
+        let sign = if linalg::determinant(&covariance) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let sum = singular_values[2] + singular_values[1] + sign * singular_values[0];
+
+        Ok(((sum_squared - 2.0 * sum) / total_mass).max(0.0).sqrt())
+    }
+
+    /// Remove the center of mass motion from the atoms, so that the total momentum
+    /// of the configuration becomes zero. Atoms without a velocity are not modified.
+    pub fn remove_center_of_mass_motion(&mut self, mass_of: impl Fn(&Atom) -> f64) {
+        let total_mass: f64 = self
+            .atoms
+            .iter()
+            .filter(|atom| atom.velocity.is_some())
+            .map(&mass_of)
+            .sum();
+
+        if total_mass <= 0.0 {
+            return;
+        }
+
+        let com_velocity = self.total_momentum(&mass_of) / total_mass;
+
+        for atom in self.atoms.iter_mut() {
+            if let Some(ref mut velocity) = atom.velocity {
+                *velocity -= com_velocity;
+            }
+        }
+    }
+
+    /// Assign random velocities to all atoms from the Maxwell-Boltzmann distribution at
+    /// a temperature, using the input function to get the mass of each atom and the
+    /// input Boltzmann constant.
+    ///
+    /// Each velocity component is drawn from a normal distribution with variance
+    /// `kb * temperature / mass`, using a random number generator seeded with the input
+    /// seed to make the velocities reproducible. The center of mass motion is removed
+    /// afterwards. Requires the `rand` feature.
+    #[cfg(feature = "rand")]
+    pub fn assign_maxwell_velocities(
+        &mut self,
+        temperature: f64,
+        kb: f64,
+        mass_of: impl Fn(&Atom) -> f64,
+        seed: u64,
+    ) {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        // Draw from the standard normal distribution using the Box-Muller transform
+        let mut normal = || {
+            let u1 = 1.0 - rng.gen::<f64>();
+            let u2 = rng.gen::<f64>();
+
+            (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+        };
+
+        for atom in self.atoms.iter_mut() {
+            let sigma = (kb * temperature / mass_of(atom)).sqrt();
+
+            atom.velocity = Some(RVec {
+                x: sigma * normal(),
+                y: sigma * normal(),
+                z: sigma * normal(),
+            });
+        }
+
+        self.remove_center_of_mass_motion(mass_of);
+    }
+
+    /// Write the configuration to a file, with the format determined by the file extension.
+    ///
+    /// The supported extensions are the same as for `Conf::load`, except for `.crd`
+    /// which can only be read. As for `write_format` an existing file is not overwritten
+    /// if the configuration cannot be written.
+    ///
+    /// GROMOS87 files are written with a check of the box size, so a configuration
+    /// without a box, as read from an XYZ file or a PDB file without a `CRYST1` record,
+    /// must first have one set, for example with `fit_box`.
+    pub fn save(&self, path: &Path) -> Result<(), WriteError> {
+        match get_file_format(path) {
+            Some(FileFormat::Gromos87) => self.write_format::<Gromos87>(path),
+            Some(FileFormat::Gromos87Gz) => {
+                let mut writer = GzEncoder::new(Vec::new(), Compression::default());
+                Gromos87::write(self, &mut writer)?;
+                let buf = writer.finish()?;
+
+                File::create(path)?.write_all(&buf)?;
+
+                Ok(())
+            }
+            Some(FileFormat::Pdb) => self.write_format::<Pdb>(path),
+            Some(FileFormat::Xyz) => self.write_format::<Xyz>(path),
+            Some(FileFormat::Charmm) | None => {
+                Err(WriteError::UnknownFormat(path.display().to_string()))
+            }
+        }
+    }
+
+    /// Write the configuration to a file of the given format.
+    ///
+    /// The configuration is formatted in memory before the file is created, so a
+    /// configuration which cannot be written never overwrites an existing file.
+    pub fn write_format<F: ConfFormat>(&self, path: &Path) -> Result<(), WriteError> {
+        let mut buf = Vec::new();
+        F::write(self, &mut buf)?;
+
+        File::create(path)?.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Wrap every atom of the configuration into its box, along every direction of
+    /// non-zero size. Residues may be split over the periodic boundaries.
+    pub fn wrap_into_box(&mut self) {
+        let (origin, size) = (self.origin, self.size);
+
+        self.atoms
+            .iter_mut()
+            .for_each(|atom| atom.position = atom.wrapped_position(origin, size));
+    }
+
+    /// Mirror the configuration along a direction about a plane at the input coordinate.
+    ///
+    /// The velocity component along the direction is negated. The box is not changed.
+    pub fn reflect(&mut self, dir: Direction, plane_coord: f64) {
+        for atom in self.atoms.iter_mut() {
+            let x = atom.position.get_mut(dir);
+            *x = 2.0 * plane_coord - *x;
+
+            if let Some(velocity) = atom.velocity.as_mut() {
+                let v = velocity.get_mut(dir);
+                *v = -*v;
+            }
+        }
+    }
+
+    /// Translate the configuration to place an atom at the center of the box, then
+    /// wrap all atoms into the box with `wrap_into_box`. Panics if there is no atom
+    /// with the input index.
+    pub fn center_on_atom(&mut self, atom_index: usize) {
+        let position = self.atoms[atom_index].position;
+        self.center_on_point(position);
+    }
+
+    /// Translate the configuration to place its center of mass at the center of
+    /// the box, then wrap all atoms into the box with `wrap_into_box`.
+    ///
+    /// The center of mass is computed before wrapping, so it is only kept at the center
+    /// if no atoms are moved by the wrapping.
+    pub fn center_box_on_com(&mut self, mass_of: impl Fn(&Atom) -> f64) {
+        let com = self.center_of_mass(mass_of);
+        self.center_on_point(com);
+    }
+
+    /// Translate the configuration to place a point at the center of the box, then wrap
+    /// all atoms into the box.
+    fn center_on_point(&mut self, point: RVec) {
+        let shift = self.origin + self.size * 0.5 - point;
+
+        self.atoms
+            .iter_mut()
+            .for_each(|atom| atom.position += shift);
+
+        self.wrap_into_box();
+    }
+
+    /// Wrap the residues of the configuration into its box, keeping them whole.
+    ///
+    /// Every complete residue is shifted as a unit by the box vectors which bring
+    /// its center of geometry into the box, so its atoms stay together even if
+    /// some of them end up outside of it. Incomplete residues are left in place.
+    pub fn wrap_molecules(&mut self) {
+        let ranges = get_residue_ranges(self)
+            .into_iter()
+            .filter_map(|range| range.ok())
+            .collect::<Vec<_>>();
+
+        for range in ranges {
+            let atoms = &mut self.atoms[range];
+
+            let sum = atoms
+                .iter()
+                .fold(RVec::default(), |acc, atom| acc + atom.position);
+            let num = atoms.len() as f64;
+            let center = RVec {
+                x: sum.x / num,
+                y: sum.y / num,
+                z: sum.z / num,
+            };
+
+            let shift = get_pbc_shift(center, self.origin, self.size);
+            atoms.iter_mut().for_each(|atom| atom.position += shift);
+        }
+    }
+
+    /// Make residues which are split over the periodic boundaries whole.
+    ///
+    /// In every complete residue the first atom is kept in place and each following
+    /// atom is moved to its periodic image which is closest to the previous atom,
+    /// so that bonded atoms are next to each other. Incomplete residues are not
+    /// modified.
+    pub fn make_molecules_whole(&mut self) {
+        let ranges = get_residue_ranges(self)
+            .into_iter()
+            .filter_map(|range| range.ok())
+            .collect::<Vec<_>>();
+
+        for range in ranges {
+            for i in (range.start + 1)..range.end {
+                let previous = self.atoms[i - 1].position;
+                let position = self.atoms[i].position;
+
+                self.atoms[i].position = RVec::closest_image(position, previous, &self.size);
+            }
+        }
+    }
+
+    /// Translate the atoms of a single residue.
+    ///
+    /// The residue is given by its index among the complete residues, in the order
+    /// of `iter_valid_residues`. An error is returned if there is no complete residue
+    /// with the index.
+    pub fn translate_residue(
+        &mut self,
+        residue_index: usize,
+        shift: RVec,
+    ) -> Result<(), ResidueError> {
+        let range = get_residue_ranges(self)
+            .into_iter()
+            .filter_map(Result::ok)
+            .nth(residue_index)
+            .ok_or(ResidueError::NoResidue(residue_index))?;
+
+        self.atoms[range]
+            .iter_mut()
+            .for_each(|atom| atom.position += shift);
+
+        Ok(())
+    }
+}
+
+/// The range of atom indices of a residue, or for a bad residue the range of atoms
+/// which were skipped over along with the error.
+type ResidueRange = Result<Range<usize>, (Range<usize>, ResidueError)>;
+
+/// Get the ranges of atom indices which make up each residue of `iter_residues`,
+/// without copying the atoms. Every atom is in exactly one range.
+pub fn get_residue_ranges(conf: &Conf) -> Vec<ResidueRange> {
+    let mut iter = conf.iter_residues();
+    let mut ranges = Vec::new();
+
+    loop {
+        let start = iter.index;
+
+        match iter.next_range() {
+            Some(Ok(range)) => ranges.push(Ok(range)),
+            Some(Err(err)) => ranges.push(Err((start..iter.index, err))),
+            None => break,
+        }
+    }
+
+    ranges
+}
+
+/// Read the number of atoms of a `Gromos87` formatted file from its header, without
+/// reading the atoms.
+pub fn count_atoms_gromos87(path: &Path) -> Result<usize, ReadError> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+
+    let (_, num_atoms) =
+        gromos87::read_gromos87_title_and_count(&mut reader).map_err(ReadError::Gromos87)?;
+
+    Ok(num_atoms)
+}
+
+/// Return the volume of a box given as a matrix whose rows are the box vectors.
+///
+/// The volume is the absolute value of the determinant of the matrix, or for
+/// a rectangular box with no off-diagonal elements the product of the diagonal.
+pub fn box_volume(matrix: &[[f64; 3]; 3]) -> f64 {
+    if box_is_orthorhombic(matrix, 0.0) {
+        (matrix[0][0] * matrix[1][1] * matrix[2][2]).abs()
+    } else {
+        linalg::determinant(matrix).abs()
+    }
+}
+
+/// Return whether all off-diagonal elements of a box matrix are within a tolerance
+/// of zero, that is whether the box vectors are orthogonal and along the axes.
+pub fn box_is_orthorhombic(matrix: &[[f64; 3]; 3], tolerance: f64) -> bool {
+    (0..3).all(|i| (0..3).all(|j| i == j || matrix[i][j].abs() <= tolerance))
+}
+
+/// File formats which can be determined from a file extension.
+enum FileFormat {
+    Gromos87,
+    Gromos87Gz,
+    Pdb,
+    Xyz,
+    Charmm,
+}
+
+fn get_file_format(path: &Path) -> Option<FileFormat> {
+    let file_name = path.file_name()?.to_str()?.to_lowercase();
+
+    if file_name.ends_with(".gro.gz") {
+        Some(FileFormat::Gromos87Gz)
+    } else if file_name.ends_with(".gro") {
+        Some(FileFormat::Gromos87)
+    } else if file_name.ends_with(".pdb") {
+        Some(FileFormat::Pdb)
+    } else if file_name.ends_with(".xyz") {
+        Some(FileFormat::Xyz)
+    } else if file_name.ends_with(".crd") {
+        Some(FileFormat::Charmm)
+    } else {
+        None
+    }
+}
+
+/// Get the shift which moves a position into the box, along every dimension
+/// of non-zero size.
+fn get_pbc_shift(position: RVec, origin: RVec, size: RVec) -> RVec {
+    let shift = |x: f64, x0: f64, dx: f64| {
+        if dx > 0.0 {
+            -dx * ((x - x0) / dx).floor()
+        } else {
+            0.0
+        }
+    };
+
+    RVec {
+        x: shift(position.x, origin.x, size.x),
+        y: shift(position.y, origin.y, size.y),
+        z: shift(position.z, origin.z, size.z),
+    }
+}
+
+/// Error from iterating over or looking up residues.
+#[derive(Debug, Fail, PartialEq)]
+pub enum ResidueError {
+    #[fail(display = "Bad residue starting at index {}", index)]
+    BadResidue { index: usize },
+    #[fail(display = "No complete residue with index {}", _0)]
+    NoResidue(usize),
+}
+
+/// An iterator over residues of a collection of `Atom`s.
+#[derive(Debug)]
+pub struct ResidueIter<'a> {
+    index: usize,
+    atoms: &'a [Atom],
+}
+
+impl<'a> ResidueIter<'a> {
+    fn get_iter_error(&mut self, i: usize) -> ResidueError {
+        self.index += i;
+        ResidueError::BadResidue {
+            index: self.index - i,
+        }
+    }
+
+    /// Advance the iterator over the next residue, returning the range of its atoms
+    /// without copying them.
+    fn next_range(&mut self) -> Option<Result<Range<usize>, ResidueError>> {
+        let atom1 = self.atoms.get(self.index)?;
+
+        let residue = atom1.residue.borrow();
+        let residue_len = residue.atoms.len();
+
+        // If the first atom is wrong, return an error and skip it
+        if !Rc::ptr_eq(&atom1.name, &residue.atoms[0]) {
+            return Some(Err(self.get_iter_error(1)));
+        }
+
+        for i in 1..residue_len {
+            match self.atoms.get(i + self.index) {
+                Some(atom) => {
+                    if !Rc::ptr_eq(&atom.name, &residue.atoms[i]) {
+                        return Some(Err(self.get_iter_error(i)));
+                    }
+                }
+                None => {
+                    return Some(Err(self.get_iter_error(i)));
+                }
+            }
+        }
+
+        let start = self.index;
+        self.index += residue_len;
+
+        Some(Ok(start..self.index))
+    }
+}
+
+impl<'a> Iterator for ResidueIter<'a> {
+    type Item = Result<Vec<Atom>, ResidueError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let atoms = self.atoms;
+
+        self.next_range()
+            .map(|range| range.map(|range| atoms[range].to_vec()))
+    }
+}
+
+/// Information about a residue.
+#[derive(Clone, Debug)]
+pub struct Residue {
+    /// The residue name.
+    pub name: Rc<RefCell<String>>,
+    /// Atoms which belong to the residue.
+    pub atoms: Vec<Rc<RefCell<String>>>,
+}
+
+impl Residue {
+    /// Compare the residue's name to an input.
+    pub fn cmp_name(&self, to_name: &str) -> bool {
+        &*self.name.borrow() == to_name
+    }
+
+    /// Get the atom with the input name from the residue, adding it last
+    /// if it does not exist.
+    pub fn get_or_insert_atom(&mut self, atom_name: &str) -> Rc<RefCell<String>> {
+        self.atoms
+            .iter()
+            .find(|name| &*name.borrow() == &atom_name)
+            .cloned()
+            .unwrap_or_else(|| {
+                let atom = Rc::new(RefCell::new(String::from(atom_name)));
+                self.atoms.push(atom.clone());
+
+                atom
+            })
+    }
+}
+
+/// A single atom belonging to a residue in the configuration.
+#[derive(Clone, Debug)]
+pub struct Atom {
+    /// A reference to the atom name. Should point to an atom in the `residue`.
+    pub name: Rc<RefCell<String>>,
+    /// A reference to the residue which owns the atom. Will typicall point to a residue
+    /// in the `Conf` in which this atom exists.
+    pub residue: Rc<RefCell<Residue>>,
+    /// The atom position in configuration-relative coordinates.
+    pub position: RVec,
+    /// The atom velocity, if it has one.
+    pub velocity: Option<RVec>,
+    /// The atom charge, if it has one.
+    pub charge: Option<f64>,
+    /// The occupancy of the atom position, if it has one. Read from and written to PDB files.
+    pub occupancy: Option<f64>,
+    /// The temperature factor (B-factor) of the atom, if it has one. Read from and written
+    /// to PDB files.
+    pub b_factor: Option<f64>,
+    /// The residue number of the atom, if it has one. Written to GROMOS87 files instead
+    /// of the running residue number if `write_stored_numbers` is set in the options.
+    pub residue_number: Option<usize>,
+    /// The atom number, if it has one. Written to GROMOS87 files instead of the running
+    /// atom number if `write_stored_numbers` is set in the options.
+    pub atom_number: Option<usize>,
+}
+
+impl Atom {
+    /// Construct an atom, verifying that the atom name belongs to the residue.
+    pub fn new_checked(
+        residue: Rc<RefCell<Residue>>,
+        atom_name: &Rc<RefCell<String>>,
+        position: RVec,
+        velocity: Option<RVec>,
+    ) -> Result<Atom, String> {
+        if !residue
+            .borrow()
+            .atoms
+            .iter()
+            .any(|name| Rc::ptr_eq(name, atom_name))
+        {
+            return Err(format!(
+                "atom '{}' does not belong to residue '{}'",
+                atom_name.borrow(),
+                residue.borrow().name.borrow()
+            ));
+        }
+
+        Ok(Atom {
+            name: Rc::clone(atom_name),
+            residue,
+            position,
+            velocity,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        })
+    }
+
+    /// Compare the atom's name to an input.
+    pub fn cmp_name(&self, to_name: &str) -> bool {
+        &*self.name.borrow() == to_name
+    }
+
+    /// Compare the atom's parent residue name to an input.
+    pub fn cmp_residue_name(&self, to_name: &str) -> bool {
+        &*self.residue.borrow().name.borrow() == to_name
+    }
+
+    /// Return the periodic image of the atom position inside a box which spans from
+    /// the origin up to the origin plus its size, along every dimension of non-zero size.
+    pub fn wrapped_position(&self, origin: RVec, size: RVec) -> RVec {
+        self.position + get_pbc_shift(self.position, origin, size)
+    }
+}
+
+fn get_or_insert_residue(
+    name: &str,
+    residues: &mut Vec<Rc<RefCell<Residue>>>,
+) -> Rc<RefCell<Residue>> {
+    residues
+        .iter()
+        .find(|res| *res.borrow().name.borrow() == name)
+        .cloned()
+        .unwrap_or_else(|| {
+            let res = Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new(String::from(name))),
+                atoms: Vec::new(),
+            }));
+
+            residues.push(res.clone());
+            res
+        })
+}
+
+pub fn get_or_insert_atom_and_residue(
+    residue_name: &str,
+    atom_name: &str,
+    residues: &mut Vec<Rc<RefCell<Residue>>>,
+) -> Result<(Rc<RefCell<Residue>>, Rc<RefCell<String>>), String> {
+    let residue = get_or_insert_residue(residue_name, residues);
+    let atom = residue.borrow_mut().get_or_insert_atom(atom_name);
+
+    Ok((residue, atom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    #[test]
+    fn get_or_insert_residue_from_list() {
+        let mut residues = Vec::new();
+
+        let res1_name = "RES1";
+        let res1 = get_or_insert_residue(res1_name, &mut residues);
+
+        assert_eq!(*res1.borrow().name.borrow(), res1_name);
+        assert!(&res1.borrow().atoms.is_empty());
+
+        assert_eq!(residues.len(), 1);
+        assert!(Rc::ptr_eq(&res1, &residues[0]));
+
+        let res1_again = get_or_insert_residue(res1_name, &mut residues);
+        assert!(Rc::ptr_eq(&res1, &res1_again));
+
+        let res2_name = "RES2";
+        let res2 = get_or_insert_residue(res2_name, &mut residues);
+
+        assert_eq!(*res2.borrow().name.borrow(), res2_name);
+        assert!(&res2.borrow().atoms.is_empty());
+        assert!(!Rc::ptr_eq(&res1, &res2));
+
+        assert_eq!(residues.len(), 2);
+        assert!(Rc::ptr_eq(&res2, &residues[1]));
+    }
+
+    #[test]
+    fn get_or_insert_atom_from_residue() {
+        let mut residue = Residue {
+            name: Rc::new(RefCell::new(String::from("RES"))),
+            atoms: Vec::new(),
+        };
+
+        let atom1_name = "ATOM1";
+        let atom1 = residue.get_or_insert_atom(atom1_name);
+
+        assert_eq!(&*atom1.borrow(), atom1_name);
+        assert!(Rc::ptr_eq(&atom1, &residue.atoms[0]));
+
+        let atom1_again = residue.get_or_insert_atom(atom1_name);
+        assert!(Rc::ptr_eq(&atom1_again, &atom1));
+
+        let atom2_name = "ATOM2";
+        let atom2 = residue.get_or_insert_atom(atom2_name);
+
+        assert_eq!(&*atom2.borrow(), atom2_name);
+        assert!(Rc::ptr_eq(&atom2, &residue.atoms[1]));
+        assert!(!Rc::ptr_eq(&atom1, &atom2));
+    }
+
+    #[test]
+    fn get_atom_and_residue_from_list() {
+        let mut residues = Vec::new();
+
+        let res1_name = "RES1";
+        let atom1_name = "AT1";
+
+        let (res1, atom1) =
+            get_or_insert_atom_and_residue(res1_name, atom1_name, &mut residues).unwrap();
+
+        assert_eq!(*res1.borrow().name.borrow(), res1_name);
+        assert_eq!(&*atom1.borrow(), &atom1_name);
+        assert!(Rc::ptr_eq(&atom1, &res1.borrow().atoms[0]));
+
+        let atom2_name = "AT2";
+        let (res1_again, atom2) =
+            get_or_insert_atom_and_residue(res1_name, atom2_name, &mut residues).unwrap();
+
+        assert!(Rc::ptr_eq(&res1, &res1_again));
+        assert_eq!(&*atom2.borrow(), &atom2_name);
+
+        let res2_name = "RES2";
+        let atom3_name = "AT3";
+
+        let (res2, atom3) =
+            get_or_insert_atom_and_residue(res2_name, atom3_name, &mut residues).unwrap();
+
+        assert!(!Rc::ptr_eq(&res1, &res2));
+        assert_eq!(*res2.borrow().name.borrow(), res2_name);
+        assert_eq!(&*atom3.borrow(), &atom3_name);
+
+        // An atom with a name of another residue can be added, they will not be the same
+        let (res2_again, atom1_not_res1) =
+            get_or_insert_atom_and_residue(res2_name, atom1_name, &mut residues).unwrap();
+
+        assert!(Rc::ptr_eq(&res2, &res2_again));
+        assert!(!Rc::ptr_eq(&atom1, &atom1_not_res1));
+    }
+
+    #[test]
+    fn read_bad_filename_gives_error() {
+        let mut filename = temp_dir();
+        filename.push("_file_should_not_exist_mdio_test_");
+
+        assert!(Conf::from_gromos87(&filename).is_err());
+    }
+
+    #[test]
+    fn residue_iter_on_empty_conf_returns_none() {
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            residues: Vec::new(),
+            atoms: Vec::new(),
+        };
+
+        let mut iter = conf.iter_residues();
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn residue_iter_over_two_atoms_of_different_residues() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT2".to_string()))],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                // Residue 2
+                Atom {
+                    name: Rc::clone(&residues[1].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[1]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: Some(RVec {
+                        x: 0.0,
+                        y: 0.1,
+                        z: 0.2,
+                    }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                // Residue 1
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 3.0,
+                        y: 4.0,
+                        z: 5.0,
+                    },
+                    velocity: Some(RVec {
+                        x: 0.3,
+                        y: 0.4,
+                        z: 0.5,
+                    }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let mut iter = conf.iter_residues();
+
+        let res = iter.next().unwrap().unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(Rc::ptr_eq(&res[0].residue, &residues[1]));
+        assert!(Rc::ptr_eq(&res[0].name, &residues[1].borrow().atoms[0]));
+        assert_eq!(
+            res[0].position,
+            RVec {
+                x: 0.0,
+                y: 1.0,
+                z: 2.0,
+            }
+        );
+        assert_eq!(
+            res[0].velocity.unwrap(),
+            RVec {
+                x: 0.0,
+                y: 0.1,
+                z: 0.2,
+            }
+        );
+
+        let res = iter.next().unwrap().unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
+        assert_eq!(
+            res[0].position,
+            RVec {
+                x: 3.0,
+                y: 4.0,
+                z: 5.0,
+            }
+        );
+        assert_eq!(
+            res[0].velocity.unwrap(),
+            RVec {
+                x: 0.3,
+                y: 0.4,
+                z: 0.5,
+            }
+        );
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterate_over_a_residue_with_several_atoms() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 3.0,
+                        y: 4.0,
+                        z: 5.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let mut iter = conf.iter_residues();
+
+        let res = iter.next().unwrap().unwrap();
+        assert_eq!(res.len(), 2);
+
+        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
+        assert_eq!(
+            res[0].position,
+            RVec {
+                x: 0.0,
+                y: 1.0,
+                z: 2.0,
+            }
+        );
+
+        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
+        assert_eq!(
+            res[1].position,
+            RVec {
+                x: 3.0,
+                y: 4.0,
+                z: 5.0,
+            }
+        );
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterating_over_residues_ensures_that_all_are_consistent() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                // Complete residue
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 3.0,
+                        y: 4.0,
+                        z: 5.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                // Incomplete residue: misses second atom
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                // A final complete residue
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 6.0,
+                        y: 7.0,
+                        z: 8.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 9.0,
+                        y: 10.0,
+                        z: 11.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let mut iter = conf.iter_residues();
+
+        assert!(iter.next().unwrap().is_ok());
+
+        // Second gives error
+        assert!(iter.next().unwrap().is_err());
+
+        // Third recovers (TODO: Decide whether this should be the case)
+        let res = iter.next().unwrap().unwrap();
+        assert_eq!(res.len(), 2);
+
+        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
+        assert_eq!(
+            res[0].position,
+            RVec {
+                x: 6.0,
+                y: 7.0,
+                z: 8.0,
+            }
+        );
+
+        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
+        assert_eq!(
+            res[1].position,
+            RVec {
+                x: 9.0,
+                y: 10.0,
+                z: 11.0,
+            }
+        );
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterating_over_residues_ensures_that_they_are_ordered() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                // Residue begins with wrong atom, and skipped
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                // This residue (which along with the previous atom is a good residue)
+                // is found as incomplete and skipped
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                // The next residue is good
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 6.0,
+                        y: 7.0,
+                        z: 8.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 9.0,
+                        y: 10.0,
+                        z: 11.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let mut iter = conf.iter_residues();
+
+        // First and second residues will be bad (both are incomplete)
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().unwrap().is_err());
+
+        // This is good
+        let res = iter.next().unwrap().unwrap();
+        assert_eq!(res.len(), 2);
+
+        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
+        assert_eq!(
+            res[0].position,
+            RVec {
+                x: 6.0,
+                y: 7.0,
+                z: 8.0,
+            }
+        );
+
+        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
+        assert_eq!(
+            res[1].position,
+            RVec {
+                x: 9.0,
+                y: 10.0,
+                z: 11.0,
+            }
+        );
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn iterate_over_several_different_residues() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("At2".to_string())),
+                ],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
+        ];
+
+        // This configuration contains 2 of the first residue, then 2 of the second,
+        // and finally 1 of the first
+        let atoms = vec![
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 2.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 3.0,
+                    y: 4.0,
+                    z: 5.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 6.0,
+                    y: 7.0,
+                    z: 8.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 9.0,
+                    y: 10.0,
+                    z: 11.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec {
+                    x: 12.0,
+                    y: 13.0,
+                    z: 14.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec {
+                    x: 15.0,
+                    y: 16.0,
+                    z: 17.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 18.0,
+                    y: 19.0,
+                    z: 20.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 21.0,
+                    y: 22.0,
+                    z: 23.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+        ];
+
+        let conf = Conf {
+            title: "System".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            residues: residues.clone(),
+            atoms,
+        };
+
+        let mut iter = conf.iter_residues();
+
+        // Check the fourth and fifth (final) residues
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_ok());
+
+        let res4 = iter.next().unwrap().unwrap();
+        assert_eq!(res4.len(), 1);
+        assert!(Rc::ptr_eq(&res4[0].residue, &residues[1]));
+        assert!(Rc::ptr_eq(&res4[0].name, &residues[1].borrow().atoms[0]));
+        assert_eq!(
+            res4[0].position,
+            RVec {
+                x: 15.0,
+                y: 16.0,
+                z: 17.0,
+            }
+        );
+        assert_eq!(res4[0].velocity, None);
+
+        let res5 = iter.next().unwrap().unwrap();
+        assert_eq!(res5.len(), 2);
+
+        assert!(Rc::ptr_eq(&res5[0].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res5[0].name, &residues[0].borrow().atoms[0]));
+        assert_eq!(
+            res5[0].position,
+            RVec {
+                x: 18.0,
+                y: 19.0,
+                z: 20.0,
+            }
+        );
+        assert_eq!(res5[0].velocity, None);
+
+        assert!(Rc::ptr_eq(&res5[1].residue, &residues[0]));
+        assert!(Rc::ptr_eq(&res5[1].name, &residues[0].borrow().atoms[1]));
+        assert_eq!(
+            res5[1].position,
+            RVec {
+                x: 21.0,
+                y: 22.0,
+                z: 23.0,
+            }
+        );
+        assert_eq!(res5[1].velocity, None);
+
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn multiply_conf_to_extend_it() {
+        let size = RVec {
+            x: 10.0,
+            y: 20.0,
+            z: 30.0,
+        };
+
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT2".to_string()))],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size,
+            residues: residues.clone(),
+            atoms: vec![
+                Atom {
+                    name: Rc::clone(&residues[1].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[1]),
+                    position: RVec {
+                        x: 0.0,
+                        y: 1.0,
+                        z: 2.0,
+                    },
+                    velocity: Some(RVec {
+                        x: 0.0,
+                        y: 0.1,
+                        z: 0.2,
+                    }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 3.0,
+                        y: 4.0,
+                        z: 5.0,
+                    },
+                    velocity: Some(RVec {
+                        x: 0.3,
+                        y: 0.4,
+                        z: 0.5,
+                    }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let (nx, ny, nz) = (2, 3, 4);
+        let multiplied_conf = conf.pbc_multiply(nx, ny, nz);
+
+        assert_eq!(
+            multiplied_conf.size,
+            RVec {
+                x: 10.0 * (nx as f64),
+                y: 20.0 * (ny as f64),
+                z: 30.0 * (nz as f64),
+            }
+        );
+        assert_eq!(multiplied_conf.atoms.len(), conf.atoms.len() * nx * ny * nz);
+
+        // The final atom should be from the maximum (nx, ny, nz) image
+        assert!(Rc::ptr_eq(
+            &multiplied_conf.atoms.last().unwrap().name,
+            &conf.atoms.last().unwrap().name
+        ));
+        assert!(Rc::ptr_eq(
+            &multiplied_conf.atoms.last().unwrap().residue,
+            &conf.atoms.last().unwrap().residue
+        ));
+        assert_eq!(
+            multiplied_conf.atoms.last().unwrap().position,
+            conf.atoms.last().unwrap().position + conf.size.pbc_multiply(nx - 1, ny - 1, nz - 1)
+        );
+        assert_eq!(
+            multiplied_conf.atoms.last().unwrap().velocity,
+            conf.atoms.last().unwrap().velocity
+        );
+    }
+
+    #[test]
+    fn tile_conf_to_reach_target_box_size() {
+        let content = "\
+A title
+2
+    1RES     A1    1   1.000   1.000   1.000
+    2RES     A1    2   6.000   6.000   6.000
+  10.00000  10.00000  10.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let target = RVec {
+            x: 25.0,
+            y: 10.0,
+            z: 10.0,
+        };
+
+        let tiled = conf.tile_to_size(target);
+        assert_eq!(
+            tiled.size,
+            RVec {
+                x: 30.0,
+                y: 10.0,
+                z: 10.0,
+            }
+        );
+        assert_eq!(tiled.atoms.len(), 6);
+
+        // The exact target is reached by cropping
+        assert_eq!(tiled.crop(RVec::default(), target).atoms.len(), 5);
+
+        // Directions with zero size are not extended
+        conf.size.y = 0.0;
+        assert_eq!(conf.tile_to_size(target).atoms.len(), 6);
+    }
+
+    #[test]
+    fn grid_free_volume_of_empty_and_filled_boxes() {
+        let content = "\
+A title
+1
+    1RES     A1    1   0.000   0.000   0.000
+   0.20000   0.20000   0.20000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut conf = Conf::from_template_lattice(&template, 10, 10, 10, template.size);
+        assert_eq!(conf.atoms.len(), 1000);
+
+        // Every grid point is within half a cell diagonal of an atom
+        assert_eq!(conf.grid_free_volume(0.2, 0.25), 0.0);
+
+        let free_volume = conf.grid_free_volume(0.05, 0.05);
+        assert!(free_volume > 0.0 && free_volume < 8.0);
+
+        conf.atoms.clear();
+        assert_eq!(conf.grid_free_volume(0.2, 0.25), 8.0);
+        assert_eq!(conf.grid_free_volume(0.2, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rdf_of_simple_cubic_lattice_has_first_peak_at_lattice_spacing() {
+        let content = "\
+A title
+1
+    1RES     A1    1   0.000   0.000   0.000
+   0.50000   0.50000   0.50000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let conf = Conf::from_template_lattice(&template, 6, 6, 6, template.size);
+
+        let bin_width = 0.04;
+        let rdf = conf.rdf(bin_width, 1.2);
+        assert_eq!(rdf.len(), 30);
+
+        // The six nearest neighbours at the spacing 0.5 fall in bin 12
+        let first_peak = rdf.iter().position(|&value| value > 0.0).unwrap();
+        assert_eq!(first_peak, 12);
+
+        let density = 216.0 / 27.0;
+        let shell_volume = 4.0 / 3.0 * PI * (0.52f64.powi(3) - 0.48f64.powi(3));
+        assert!((rdf[12] - 6.0 / (density * shell_volume)).abs() < 1e-9);
+
+        // The next neighbours are at the diagonal of a face
+        assert_eq!(
+            rdf.iter().skip(13).position(|&value| value > 0.0).unwrap() + 13,
+            (0.5 * 2.0f64.sqrt() / bin_width) as usize
+        );
+    }
+
+    #[test]
+    fn pbc_images_yield_the_atoms_of_multiplied_conf() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000  1.0000  2.0000  3.0000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+   1.00000   2.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let (nx, ny, nz) = (2, 3, 2);
+        let atoms = conf.pbc_images(nx, ny, nz).collect::<Vec<_>>();
+        let multiplied = conf.pbc_multiply(nx, ny, nz);
+
+        assert_eq!(atoms.len(), 36);
+        assert_eq!(atoms.len(), multiplied.atoms.len());
+
+        for (atom, expected) in atoms.iter().zip(multiplied.atoms.iter()) {
+            assert!(Rc::ptr_eq(&atom.name, &expected.name));
+            assert!(Rc::ptr_eq(&atom.residue, &expected.residue));
+            assert_eq!(atom.position, expected.position);
+            assert_eq!(atom.velocity, expected.velocity);
+        }
+
+        // The second image is translated along z
+        assert_eq!(
+            atoms[3].position,
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 3.0,
+            }
+        );
+
+        assert_eq!(conf.pbc_images(0, 1, 1).count(), 0);
+    }
+
+    #[test]
+    fn assign_filtered_residues_to_configuration() {
+        // Two types of residues, we want to filter out the second.
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("At2".to_string())),
+                ],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
+        ];
+
+        // This configuration contains 2 different residues, which we will filter to only get one.
+        let atoms = vec![
+            // Filter the next two objects residues
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec {
+                    x: 12.0,
+                    y: 13.0,
+                    z: 14.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec {
+                    x: 15.0,
+                    y: 16.0,
+                    z: 17.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            // Two residues of the type we want to keep (2 atoms per residue)
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 2.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 3.0,
+                    y: 4.0,
+                    z: 5.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 6.0,
+                    y: 7.0,
+                    z: 8.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec {
+                    x: 9.0,
+                    y: 10.0,
+                    z: 11.0,
+                },
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+        ];
+
+        let mut conf = Conf {
+            title: "System".to_string(),
+            origin: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            size: RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            residues: residues.clone(),
+            atoms: atoms.clone(),
+        };
+
+        let residues = conf.iter_residues()
+            .filter_map(|atoms| atoms.ok())
+            .filter(|atoms| {
+                let atom = atoms[0].clone();
+                let residue = atom.residue.clone();
+
+                if &*residue.borrow().name.borrow() == "RES1" {
+                    true
+                } else {
+                    false
+                }
+            })
+            .collect::<Vec<_>>();
+
+        conf.assign_residues(residues.as_slice());
+        assert_eq!(conf.atoms.len(), 4);
+
+        // Compare against the original list, with the first two should-be-filtered
+        // residues being skipped
+        for (atom1, atom2) in conf.atoms.iter().zip(atoms.iter().skip(2)) {
+            assert!(Rc::ptr_eq(&atom1.name, &atom2.name));
+            assert!(Rc::ptr_eq(&atom1.residue, &atom2.residue));
+            assert_eq!(atom1.position, atom2.position);
+            assert_eq!(atom1.velocity, atom2.velocity);
+        }
+    }
+
+    #[test]
+    fn compare_atom_name_and_residue_name_to_input() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![
+                Rc::new(RefCell::new("AT1".to_string())),
+                Rc::new(RefCell::new("AT2".to_string())),
+            ],
+        }));
+
+        let atom = Atom {
+            name: residue.borrow().atoms[0].clone(),
+            residue: residue.clone(),
+            position: RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+
+        assert!(residue.borrow().cmp_name("RES1"));
+        assert!(!residue.borrow().cmp_name("RES2"));
+
+        assert!(atom.cmp_name("AT1"));
+        assert!(!atom.cmp_name("AT2"));
+
+        assert!(atom.cmp_residue_name("RES1"));
+        assert!(!atom.cmp_residue_name("RES2"));
+    }
+
+    #[test]
+    fn wrapped_position_of_atom_outside_box() {
+        let content = "\
+A title
+1
+    1SOL     OW    1  -0.500   4.250   1.000
+   2.00000   2.00000   0.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let atom = &conf.atoms[0];
+
+        let origin = RVec {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+
+        // The box has no size along z, so the position is not wrapped along it
+        assert_eq!(
+            atom.wrapped_position(origin, conf.size),
+            RVec {
+                x: 1.5,
+                y: 2.25,
+                z: 1.0,
+            }
+        );
+
+        assert_eq!(
+            atom.position,
+            RVec {
+                x: -0.5,
+                y: 4.25,
+                z: 1.0,
+            }
+        );
+    }
+
+    #[test]
+    fn reflect_conf_about_plane() {
+        let content = "\
+A title
+2
+    1RES     A1    1   3.000   1.000   2.000  0.1000  0.2000  0.3000
+    2RES     A1    2   5.500   4.000   6.000
+  10.00000  10.00000  10.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.reflect(Direction::X, 5.0);
+
+        assert_eq!(
+            conf.atoms[0].position,
+            RVec {
+                x: 7.0,
+                y: 1.0,
+                z: 2.0,
+            }
+        );
+        assert_eq!(
+            conf.atoms[0].velocity,
+            Some(RVec {
+                x: -0.1,
+                y: 0.2,
+                z: 0.3,
+            })
+        );
+        assert_eq!(conf.atoms[1].position.x, 4.5);
+        assert_eq!(conf.atoms[1].velocity, None);
+    }
+
+    #[test]
+    fn center_on_atom_near_box_edge() {
+        let content = "\
+A title
+3
+    1RES     A1    1   0.100   0.100   0.100
+    1RES     A2    2   3.900   0.100   0.100
+    2RES     A1    3   2.000   2.000   2.000
+   4.00000   4.00000   4.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.center_on_atom(0);
+
+        let expected = [
+            (2.0, 2.0, 2.0),
+            // The neighbour across the periodic boundary is wrapped next to the atom
+            (1.8, 2.0, 2.0),
+            (3.9, 3.9, 3.9),
+        ];
+
+        for (atom, &(x, y, z)) in conf.atoms.iter().zip(expected.iter()) {
+            assert!(atom.position.distance(&RVec { x, y, z }) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn center_of_mass_of_zero_mass_conf_is_nan() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.000   0.000   0.000
+    2RES     A1    2   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert!(conf.center_of_mass(|_| 0.0).x.is_nan());
+        assert!(conf.principal_axes(|_| 0.0)[0].x.is_nan());
+
+        let empty = conf.select_atoms(|_| false);
+        assert!(empty.center_of_mass(|_| 1.0).x.is_nan());
+    }
+
+    #[test]
+    fn center_box_on_center_of_mass() {
+        let content = "\
+A title
+3
+    1RES    HEA    1   0.100   0.200   0.300
+    1RES     L1    2   0.500   0.200   0.300
+    1RES     L2    3   0.100   0.600   0.300
+   4.00000   4.00000   2.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.origin = RVec {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        let mass_of = |atom: &Atom| if atom.cmp_name("HEA") { 8.0 } else { 1.0 };
+        conf.center_box_on_com(mass_of);
+
+        let expected = RVec {
+            x: 3.0,
+            y: 2.0,
+            z: 1.0,
+        };
+
+        assert!(conf.center_of_mass(mass_of).distance(&expected) < 1e-9);
+        assert!(conf.atoms_outside_box().is_empty());
+    }
+
+    #[test]
+    fn wrap_molecules_keeps_residues_whole() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![
+                Rc::new(RefCell::new("AT1".to_string())),
+                Rc::new(RefCell::new("AT2".to_string())),
+            ],
+        }));
+
+        // The residue straddles the upper x face of the box, with its center outside of it
+        let mut conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec {
+                x: 10.0,
+                y: 10.0,
+                z: 10.0,
+            },
+            residues: vec![residue.clone()],
+            atoms: vec![
+                Atom {
+                    name: residue.borrow().atoms[0].clone(),
+                    residue: residue.clone(),
+                    position: RVec {
+                        x: 9.8,
+                        y: 5.0,
+                        z: 5.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: residue.borrow().atoms[1].clone(),
+                    residue: residue.clone(),
+                    position: RVec {
+                        x: 10.4,
+                        y: 5.0,
+                        z: 5.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        conf.wrap_molecules();
+
+        let (pos1, pos2) = (conf.atoms[0].position, conf.atoms[1].position);
+        assert!((pos1.x - (-0.2)).abs() < 1e-9);
+        assert!((pos2.x - 0.4).abs() < 1e-9);
+        assert_eq!((pos1.y, pos1.z), (5.0, 5.0));
+        assert_eq!((pos2.y, pos2.z), (5.0, 5.0));
+        assert!((pos1.distance(&pos2) - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn make_molecules_split_over_the_box_whole() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.100   1.000   2.950
+    1SOL    HW1    2   2.950   1.000   0.050
+    1SOL    HW2    3   0.050   2.950   0.100
+    2NA      NA    4   2.900   2.900   2.900
+    3SOL     OW    5   0.100   0.100   0.100
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.make_molecules_whole();
+
+        let expected = [
+            RVec {
+                x: 0.1,
+                y: 1.0,
+                z: 2.95,
+            },
+            RVec {
+                x: -0.05,
+                y: 1.0,
+                z: 3.05,
+            },
+            RVec {
+                x: 0.05,
+                y: -0.05,
+                z: 3.1,
+            },
+        ];
+
+        for (atom, position) in conf.atoms.iter().zip(expected.iter()) {
+            assert!(atom.position.distance(position) < 1e-9);
+        }
+
+        assert!(conf.atoms[0].position.distance(&conf.atoms[1].position) < 0.3);
+
+        // Single atoms and incomplete residues are not moved
+        assert_eq!(conf.atoms[3].position.x, 2.9);
+        assert_eq!(conf.atoms[4].position.x, 0.1);
+    }
+
+    #[test]
+    fn wrap_molecules_leaves_incomplete_residues_in_place() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![
+                Rc::new(RefCell::new("AT1".to_string())),
+                Rc::new(RefCell::new("AT2".to_string())),
+            ],
+        }));
+
+        let position = RVec {
+            x: 15.0,
+            y: 5.0,
+            z: 5.0,
+        };
+
+        let mut conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec {
+                x: 10.0,
+                y: 10.0,
+                z: 10.0,
+            },
+            residues: vec![residue.clone()],
+            atoms: vec![Atom {
+                name: residue.borrow().atoms[0].clone(),
+                residue: residue.clone(),
+                position,
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            }],
+        };
+
+        conf.wrap_molecules();
+        assert_eq!(conf.atoms[0].position, position);
+    }
+
+    #[test]
+    fn select_atoms_by_name_in_all_residues() {
+        let content = "\
+A title
+5
+    1RES1   AT1    1   0.000   1.000   2.000
+    1RES1   AT2    2   3.000   4.000   5.000
+    2RES2   AT1    3   6.000   7.000   8.000
+    2RES2   AT3    4   9.000  10.000  11.000
+    3RES1   AT1    5  12.000  13.000  14.000
+  10.00000  11.00000  12.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let atoms = conf.atoms_named("AT1");
+        assert_eq!(atoms.len(), 3);
+
+        let indices = atoms.iter().map(|&(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(indices, vec![0, 2, 4]);
+
+        for &(i, position) in &atoms {
+            assert_eq!(position, conf.atoms[i].position);
+        }
+        assert_eq!(atoms[1].1.x, 6.0);
+
+        assert!(conf.atoms_named("AT4").is_empty());
+    }
+
+    #[test]
+    fn atom_records_yield_owned_names_and_vectors() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT2".to_string()))],
+            })),
+        ];
+
+        let position1 = RVec {
+            x: 0.0,
+            y: 1.0,
+            z: 2.0,
+        };
+        let velocity1 = RVec {
+            x: 0.0,
+            y: 0.1,
+            z: 0.2,
+        };
+        let position2 = RVec {
+            x: 3.0,
+            y: 4.0,
+            z: 5.0,
+        };
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
+            residues: residues.clone(),
+            atoms: vec![
+                Atom {
+                    name: Rc::clone(&residues[1].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[1]),
+                    position: position1,
+                    velocity: Some(velocity1),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: position2,
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let records = conf.atom_records().collect::<Vec<_>>();
+
+        assert_eq!(
+            records,
+            vec![
+                (
+                    "RES2".to_string(),
+                    "AT2".to_string(),
+                    position1,
+                    Some(velocity1),
+                ),
+                ("RES1".to_string(), "AT1".to_string(), position2, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn residue_templates_list_the_residue_and_atom_names() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
+        ];
+
+        let atoms = vec![
+            Atom {
+                name: residues[0].borrow().atoms[0].clone(),
+                residue: residues[0].clone(),
+                position: RVec::default(),
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[0].borrow().atoms[1].clone(),
+                residue: residues[0].clone(),
+                position: RVec::default(),
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec::default(),
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+            Atom {
+                name: residues[1].borrow().atoms[0].clone(),
+                residue: residues[1].clone(),
+                position: RVec::default(),
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            },
+        ];
+
+        let conf = Conf {
+            title: "System".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
+            residues: residues.clone(),
+            atoms,
+        };
+
+        assert_eq!(
+            conf.residue_templates(),
+            vec![
+                (
+                    "RES1".to_string(),
+                    vec!["AT1".to_string(), "AT2".to_string()],
+                ),
+                ("RES2".to_string(), vec!["AT3".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn construct_checked_atom_from_residue_and_name() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+        }));
+        let name = residue.borrow().atoms[0].clone();
+
+        let position = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+
+        let atom = Atom::new_checked(residue.clone(), &name, position, None).unwrap();
+        assert!(Rc::ptr_eq(&atom.name, &name));
+        assert!(Rc::ptr_eq(&atom.residue, &residue));
+        assert_eq!(atom.position, position);
+        assert_eq!(atom.velocity, None);
+    }
+
+    #[test]
+    fn construct_checked_atom_with_name_not_in_residue_is_error() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+        }));
+
+        // Same name but a different object, which is not linked to the residue
+        let name = Rc::new(RefCell::new("AT1".to_string()));
+
+        assert!(Atom::new_checked(residue, &name, RVec::default(), None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn assign_maxwell_velocities_at_a_temperature() {
+        let content = "\
+A title
+2
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+   0.20000   0.20000   0.20000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut conf = Conf::from_template_lattice(&template, 20, 20, 10, template.size);
+        assert_eq!(conf.atoms.len(), 8000);
+
+        let mass_of = |atom: &Atom| if atom.cmp_name("OW") { 16.0 } else { 1.0 };
+        let (temperature, kb) = (300.0, 0.0083144626);
+
+        conf.assign_maxwell_velocities(temperature, kb, mass_of, 1);
+
+        assert!(conf.atoms.iter().all(|atom| atom.velocity.is_some()));
+        assert!(conf.total_momentum(mass_of).norm() < 1e-9);
+        assert!((conf.temperature(mass_of, kb) - temperature).abs() < 0.05 * temperature);
+
+        // The same seed gives the same velocities
+        let mut other = conf.clone();
+        other.assign_maxwell_velocities(temperature, kb, mass_of, 1);
+        assert!(conf
+            .atoms
+            .iter()
+            .zip(other.atoms.iter())
+            .all(|(atom1, atom2)| atom1.velocity == atom2.velocity));
+    }
+
+    #[test]
+    fn total_momentum_is_removed_with_center_of_mass_motion() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+        }));
+
+        let get_atom = |velocity: Option<RVec>| Atom {
+            name: residue.borrow().atoms[0].clone(),
+            residue: residue.clone(),
+            position: RVec::default(),
+            velocity,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
 
-        // An atom with a name of another residue can be added, they will not be the same
-        let (res2_again, atom1_not_res1) =
-            get_or_insert_atom_and_residue(res2_name, atom1_name, &mut residues).unwrap();
+        let velocity = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
 
-        assert!(Rc::ptr_eq(&res2, &res2_again));
-        assert!(!Rc::ptr_eq(&atom1, &atom1_not_res1));
-    }
+        let mut conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
+            residues: vec![residue.clone()],
+            atoms: vec![
+                get_atom(Some(velocity)),
+                get_atom(Some(-velocity)),
+                get_atom(None),
+            ],
+        };
 
-    #[test]
-    fn read_bad_filename_gives_error() {
-        let mut filename = temp_dir();
-        filename.push("_file_should_not_exist_mdio_test_");
+        let mass_of = |_: &Atom| 2.0;
+        assert_eq!(conf.total_momentum(mass_of), RVec::default());
 
-        assert!(Conf::from_gromos87(&filename).is_err());
+        // Add two atoms with a net velocity
+        conf.atoms.push(get_atom(Some(velocity)));
+        conf.atoms.push(get_atom(Some(velocity)));
+        assert_eq!(conf.total_momentum(mass_of), velocity * 4.0);
+
+        conf.remove_center_of_mass_motion(mass_of);
+
+        let momentum = conf.total_momentum(mass_of);
+        assert!(momentum.x.abs() < 1e-9);
+        assert!(momentum.y.abs() < 1e-9);
+        assert!(momentum.z.abs() < 1e-9);
+        assert_eq!(conf.atoms[2].velocity, None);
     }
 
     #[test]
-    fn residue_iter_on_empty_conf_returns_none() {
+    fn load_and_save_dispatch_on_file_extension() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+        }));
+
         let conf = Conf {
             title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
+            origin: RVec::default(),
             size: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
             },
+            residues: vec![residue.clone()],
+            atoms: vec![Atom {
+                name: residue.borrow().atoms[0].clone(),
+                residue: residue.clone(),
+                position: RVec {
+                    x: 0.1,
+                    y: 0.2,
+                    z: 0.3,
+                },
+                velocity: Some(RVec {
+                    x: 0.4,
+                    y: 0.5,
+                    z: 0.6,
+                }),
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            }],
+        };
+
+        for file_name in &["_mdio_test_load.gro", "_mdio_test_load.GRO.gz"] {
+            let mut path = temp_dir();
+            path.push(file_name);
+
+            conf.save(&path).unwrap();
+            let read_conf = Conf::load(&path).unwrap();
+            remove_file(&path).unwrap();
+
+            assert_eq!(read_conf.title, conf.title);
+            assert_eq!(read_conf.size, conf.size);
+            assert_eq!(read_conf.atoms.len(), 1);
+            assert_eq!(read_conf.atoms[0].position, conf.atoms[0].position);
+            assert_eq!(read_conf.atoms[0].velocity, conf.atoms[0].velocity);
+        }
+    }
+
+    #[test]
+    fn count_atoms_of_gromos87_file_from_header() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    2NA      NA    3   0.500   0.500   0.500
+   1.00000   1.00000   1.00000
+";
+
+        let mut path = temp_dir();
+        path.push("_mdio_test_count_atoms.gro");
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.write_gromos87(&path).unwrap();
+
+        let num_atoms = count_atoms_gromos87(&path);
+        let read_conf = Conf::from_gromos87(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(num_atoms.unwrap(), 3);
+        assert_eq!(read_conf.atoms.len(), 3);
+
+        path.set_file_name("_mdio_test_count_atoms_missing.gro");
+        assert!(count_atoms_gromos87(&path).is_err());
+    }
+
+    #[test]
+    fn volume_of_rectangular_and_triclinic_boxes() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   2.00000   3.00000   4.00000
+";
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert_eq!(conf.volume(), 24.0);
+        assert_eq!(box_volume(&conf.box_matrix()), 24.0);
+
+        // Shearing the box vectors along the earlier ones keeps the determinant
+        // as the product of the diagonal
+        let sheared = [[2.0, 0.0, 0.0], [1.0, 3.0, 0.0], [0.5, -1.5, 4.0]];
+        assert!((box_volume(&sheared) - 24.0).abs() < 1e-12);
+
+        let skewed = [[2.0, 1.0, 0.0], [1.0, 3.0, 0.0], [0.0, 0.0, 4.0]];
+        assert!((box_volume(&skewed) - 20.0).abs() < 1e-12);
+
+        // The orientation of the vectors does not matter
+        let mirrored = [[1.0, 3.0, 0.0], [2.0, 1.0, 0.0], [0.0, 0.0, 4.0]];
+        assert!((box_volume(&mirrored) - 20.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn orthorhombic_box_check_and_aspect_ratio() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   2.00000   3.00000   1.00000
+";
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert!(conf.box_is_orthorhombic(0.0));
+        assert_eq!(conf.aspect_ratio(), (1.5, 0.5));
+
+        let sheared = [[2.0, 0.0, 0.0], [0.5, 3.0, 0.0], [0.0, 0.0, 1.0]];
+        assert!(!box_is_orthorhombic(&sheared, 0.0));
+        assert!(!box_is_orthorhombic(&sheared, 0.1));
+        assert!(box_is_orthorhombic(&sheared, 0.5));
+
+        let nearly_orthorhombic = [[2.0, 1e-9, 0.0], [0.0, 3.0, -1e-9], [0.0, 0.0, 1.0]];
+        assert!(!box_is_orthorhombic(&nearly_orthorhombic, 0.0));
+        assert!(box_is_orthorhombic(&nearly_orthorhombic, 1e-6));
+    }
+
+    #[test]
+    fn write_frames_to_numbered_gromos87_files() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let frames = (0..3)
+            .map(|i| {
+                let mut frame = conf.clone();
+                frame.title = format!("Frame {}", i);
+                frame
+            })
+            .collect::<Vec<_>>();
+
+        let dir = temp_dir();
+        let paths = Conf::write_gromos87_series(&frames, &dir, "_mdio_test_series").unwrap();
+
+        let file_names = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            file_names,
+            vec![
+                "_mdio_test_series_00001.gro",
+                "_mdio_test_series_00002.gro",
+                "_mdio_test_series_00003.gro",
+            ]
+        );
+
+        for (path, frame) in paths.iter().zip(frames.iter()) {
+            assert_eq!(path.parent().unwrap(), dir.as_path());
+
+            let read_conf = Conf::from_gromos87(path).unwrap();
+            remove_file(path).unwrap();
+
+            assert_eq!(read_conf.title, frame.title);
+            assert_eq!(read_conf.atoms.len(), 1);
+        }
+    }
+
+    #[test]
+    fn load_and_save_unknown_extension_is_error() {
+        let mut path = temp_dir();
+        path.push("_mdio_test_unknown_format.txt");
+
+        match Conf::load(&path) {
+            Err(ReadError::UnknownFormat(_)) => (),
+            _ => panic!("expected an unknown format error"),
+        }
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
             residues: Vec::new(),
             atoms: Vec::new(),
         };
 
-        let mut iter = conf.iter_residues();
+        match conf.save(&path) {
+            Err(WriteError::UnknownFormat(_)) => (),
+            _ => panic!("expected an unknown format error"),
+        }
+        assert!(!path.exists());
+    }
 
-        assert!(iter.next().is_none());
+    #[test]
+    fn conf_without_a_box_must_have_one_set_to_be_saved_as_gromos87() {
+        let xyz_content = "\
+1
+A comment
+O      0.00000      1.00000      2.00000
+";
+        let pdb_content = "\
+ATOM      1  OW  SOL     1       0.000  10.000  20.000  1.00  0.00
+";
+
+        let mut path = temp_dir();
+        path.push("_mdio_test_save_conf_without_box.gro");
+
+        for mut conf in [
+            Xyz::read(xyz_content.as_bytes()).unwrap(),
+            Pdb::read(pdb_content.as_bytes()).unwrap(),
+        ] {
+            assert_eq!(conf.size, RVec::default());
+
+            match conf.save(&path) {
+                Err(WriteError::Gromos87(gromos87::WriteError::InvalidBox(_))) => (),
+                _ => panic!("expected an invalid box error"),
+            }
+
+            conf.fit_box(RVec {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            });
+            conf.save(&path).unwrap();
+            remove_file(&path).unwrap();
+        }
     }
 
     #[test]
-    fn residue_iter_over_two_atoms_of_different_residues() {
+    fn residue_template_consistency_detects_merged_residues() {
+        let content = "\
+A title
+7
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   0.100   0.000   0.000
+    2RES     A1    3   1.000   0.000   0.000
+    2RES     A2    4   1.100   0.000   0.000
+    3SOL     OW    5   2.000   0.000   0.000
+    4RES     A1    6   3.000   0.000   0.000
+    4RES     B2    7   3.100   0.000   0.000
+   5.00000   5.00000   5.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let err = conf.check_residue_template_consistency().unwrap_err();
+        assert!(err.contains("'RES' starting at atom 5 has atoms [\"A1\"]"));
+        assert!(err.contains("[\"A1\", \"A2\"]"));
+
+        // Without the last residue all residues of the name have the same atoms
+        let consistent = conf.select_atoms(|atom| atom.position.x < 3.0);
+        assert_eq!(consistent.atoms.len(), 5);
+        assert!(consistent.check_residue_template_consistency().is_ok());
+    }
+
+    #[test]
+    fn renumber_atoms_and_residues_from_base_wraps_at_100_000() {
+        let content = "\
+A title
+2
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let spacing = RVec {
+            x: 0.3,
+            y: 0.3,
+            z: 0.3,
+        };
+        let mut conf = Conf::from_template_lattice(&template, 30, 30, 30, spacing);
+        assert_eq!(conf.atoms.len(), 54000);
+
+        conf.renumber_from(99_990, 50_000);
+
+        assert_eq!(conf.atoms[0].atom_number, Some(50_000));
+        assert_eq!(conf.atoms[49_999].atom_number, Some(99_999));
+        assert_eq!(conf.atoms[50_000].atom_number, Some(0));
+        assert_eq!(conf.atoms[53_999].atom_number, Some(3999));
+
+        assert_eq!(conf.atoms[0].residue_number, Some(99_990));
+        assert_eq!(conf.atoms[1].residue_number, Some(99_990));
+        assert_eq!(conf.atoms[19].residue_number, Some(99_999));
+        assert_eq!(conf.atoms[20].residue_number, Some(0));
+        assert_eq!(conf.atoms[53_999].residue_number, Some(26_989));
+
+        // The numbers are used when writing the configuration with the option
+        let options = gromos87::Gromos87WriteOptions {
+            write_stored_numbers: true,
+            ..gromos87::Gromos87WriteOptions::default()
+        };
+
+        let mut buf = Vec::new();
+        gromos87::write_gromos87_conf_with_options(&conf, &mut buf, &options).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines = output.lines().skip(2).collect::<Vec<_>>();
+
+        assert!(lines[0].starts_with("99990SOL     OW50000"));
+        assert!(lines[50_000].starts_with("24990SOL     OW    0"));
+    }
+
+    #[test]
+    fn stored_numbers_of_derived_conf_are_only_written_with_option() {
+        let content = "\
+A title
+2
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        conf.renumber_from(10, 20);
+
+        let multiplied = conf.pbc_multiply(2, 1, 1);
+
+        let write = |conf: &Conf, options: &gromos87::Gromos87WriteOptions| {
+            let mut buf = Vec::new();
+            gromos87::write_gromos87_conf_with_options(conf, &mut buf, options).unwrap();
+
+            String::from_utf8(buf)
+                .unwrap()
+                .lines()
+                .skip(2)
+                .take(4)
+                .map(|line| line[..20].to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // The copies are numbered from 1 by default
+        assert_eq!(
+            write(&multiplied, &gromos87::Gromos87WriteOptions::default()),
+            vec![
+                "    1SOL     OW    1",
+                "    1SOL    HW1    2",
+                "    2SOL     OW    3",
+                "    2SOL    HW1    4",
+            ]
+        );
+
+        let options = gromos87::Gromos87WriteOptions {
+            write_stored_numbers: true,
+            ..gromos87::Gromos87WriteOptions::default()
+        };
+
+        // The stored numbers are copied along with the atoms
+        assert_eq!(
+            write(&multiplied, &options),
+            vec![
+                "   10SOL     OW   20",
+                "   10SOL    HW1   21",
+                "   10SOL     OW   20",
+                "   10SOL    HW1   21",
+            ]
+        );
+
+        let mut renumbered = multiplied.clone();
+        renumbered.renumber_from(10, 20);
+
+        assert_eq!(
+            write(&renumbered, &options),
+            vec![
+                "   10SOL     OW   20",
+                "   10SOL    HW1   21",
+                "   11SOL     OW   22",
+                "   11SOL    HW1   23",
+            ]
+        );
+    }
+
+    #[test]
+    fn residue_atom_consistency_detects_broken_links() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    2NA      NA    3   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        assert!(conf.assert_residue_atom_consistency().is_ok());
+
+        // A name which is equal by value but not shared with the residue
+        let mut mislinked = conf.clone();
+        mislinked.atoms[1].name = Rc::new(RefCell::new("HW1".to_string()));
+        let err = mislinked.assert_residue_atom_consistency().unwrap_err();
+        assert!(err.contains("atom 1"));
+        assert!(err.contains("SOL"));
+
+        // A residue which is missing from the configuration
+        let mut missing = conf.clone();
+        missing.residues.pop();
+        let err = missing.assert_residue_atom_consistency().unwrap_err();
+        assert!(err.contains("atom 2"));
+        assert!(err.contains("NA"));
+    }
+
+    #[test]
+    fn validate_residues_returns_the_first_bad_residue() {
         let residues = vec![
             Rc::new(RefCell::new(Residue {
                 name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
             })),
             Rc::new(RefCell::new(Residue {
                 name: Rc::new(RefCell::new("RES2".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT2".to_string()))],
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
             })),
         ];
 
-        let conf = Conf {
+        let atom1 = Atom {
+            name: Rc::clone(&residues[0].borrow().atoms[0]),
+            residue: Rc::clone(&residues[0]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+        let atom2 = Atom {
+            name: Rc::clone(&residues[0].borrow().atoms[1]),
+            residue: Rc::clone(&residues[0]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+        let atom3 = Atom {
+            name: Rc::clone(&residues[1].borrow().atoms[0]),
+            residue: Rc::clone(&residues[1]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+
+        let mut conf = Conf {
             title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
+            origin: RVec::default(),
+            size: RVec::default(),
             residues: residues.clone(),
-            atoms: vec![
-                // Residue 2
-                Atom {
-                    name: Rc::clone(&residues[1].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[1]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: Some(RVec {
-                        x: 0.0,
-                        y: 0.1,
-                        z: 0.2,
-                    }),
-                },
-                // Residue 1
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 3.0,
-                        y: 4.0,
-                        z: 5.0,
-                    },
-                    velocity: Some(RVec {
-                        x: 0.3,
-                        y: 0.4,
-                        z: 0.5,
-                    }),
-                },
-            ],
+            atoms: vec![atom3.clone(), atom1.clone(), atom2.clone()],
         };
 
-        let mut iter = conf.iter_residues();
+        assert!(conf.validate_residues().is_ok());
 
-        let res = iter.next().unwrap().unwrap();
-        assert_eq!(res.len(), 1);
-        assert!(Rc::ptr_eq(&res[0].residue, &residues[1]));
-        assert!(Rc::ptr_eq(&res[0].name, &residues[1].borrow().atoms[0]));
-        assert_eq!(
-            res[0].position,
-            RVec {
-                x: 0.0,
-                y: 1.0,
-                z: 2.0,
-            }
-        );
+        // The first residue is interleaved with the second
+        conf.atoms = vec![atom3.clone(), atom1.clone(), atom3.clone(), atom2.clone()];
         assert_eq!(
-            res[0].velocity.unwrap(),
-            RVec {
-                x: 0.0,
-                y: 0.1,
-                z: 0.2,
-            }
+            conf.validate_residues(),
+            Err(ResidueError::BadResidue { index: 1 })
         );
+    }
+
+    #[test]
+    fn write_to_and_read_from_gromos87_string() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.100   0.200  1.0000  2.0000  3.0000
+    1SOL    HW1    2   0.100   0.000   0.000  0.0000  0.0000  0.0000
+    2NA      NA    3   1.000   1.000   1.000  0.0000  0.0000  0.0000
+      3.00000      3.00000      3.00000
+";
+
+        let conf = Conf::from_gromos87_str(content).unwrap();
+        let output = conf.to_gromos87_string().unwrap();
+        assert_eq!(output, content);
+
+        let read_conf = Conf::from_gromos87_str(&output).unwrap();
+
+        assert_eq!(read_conf.title, conf.title);
+        assert_eq!(read_conf.origin, conf.origin);
+        assert_eq!(read_conf.size, conf.size);
+        assert_eq!(read_conf.residues.len(), conf.residues.len());
+        assert_eq!(read_conf.atoms.len(), conf.atoms.len());
+
+        for (read_atom, atom) in read_conf.atoms.iter().zip(conf.atoms.iter()) {
+            assert_eq!(*read_atom.name.borrow(), *atom.name.borrow());
+            assert_eq!(
+                *read_atom.residue.borrow().name.borrow(),
+                *atom.residue.borrow().name.borrow()
+            );
+            assert_eq!(read_atom.position, atom.position);
+            assert_eq!(read_atom.velocity, atom.velocity);
+        }
+
+        assert!(Conf::from_gromos87_str("A title\n1\n").is_err());
+    }
+
+    #[test]
+    fn failed_gromos87_write_does_not_overwrite_existing_file() {
+        let residue = Rc::new(RefCell::new(Residue {
+            name: Rc::new(RefCell::new("RES1".to_string())),
+            atoms: vec![
+                Rc::new(RefCell::new("AT1".to_string())),
+                Rc::new(RefCell::new("AT2".to_string())),
+            ],
+        }));
+
+        // The residue is missing its second atom
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            residues: vec![residue.clone()],
+            atoms: vec![Atom {
+                name: Rc::clone(&residue.borrow().atoms[0]),
+                residue: Rc::clone(&residue),
+                position: RVec::default(),
+                velocity: None,
+                charge: None,
+                occupancy: None,
+                b_factor: None,
+                residue_number: None,
+                atom_number: None,
+            }],
+        };
+
+        let mut path = temp_dir();
+        path.push("_mdio_test_failed_write.gro");
+
+        let content = "An existing file\n";
+        ::std::fs::write(&path, content).unwrap();
+
+        match conf.write_gromos87(&path) {
+            Err(WriteError::Gromos87(gromos87::WriteError::BadResidue(1))) => (),
+            result => panic!("expected a bad residue error, got {:?}", result),
+        }
+
+        let read_content = ::std::fs::read_to_string(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_eq!(read_content, content);
+    }
 
-        let res = iter.next().unwrap().unwrap();
-        assert_eq!(res.len(), 1);
-        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
-        assert_eq!(
-            res[0].position,
-            RVec {
-                x: 3.0,
-                y: 4.0,
-                z: 5.0,
-            }
-        );
-        assert_eq!(
-            res[0].velocity.unwrap(),
-            RVec {
-                x: 0.3,
-                y: 0.4,
-                z: 0.5,
+    #[test]
+    fn failed_save_does_not_overwrite_existing_file() {
+        let content = "\
+1
+A comment
+O      0.00000      1.00000      2.00000
+";
+        // Without a box the configuration cannot be written as GROMOS87
+        let conf = Xyz::read(content.as_bytes()).unwrap();
+
+        for extension in &["gro", "gro.gz"] {
+            let mut path = temp_dir();
+            path.push(format!("_mdio_test_failed_save.{}", extension));
+
+            ::std::fs::write(&path, content).unwrap();
+
+            match conf.save(&path) {
+                Err(WriteError::Gromos87(gromos87::WriteError::InvalidBox(_))) => (),
+                result => panic!("expected an invalid box error, got {:?}", result),
             }
-        );
 
-        assert!(iter.next().is_none());
+            let read_content = ::std::fs::read_to_string(&path).unwrap();
+            remove_file(&path).unwrap();
+
+            assert_eq!(read_content, content);
+        }
     }
 
     #[test]
-    fn iterate_over_a_residue_with_several_atoms() {
+    fn translate_single_residue() {
         let residues = vec![
             Rc::new(RefCell::new(Residue {
                 name: Rc::new(RefCell::new("RES1".to_string())),
@@ -482,20 +4970,16 @@ mod tests {
                     Rc::new(RefCell::new("AT2".to_string())),
                 ],
             })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
         ];
 
-        let conf = Conf {
+        let mut conf = Conf {
             title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
+            origin: RVec::default(),
+            size: RVec::default(),
             residues: residues.clone(),
             atoms: vec![
                 Atom {
@@ -507,6 +4991,11 @@ mod tests {
                         z: 2.0,
                     },
                     velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 },
                 Atom {
                     name: Rc::clone(&residues[0].borrow().atoms[1]),
@@ -517,680 +5006,1839 @@ mod tests {
                         z: 5.0,
                     },
                     velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[1].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[1]),
+                    position: RVec {
+                        x: 6.0,
+                        y: 7.0,
+                        z: 8.0,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 },
             ],
         };
 
-        let mut iter = conf.iter_residues();
+        let shift = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
 
-        let res = iter.next().unwrap().unwrap();
-        assert_eq!(res.len(), 2);
+        assert!(conf.translate_residue(1, shift).is_ok());
 
-        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
         assert_eq!(
-            res[0].position,
+            conf.atoms[0].position,
             RVec {
                 x: 0.0,
                 y: 1.0,
                 z: 2.0,
             }
         );
-
-        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
         assert_eq!(
-            res[1].position,
+            conf.atoms[1].position,
             RVec {
                 x: 3.0,
                 y: 4.0,
                 z: 5.0,
             }
         );
+        assert_eq!(
+            conf.atoms[2].position,
+            RVec {
+                x: 7.0,
+                y: 9.0,
+                z: 11.0,
+            }
+        );
 
-        assert!(iter.next().is_none());
+        assert_eq!(
+            conf.translate_residue(2, shift),
+            Err(ResidueError::NoResidue(2))
+        );
     }
 
     #[test]
-    fn iterating_over_residues_ensures_that_all_are_consistent() {
-        let residues = vec![
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![
-                    Rc::new(RefCell::new("AT1".to_string())),
-                    Rc::new(RefCell::new("AT2".to_string())),
-                ],
-            })),
-        ];
+    fn translate_residue_skips_incomplete_residues() {
+        let content = "\
+A title
+4
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   1.000   0.000   0.000
+    2RES     A1    3   2.000   0.000   0.000
+    3ONE     B1    4   3.000   0.000   0.000
+   5.00000   5.00000   5.00000
+";
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
 
-        let conf = Conf {
-            title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            residues: residues.clone(),
-            atoms: vec![
-                // Complete residue
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: None,
-                },
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[1]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 3.0,
-                        y: 4.0,
-                        z: 5.0,
-                    },
-                    velocity: None,
-                },
-                // Incomplete residue: misses second atom
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: None,
-                },
-                // A final complete residue
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 6.0,
-                        y: 7.0,
-                        z: 8.0,
-                    },
-                    velocity: None,
-                },
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[1]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 9.0,
-                        y: 10.0,
-                        z: 11.0,
-                    },
-                    velocity: None,
-                },
-            ],
+        let shift = RVec {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+
+        // The incomplete second residue is not counted
+        assert!(conf.translate_residue(1, shift).is_ok());
+
+        assert_eq!(conf.atoms[2].position.y, 0.0);
+        assert_eq!(conf.atoms[3].position.y, 1.0);
+
+        assert!(conf.translate_residue(2, shift).is_err());
+    }
+
+    #[test]
+    fn rename_residue_type_throughout_conf() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    2SOL     OW    2   1.000   1.000   1.000
+    3NA      NA    3   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert_eq!(conf.rename_residue("SOL", "WAT"), 1);
+        assert_eq!(conf.rename_residue("SOL", "WAT"), 0);
+
+        let mut buf = Vec::new();
+        gromos87::write_gromos87_conf(&conf, &mut buf).unwrap();
+        let written = String::from_utf8(buf).unwrap();
+
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(&lines[2][5..10], "WAT  ");
+        assert_eq!(&lines[3][5..10], "WAT  ");
+        assert_eq!(&lines[4][5..10], "NA   ");
+    }
+
+    #[test]
+    fn rename_atom_only_within_residue_type() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    2SOL     OW    2   1.000   1.000   1.000
+    3OTH     OW    3   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert_eq!(conf.rename_atom("SOL", "OW", "O"), 1);
+        assert_eq!(conf.rename_atom("SOL", "OW", "O"), 0);
+
+        assert!(conf.atoms[0].cmp_name("O"));
+        assert!(conf.atoms[1].cmp_name("O"));
+        assert!(conf.atoms[2].cmp_name("OW"));
+        assert_eq!(&*conf.residues[1].borrow().atoms[0].borrow(), "OW");
+    }
+
+    #[test]
+    fn set_charges_from_function_and_sum_them() {
+        let content = "\
+A title
+4
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert!(conf.atoms.iter().all(|atom| atom.charge.is_none()));
+        assert_eq!(conf.total_charge(), 0.0);
+
+        conf.set_charges(|atom| {
+            if atom.cmp_name("OW") {
+                -0.8
+            } else if atom.cmp_residue_name("SOL") {
+                0.4
+            } else {
+                1.0
+            }
+        });
+
+        assert_eq!(conf.atoms[0].charge, Some(-0.8));
+        assert_eq!(conf.atoms[3].charge, Some(1.0));
+        assert!((conf.total_charge() - 1.0).abs() < 1e-9);
+
+        // The water molecule is neutral by itself
+        conf.atoms[3].charge = None;
+        assert!(conf.total_charge().abs() < 1e-9);
+    }
+
+    #[test]
+    fn deep_clone_shares_no_residues_with_the_original() {
+        let content = "\
+A title
+4
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    2SOL     OW    3   1.000   1.000   1.000
+    2SOL    HW1    4   1.100   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut clone = conf.deep_clone();
+
+        assert_eq!(clone.title, conf.title);
+        assert_eq!(clone.size, conf.size);
+        assert_eq!(clone.residues.len(), 1);
+        assert_eq!(clone.atoms.len(), 4);
+
+        // The grouping of atoms into residues and names is kept in the copy
+        assert!(clone
+            .atoms
+            .iter()
+            .all(|atom| Rc::ptr_eq(&atom.residue, &clone.residues[0])));
+        assert!(Rc::ptr_eq(
+            &clone.atoms[1].name,
+            &clone.residues[0].borrow().atoms[1]
+        ));
+        assert!(Rc::ptr_eq(&clone.atoms[1].name, &clone.atoms[3].name));
+        assert_eq!(clone.atoms[3].position, conf.atoms[3].position);
+
+        clone.rename_residue("SOL", "WAT");
+        clone.atoms[0].name.replace("O".to_string());
+
+        assert!(conf.residues[0].borrow().cmp_name("SOL"));
+        assert!(conf.atoms.iter().all(|atom| atom.cmp_residue_name("SOL")));
+        assert!(conf.atoms[0].cmp_name("OW"));
+
+        assert!(clone.atoms.iter().all(|atom| atom.cmp_residue_name("WAT")));
+        assert!(clone.atoms[2].cmp_name("O"));
+    }
+
+    #[test]
+    fn dipole_moment_of_charge_pair_points_from_negative_to_positive_charge() {
+        let content = "\
+A title
+3
+    1ION     CL    1   1.000   2.000   3.000
+    2ION     NA    2   1.500   2.000   3.000
+    3SOL     OW    3   9.000   9.000   9.000
+  10.00000  10.00000  10.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        assert_eq!(conf.dipole_moment(), RVec::default());
+
+        // The last atom is uncharged and does not contribute
+        conf.atoms[0].charge = Some(-2.0);
+        conf.atoms[1].charge = Some(2.0);
+
+        let dipole = conf.dipole_moment();
+        assert!((dipole.x - 2.0 * 0.5).abs() < 1e-9);
+        assert!(dipole.y.abs() < 1e-9);
+        assert!(dipole.z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn principal_axis_of_linear_molecule_is_along_the_molecule() {
+        let content = "\
+A title
+3
+    1LIN     C1    1   1.000   2.000   3.000
+    1LIN     C2    2   2.000   2.000   3.000
+    1LIN     C3    3   3.000   2.000   3.000
+   5.00000   5.00000   5.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        assert_eq!(
+            conf.center_of_mass(|_| 1.0),
+            RVec {
+                x: 2.0,
+                y: 2.0,
+                z: 3.0,
+            }
+        );
+
+        let tensor = conf.inertia_tensor(|_| 1.0);
+        let expected = [[0.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 2.0]];
+
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((tensor[i][j] - expected[i][j]).abs() < 1e-9);
+            }
+        }
+
+        // The axis with the smallest moment of inertia is along the molecule
+        let axes = conf.principal_axes(|_| 1.0);
+        assert!((axes[0].x.abs() - 1.0).abs() < 1e-9);
+        assert!(axes[0].y.abs() < 1e-9);
+        assert!(axes[0].z.abs() < 1e-9);
+    }
+
+    #[test]
+    fn rmsd_after_alignment_of_rotated_copy_is_zero() {
+        let content = "\
+A title
+4
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   0.300   0.100   0.000
+    1RES     A3    3   0.100   0.500   0.200
+    1RES     A4    4  -0.200   0.100   0.700
+   5.00000   5.00000   5.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mass_of = |atom: &Atom| if atom.cmp_name("A1") { 3.0 } else { 1.0 };
+
+        // Rotate by 60 degrees around the (1, 1, 1) axis and translate
+        let (sin, cos) = (std::f64::consts::PI / 3.0).sin_cos();
+        let axis = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        } / 3.0_f64.sqrt();
+        let shift = RVec {
+            x: 1.0,
+            y: -2.0,
+            z: 0.5,
+        };
+
+        let mut rotated = conf.clone();
+        for atom in rotated.atoms.iter_mut() {
+            let r = atom.position;
+            atom.position =
+                r * cos + axis.cross(&r) * sin + axis * (axis.dot(&r) * (1.0 - cos)) + shift;
+        }
+
+        assert!(conf.msd(&rotated).unwrap() > 1.0);
+        assert!(conf.rmsd_aligned(&rotated, mass_of).unwrap() < 1e-6);
+
+        // A mirror image cannot be superimposed by a rotation
+        let mut mirrored = conf.clone();
+        mirrored
+            .atoms
+            .iter_mut()
+            .for_each(|atom| atom.position.z = -atom.position.z);
+        assert!(conf.rmsd_aligned(&mirrored, mass_of).unwrap() > 1e-2);
+
+        let mut fewer = conf.clone();
+        fewer.atoms.pop();
+        assert!(conf.rmsd_aligned(&fewer, mass_of).is_err());
+        assert!(conf.rmsd_aligned(&rotated, |_| 0.0).is_err());
+    }
+
+    #[test]
+    fn rotate_conf_around_axis_through_center() {
+        let content = "\
+A title
+2
+    1RES     A1    1   2.000   1.000   0.000  1.0000  0.0000  0.0000
+    2RES     A1    2   1.000   1.000   3.000
+   5.00000   5.00000   5.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let axis = RVec {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        };
+        let center = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        conf.rotate(axis, PI / 2.0, center);
+
+        let expected = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 0.0,
+        };
+        assert!(conf.atoms[0].position.distance(&expected) < 1e-9);
+
+        let expected = RVec {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        };
+        assert!(conf.atoms[0].velocity.unwrap().distance(&expected) < 1e-9);
+
+        // The atom on the axis is not moved
+        let expected = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 3.0,
+        };
+        assert!(conf.atoms[1].position.distance(&expected) < 1e-9);
+        assert_eq!(conf.atoms[1].velocity, None);
+    }
+
+    #[test]
+    fn align_best_fit_plane_of_conf_to_target_normal() {
+        // Atoms close to the plane x + y + z = 3, with small deviations from it
+        let content = "\
+A title
+6
+    1RES     A1    1   1.000   1.000   1.010
+    2RES     A1    2   3.000   0.000   0.000
+    3RES     A1    3   0.000   3.000  -0.010
+    4RES     A1    4   0.000   0.000   3.000
+    5RES     A1    5   2.000   2.000  -1.000
+    6RES     A1    6  -1.000   2.000   2.010
+   5.00000   5.00000   5.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let plane_normal = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        } / 3.0f64.sqrt();
+        let normal = conf.best_fit_plane_normal(|_| 1.0);
+        assert!(normal.dot(&plane_normal).abs() > 0.999);
+
+        let target = RVec {
+            x: 0.0,
+            y: 0.0,
+            z: 2.0,
         };
+        let center = conf.center_of_mass(|_| 1.0);
+        conf.align_plane_to(target, |_| 1.0);
+
+        let normal = conf.best_fit_plane_normal(|_| 1.0);
+        assert!(normal.z.abs() > 1.0 - 1e-9);
+
+        // The atoms now lie close to a plane of constant z through the center of mass
+        assert!(conf.center_of_mass(|_| 1.0).distance(&center) < 1e-9);
+        assert!(conf
+            .atoms
+            .iter()
+            .all(|atom| (atom.position.z - center.z).abs() < 0.02));
+    }
+
+    #[test]
+    fn gyration_tensor_and_asphericity_of_symmetric_and_elongated_confs() {
+        let content = "\
+A title
+6
+    1RES     A1    1   1.000   0.000   0.000
+    2RES     A1    2  -1.000   0.000   0.000
+    3RES     A1    3   0.000   1.000   0.000
+    4RES     A1    4   0.000  -1.000   0.000
+    5RES     A1    5   0.000   0.000   1.000
+    6RES     A1    6   0.000   0.000  -1.000
+   5.00000   5.00000   5.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        for (i, row) in conf.gyration_tensor().iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 / 3.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-9);
+            }
+        }
+        assert!(conf.asphericity().abs() < 1e-9);
+
+        // Stretching the configuration along x makes it aspherical
+        conf.atoms[0].position.x = 3.0;
+        conf.atoms[1].position.x = -3.0;
+        assert!((conf.gyration_tensor()[0][0] - 3.0).abs() < 1e-9);
+        assert!((conf.asphericity() - (3.0 - 1.0 / 3.0)).abs() < 1e-9);
+
+        conf.atoms.clear();
+        assert_eq!(conf.gyration_tensor(), [[0.0; 3]; 3]);
+    }
+
+    #[test]
+    fn merge_confs_with_residues_deduplicated_by_name() {
+        let content1 = "\
+First
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+   3.00000   3.00000   3.00000
+";
+        let content2 = "\
+Second
+3
+    1SOL     OW    1   1.000   1.000   1.000
+    1SOL    HW1    2   1.100   1.000   1.000
+    1SOL    HW2    3   1.000   1.100   1.000
+   4.00000   4.00000   4.00000
+";
+
+        let conf1 = gromos87::read_gromos87_conf(&mut content1.as_bytes()).unwrap();
+        let conf2 = gromos87::read_gromos87_conf(&mut content2.as_bytes()).unwrap();
 
-        let mut iter = conf.iter_residues();
+        let merged = conf1.merge_dedup_by_name(&conf2);
 
-        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(merged.title, "First");
+        assert_eq!(merged.size, conf1.size);
+        assert_eq!(merged.residues.len(), 1);
+        assert_eq!(merged.residues[0].borrow().atoms.len(), 3);
+        assert_eq!(merged.atoms.len(), 6);
 
-        // Second gives error
-        assert!(iter.next().unwrap().is_err());
+        assert!(Rc::ptr_eq(
+            &merged.atoms[0].residue,
+            &merged.atoms[3].residue
+        ));
+        assert!(Rc::ptr_eq(&merged.atoms[1].name, &merged.atoms[4].name));
+        assert_eq!(merged.atoms[3].position, conf2.atoms[0].position);
 
-        // Third recovers (TODO: Decide whether this should be the case)
-        let res = iter.next().unwrap().unwrap();
-        assert_eq!(res.len(), 2);
+        let residues = merged.iter_residues().collect::<Vec<_>>();
+        assert_eq!(residues.len(), 2);
+        assert!(residues.iter().all(|residue| residue.is_ok()));
+    }
 
-        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
+    #[test]
+    fn merging_a_mismatching_residue_does_not_modify_the_conf() {
+        let content1 = "\
+First
+2
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+   3.00000   3.00000   3.00000
+";
+        let content2 = "\
+Second
+1
+    1SOL     MW    1   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf1 = gromos87::read_gromos87_conf(&mut content1.as_bytes()).unwrap();
+        let conf2 = gromos87::read_gromos87_conf(&mut content2.as_bytes()).unwrap();
+
+        let merged = conf1.merge_dedup_by_name(&conf2);
+        assert_eq!(merged.residues[0].borrow().atoms.len(), 3);
+        assert_eq!(merged.atoms.len(), 3);
+
+        assert_eq!(conf1.residues.len(), 1);
+        assert_eq!(conf1.residues[0].borrow().atoms.len(), 2);
+        assert!(conf1.iter_residues().all(|residue| residue.is_ok()));
+        assert!(!Rc::ptr_eq(&merged.residues[0], &conf1.residues[0]));
+    }
+
+    #[test]
+    fn residue_names_are_deduplicated_by_value() {
+        let content1 = "\
+First
+4
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+        let content2 = "\
+Second
+2
+    1SOL     OW    1   1.000   1.000   1.000
+    2CL      CL    2   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf1 = gromos87::read_gromos87_conf(&mut content1.as_bytes()).unwrap();
+        let conf2 = gromos87::read_gromos87_conf(&mut content2.as_bytes()).unwrap();
+
+        // Merge without deduplicating to keep two separate residue objects named SOL
+        let mut merged = conf1.clone();
+        merged.residues.extend(conf2.residues.iter().cloned());
+        merged.atoms.extend(conf2.atoms.iter().cloned());
+        assert_eq!(merged.residues.len(), 4);
+
+        assert_eq!(merged.residue_names(), vec!["SOL", "NA", "CL"]);
+    }
+
+    #[test]
+    fn create_lattice_from_template() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   0.100   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let spacing = RVec {
+            x: 2.0,
+            y: 3.0,
+            z: 4.0,
+        };
+        let conf = Conf::from_template_lattice(&template, 2, 2, 2, spacing);
+
+        assert_eq!(conf.atoms.len(), 16);
+        assert_eq!(conf.residues.len(), 1);
         assert_eq!(
-            res[0].position,
+            conf.size,
             RVec {
-                x: 6.0,
-                y: 7.0,
+                x: 4.0,
+                y: 6.0,
                 z: 8.0,
             }
         );
 
-        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
+        assert_eq!(conf.atoms[0].position, RVec::default());
         assert_eq!(
-            res[1].position,
+            conf.atoms[15].position,
             RVec {
-                x: 9.0,
-                y: 10.0,
-                z: 11.0,
+                x: 2.1,
+                y: 3.0,
+                z: 4.0,
             }
         );
+        assert!(conf
+            .atoms
+            .iter()
+            .all(|atom| Rc::ptr_eq(&atom.residue, &template.residues[0])));
+        assert_eq!(conf.iter_residues().filter(|res| res.is_ok()).count(), 8);
+    }
 
-        assert!(iter.next().is_none());
+    #[test]
+    fn detect_spacing_of_lattice_created_from_template() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   0.100   0.200   0.000
+   1.00000   1.00000   1.00000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let spacing = RVec {
+            x: 2.0,
+            y: 3.0,
+            z: 4.0,
+        };
+        let conf = Conf::from_template_lattice(&template, 3, 2, 2, spacing);
+
+        let detected = conf.detect_lattice_spacing(1e-6).unwrap();
+        assert!(detected.distance(&spacing) < 1e-9);
+
+        // A single layer along a direction has no spacing
+        let conf = Conf::from_template_lattice(&template, 3, 1, 2, spacing);
+        let detected = conf.detect_lattice_spacing(1e-6).unwrap();
+        assert!((detected.x - 2.0).abs() < 1e-9);
+        assert_eq!(detected.y, 0.0);
+        assert!((detected.z - 4.0).abs() < 1e-9);
+
+        assert!(template.detect_lattice_spacing(1e-6).is_none());
+        assert!(conf.detect_lattice_spacing(0.0).is_none());
     }
 
     #[test]
-    fn iterating_over_residues_ensures_that_they_are_ordered() {
-        let residues = vec![
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![
-                    Rc::new(RefCell::new("AT1".to_string())),
-                    Rc::new(RefCell::new("AT2".to_string())),
-                ],
-            })),
-        ];
+    fn detect_no_lattice_spacing_for_random_positions() {
+        let mut content = String::from("A title\n20\n");
 
-        let conf = Conf {
-            title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            residues: residues.clone(),
-            atoms: vec![
-                // Residue begins with wrong atom, and skipped
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[1]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: None,
-                },
-                // This residue (which along with the previous atom is a good residue)
-                // is found as incomplete and skipped
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: None,
-                },
-                // The next residue is good
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 6.0,
-                        y: 7.0,
-                        z: 8.0,
-                    },
-                    velocity: None,
-                },
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[1]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 9.0,
-                        y: 10.0,
-                        z: 11.0,
-                    },
-                    velocity: None,
-                },
-            ],
+        for i in 0..20 {
+            let t = i as f64;
+            content.push_str(&format!(
+                "{:>5}{:<5}{:>5}{:>5}{:>8.3}{:>8.3}{:>8.3}\n",
+                i + 1,
+                "RES",
+                "A1",
+                i + 1,
+                (t * 1.37).sin() * 3.0 + 3.0,
+                (t * 2.11).cos() * 3.0 + 3.0,
+                (t * 0.73).sin() * 3.0 + 3.0
+            ));
+        }
+        content.push_str("   6.00000   6.00000   6.00000\n");
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        assert_eq!(conf.atoms.len(), 20);
+        assert!(conf.detect_lattice_spacing(1e-3).is_none());
+    }
+
+    #[test]
+    fn detect_lattice_spacing_with_nan_positions_does_not_panic() {
+        let content = "\
+A title
+1
+    1RES     A1    1   0.000   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let spacing = RVec {
+            x: 2.0,
+            y: 3.0,
+            z: 4.0,
         };
+        let mut conf = Conf::from_template_lattice(&template, 3, 2, 2, spacing);
 
-        let mut iter = conf.iter_residues();
+        // An atom with a NaN position breaks the lattice
+        conf.atoms[5].position.y = f64::NAN;
+        assert_ne!(conf.detect_lattice_spacing(1e-6), Some(spacing));
 
-        // First and second residues will be bad (both are incomplete)
-        assert!(iter.next().unwrap().is_err());
-        assert!(iter.next().unwrap().is_err());
+        // No candidate spacings are measured from a first atom with a NaN position
+        conf.atoms[0].position.x = f64::NAN;
+        assert!(conf.detect_lattice_spacing(1e-6).is_none());
+    }
 
-        // This is good
-        let res = iter.next().unwrap().unwrap();
-        assert_eq!(res.len(), 2);
+    #[test]
+    fn rmsd_by_residue_name_matches_reordered_residues() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    2NA      NA    3   1.000   1.000   1.000
+    3SOL     OW    4   2.000   0.000   0.000
+    3SOL    HW1    5   2.100   0.000   0.000
+   3.00000   3.00000   3.00000
+";
 
-        assert!(Rc::ptr_eq(&res[0].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[0].name, &residues[0].borrow().atoms[0]));
-        assert_eq!(
-            res[0].position,
-            RVec {
-                x: 6.0,
-                y: 7.0,
-                z: 8.0,
-            }
-        );
+        // The same residues translated by (0.3, 0.4, 0.0), with the ion placed first
+        let translated = "\
+A title
+5
+    1NA      NA    1   1.300   1.400   1.000
+    2SOL     OW    2   0.300   0.400   0.000
+    2SOL    HW1    3   0.400   0.400   0.000
+    3SOL     OW    4   2.300   0.400   0.000
+    3SOL    HW1    5   2.400   0.400   0.000
+   3.00000   3.00000   3.00000
+";
 
-        assert!(Rc::ptr_eq(&res[1].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res[1].name, &residues[0].borrow().atoms[1]));
-        assert_eq!(
-            res[1].position,
-            RVec {
-                x: 9.0,
-                y: 10.0,
-                z: 11.0,
-            }
-        );
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let other = gromos87::read_gromos87_conf(&mut translated.as_bytes()).unwrap();
 
-        assert!(iter.next().is_none());
+        let rmsd = conf.rmsd_by_residue_name(&other).unwrap();
+        assert!((rmsd - 0.5).abs() < 1e-6);
+        assert!(conf.rmsd_by_residue_name(&conf).unwrap().abs() < 1e-9);
+
+        // Residue sequences which differ cannot be matched
+        let cropped = other.select_atoms(|atom| atom.residue.borrow().cmp_name("SOL"));
+        assert!(conf.rmsd_by_residue_name(&cropped).is_err());
+        assert!(cropped.rmsd_by_residue_name(&conf).is_err());
     }
 
     #[test]
-    fn iterate_over_several_different_residues() {
-        let residues = vec![
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![
-                    Rc::new(RefCell::new("AT1".to_string())),
-                    Rc::new(RefCell::new("At2".to_string())),
-                ],
-            })),
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES2".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
-            })),
-        ];
+    fn msd_of_uniformly_translated_frame() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+   3.00000   3.00000   3.00000
+";
 
-        // This configuration contains 2 of the first residue, then 2 of the second,
-        // and finally 1 of the first
-        let atoms = vec![
-            Atom {
-                name: residues[0].borrow().atoms[0].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 0.0,
-                    y: 1.0,
-                    z: 2.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[1].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 3.0,
-                    y: 4.0,
-                    z: 5.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[0].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 6.0,
-                    y: 7.0,
-                    z: 8.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[1].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 9.0,
-                    y: 10.0,
-                    z: 11.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[1].borrow().atoms[0].clone(),
-                residue: residues[1].clone(),
-                position: RVec {
-                    x: 12.0,
-                    y: 13.0,
-                    z: 14.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[1].borrow().atoms[0].clone(),
-                residue: residues[1].clone(),
-                position: RVec {
-                    x: 15.0,
-                    y: 16.0,
-                    z: 17.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[0].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 18.0,
-                    y: 19.0,
-                    z: 20.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[1].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 21.0,
-                    y: 22.0,
-                    z: 23.0,
-                },
-                velocity: None,
-            },
-        ];
+        let frame1 = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut frame2 = frame1.clone();
+
+        let shift = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 2.5,
+        };
+        frame2
+            .atoms
+            .iter_mut()
+            .for_each(|atom| atom.position += shift);
 
-        let conf = Conf {
-            title: "System".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 1.0,
-                y: 2.0,
-                z: 3.0,
-            },
-            residues: residues.clone(),
-            atoms,
+        let shift2 = shift.x.powi(2) + shift.y.powi(2) + shift.z.powi(2);
+        assert!((frame1.msd(&frame2).unwrap() - shift2).abs() < 1e-9);
+
+        // In the periodic box the shortest displacement is smaller
+        let msd_pbc = frame1.msd_pbc(&frame2, frame1.size).unwrap();
+        let shift_pbc2 = 1.0f64.powi(2) + 1.0f64.powi(2) + 0.5f64.powi(2);
+        assert!((msd_pbc - shift_pbc2).abs() < 1e-9);
+
+        frame2.atoms.pop();
+        assert!(frame1.msd(&frame2).is_err());
+        assert!(frame1.msd_pbc(&frame2, frame1.size).is_err());
+    }
+
+    #[test]
+    fn find_nearest_atom_to_point() {
+        let content = "\
+A title
+3
+    1RES     A1    1   0.500   2.000   2.000
+    2RES     A1    2   1.000   1.000   1.000
+    3RES     A1    3   2.800   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let point = RVec {
+            x: 1.1,
+            y: 1.0,
+            z: 1.0,
         };
+        let (i, dr) = conf.nearest_atom(point).unwrap();
+        assert_eq!(i, 1);
+        assert!((dr - 0.1).abs() < 1e-9);
 
-        let mut iter = conf.iter_residues();
+        // Across the periodic boundary the last atom is closer
+        let point = RVec {
+            x: 0.1,
+            y: 1.0,
+            z: 1.0,
+        };
+        assert_eq!(conf.nearest_atom(point).unwrap().0, 1);
 
-        // Check the fourth and fifth (final) residues
-        assert!(iter.next().unwrap().is_ok());
-        assert!(iter.next().unwrap().is_ok());
-        assert!(iter.next().unwrap().is_ok());
+        let (i, dr) = conf.nearest_atom_pbc(point).unwrap();
+        assert_eq!(i, 2);
+        assert!((dr - 0.3).abs() < 1e-9);
+
+        let empty = Conf {
+            title: "Empty".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
+            residues: Vec::new(),
+            atoms: Vec::new(),
+        };
+        assert!(empty.nearest_atom(point).is_none());
+        assert!(empty.nearest_atom_pbc(point).is_none());
+    }
+
+    #[test]
+    fn bonds_within_cutoff_are_found_between_adjacent_atoms_of_a_chain() {
+        let content = "\
+A title
+4
+    1RES     A1    1   0.000   0.000   0.000
+    1RES     A2    2   1.500   0.000   0.000
+    1RES     A3    3   3.000   0.000   0.000
+    1RES     A4    4   4.500   0.000   0.000
+   5.00000   5.00000   5.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let bonds = conf.bonds_within(1.6);
+        let pairs = bonds.iter().map(|&(i, j, _)| (i, j)).collect::<Vec<_>>();
+
+        assert_eq!(pairs, vec![(0, 1), (1, 2), (2, 3)]);
+        assert!(bonds
+            .iter()
+            .all(|&(_, _, distance)| (distance - 1.5).abs() < 1e-9));
+
+        assert!(conf.bonds_within(1.4).is_empty());
+        assert_eq!(conf.bonds_within(3.1).len(), 5);
+    }
+
+    #[test]
+    fn count_solvent_residues_in_shell_around_solute() {
+        let content = "\
+A title
+11
+    1PRO     C1    1   2.000   2.000   2.000
+    1PRO     C2    2   2.200   2.000   2.000
+    2SOL     OW    3   2.500   2.000   2.000
+    2SOL    HW1    4   2.600   2.000   2.000
+    3SOL     OW    5   2.000   2.700   2.000
+    3SOL    HW1    6   2.000   2.400   2.000
+    4SOL     OW    7   2.000   2.000   1.000
+    4SOL    HW1    8   2.000   2.000   0.900
+    5NA      NA    9   2.000   1.800   2.000
+    6SOL     OW   10   2.000   2.000   0.100
+    6SOL    HW1   11   2.000   2.000   0.200
+   4.00000   4.00000   2.20000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let solute = ["PRO".to_string()].iter().cloned().collect::<HashSet<_>>();
+
+        // The first two solvent residues are 0.3 and 0.4 from the solute, the next
+        // 1.0 and the last is 0.3 away across the periodic boundary
+        assert_eq!(conf.solvation_count(&solute, "SOL", 0.35), 2);
+        assert_eq!(conf.solvation_count(&solute, "SOL", 0.5), 3);
+        assert_eq!(conf.solvation_count(&solute, "SOL", 1.5), 4);
+        assert_eq!(conf.solvation_count(&solute, "NA", 0.5), 1);
+        assert_eq!(conf.solvation_count(&solute, "SOL", 0.05), 0);
+    }
+
+    #[test]
+    fn count_hydrogen_bonds_of_water_dimer() {
+        // The first hydrogen of the first water points straight at the oxygen
+        // of the second water, whose hydrogens point away from the first
+        let content = "\
+Water dimer
+6
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.096   0.000   0.000
+    1SOL    HW2    3  -0.024   0.093   0.000
+    2SOL     OW    4   0.290   0.000   0.000
+    2SOL    HW1    5   0.320   0.090   0.000
+    2SOL    HW2    6   0.320  -0.090   0.000
+   2.00000   2.00000   2.00000
+";
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let oxygens: HashSet<String> = names(&["OW"]);
+        let hydrogens: HashSet<String> = names(&["HW1", "HW2"]);
+
+        let angle_cutoff = 150.0 * PI / 180.0;
 
-        let res4 = iter.next().unwrap().unwrap();
-        assert_eq!(res4.len(), 1);
-        assert!(Rc::ptr_eq(&res4[0].residue, &residues[1]));
-        assert!(Rc::ptr_eq(&res4[0].name, &residues[1].borrow().atoms[0]));
         assert_eq!(
-            res4[0].position,
-            RVec {
-                x: 15.0,
-                y: 16.0,
-                z: 17.0,
-            }
+            conf.count_hbonds(&oxygens, &oxygens, &hydrogens, 0.35, angle_cutoff),
+            1
         );
-        assert_eq!(res4[0].velocity, None);
+        assert_eq!(
+            conf.count_hbonds(&oxygens, &oxygens, &hydrogens, 0.25, angle_cutoff),
+            0
+        );
+        assert_eq!(
+            conf.count_hbonds(&oxygens, &HashSet::new(), &hydrogens, 0.35, angle_cutoff),
+            0
+        );
+    }
 
-        let res5 = iter.next().unwrap().unwrap();
-        assert_eq!(res5.len(), 2);
+    #[test]
+    fn count_contacts_between_two_groups_of_residues() {
+        let content = "\
+A title
+7
+    1PRA     C1    1   0.000   0.000   0.000
+    1PRA     C2    2   0.000   1.000   0.000
+    2PRB     N1    3   0.300   0.000   0.000
+    2PRB     N2    4   0.300   1.000   0.000
+    3PRB     N1    5   5.000   0.000   0.000
+    3PRB     N2    6   0.300   0.200   0.000
+    4SOL     OW    7   0.100   0.000   0.000
+   6.00000   6.00000   6.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        // C1-N1 and C2-N2 of the first PRB and C1-N2 of the second PRB, but not the water
+        assert_eq!(conf.contacts_between("PRA", "PRB", 0.4), 3);
+        assert_eq!(conf.contacts_between("PRB", "PRA", 0.4), 3);
+        assert_eq!(conf.contacts_between("PRA", "PRB", 0.25), 0);
+        assert_eq!(conf.contacts_between("PRA", "SOL", 0.4), 1);
+        assert_eq!(conf.contacts_between("PRA", "NONE", 10.0), 0);
+
+        // Only N1 of the first and N2 of the second PRB residue are close
+        assert_eq!(conf.contacts_between("PRB", "PRB", 0.4), 1);
+    }
+
+    #[test]
+    fn fit_box_around_atoms_with_padding() {
+        let content = "\
+A title
+3
+    1RES     A1    1  -1.000   2.000   0.500
+    2RES     A1    2   1.000  -3.000   1.500
+    3RES     A1    3   0.000   0.000  -0.500
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let padding = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+
+        conf.fit_box(padding);
 
-        assert!(Rc::ptr_eq(&res5[0].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res5[0].name, &residues[0].borrow().atoms[0]));
         assert_eq!(
-            res5[0].position,
+            conf.origin,
             RVec {
-                x: 18.0,
-                y: 19.0,
-                z: 20.0,
+                x: -2.0,
+                y: -4.0,
+                z: -1.5,
             }
         );
-        assert_eq!(res5[0].velocity, None);
+        assert_eq!(
+            conf.size,
+            RVec {
+                x: 4.0,
+                y: 7.0,
+                z: 4.0,
+            }
+        );
+        assert!(conf.atoms_outside_box().is_empty());
 
-        assert!(Rc::ptr_eq(&res5[1].residue, &residues[0]));
-        assert!(Rc::ptr_eq(&res5[1].name, &residues[0].borrow().atoms[1]));
+        // The atoms are not moved
         assert_eq!(
-            res5[1].position,
+            conf.atoms[0].position,
             RVec {
-                x: 21.0,
-                y: 22.0,
-                z: 23.0,
+                x: -1.0,
+                y: 2.0,
+                z: 0.5,
             }
         );
-        assert_eq!(res5[1].velocity, None);
 
-        assert!(iter.next().is_none());
+        let mut empty = Conf {
+            atoms: Vec::new(),
+            ..conf.clone()
+        };
+        empty.fit_box(RVec::default());
+        assert_eq!(empty.origin, conf.origin);
+        assert_eq!(empty.size, conf.size);
     }
 
     #[test]
-    fn multiply_conf_to_extend_it() {
-        let size = RVec {
-            x: 10.0,
-            y: 20.0,
-            z: 30.0,
+    fn recenter_conf_to_origin_by_its_bounding_box() {
+        let content = "\
+A title
+3
+    1RES     A1    1  -1.000   2.000   0.500
+    2RES     A1    2   1.000  -3.000   1.500
+    3RES     A1    3   0.000   0.000  -0.500
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let (min, max) = conf.bounding_box().unwrap();
+        assert_eq!(
+            min,
+            RVec {
+                x: -1.0,
+                y: -3.0,
+                z: -0.5,
+            }
+        );
+        assert_eq!(
+            max,
+            RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 1.5,
+            }
+        );
+
+        conf.recenter_to_origin();
+
+        assert_eq!(conf.bounding_box().unwrap().0, RVec::default());
+        assert_eq!(
+            conf.atoms[0].position,
+            RVec {
+                x: 0.0,
+                y: 5.0,
+                z: 1.0,
+            }
+        );
+        assert_eq!(
+            conf.size,
+            RVec {
+                x: 3.0,
+                y: 3.0,
+                z: 3.0,
+            }
+        );
+
+        let mut empty = Conf {
+            title: "Empty".to_string(),
+            origin: RVec::default(),
+            size: RVec::default(),
+            residues: Vec::new(),
+            atoms: Vec::new(),
         };
+        assert!(empty.bounding_box().is_none());
+        empty.recenter_to_origin();
+    }
 
+    #[test]
+    fn iterate_over_valid_residues_skips_bad_residues() {
         let residues = vec![
             Rc::new(RefCell::new(Residue {
                 name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT1".to_string()))],
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
             })),
             Rc::new(RefCell::new(Residue {
                 name: Rc::new(RefCell::new("RES2".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT2".to_string()))],
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
             })),
         ];
 
+        let atom1 = Atom {
+            name: Rc::clone(&residues[0].borrow().atoms[0]),
+            residue: Rc::clone(&residues[0]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+        let atom2 = Atom {
+            name: Rc::clone(&residues[0].borrow().atoms[1]),
+            residue: Rc::clone(&residues[0]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+        let atom3 = Atom {
+            name: Rc::clone(&residues[1].borrow().atoms[0]),
+            residue: Rc::clone(&residues[1]),
+            position: RVec::default(),
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+
+        // The first residue is interleaved with the second and then complete
         let conf = Conf {
             title: "A title".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size,
+            origin: RVec::default(),
+            size: RVec::default(),
             residues: residues.clone(),
             atoms: vec![
-                Atom {
-                    name: Rc::clone(&residues[1].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[1]),
-                    position: RVec {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 2.0,
-                    },
-                    velocity: Some(RVec {
-                        x: 0.0,
-                        y: 0.1,
-                        z: 0.2,
-                    }),
-                },
-                Atom {
-                    name: Rc::clone(&residues[0].borrow().atoms[0]),
-                    residue: Rc::clone(&residues[0]),
-                    position: RVec {
-                        x: 3.0,
-                        y: 4.0,
-                        z: 5.0,
-                    },
-                    velocity: Some(RVec {
-                        x: 0.3,
-                        y: 0.4,
-                        z: 0.5,
-                    }),
-                },
+                atom1.clone(),
+                atom3.clone(),
+                atom2.clone(),
+                atom1.clone(),
+                atom2.clone(),
             ],
         };
 
-        let (nx, ny, nz) = (2, 3, 4);
-        let multiplied_conf = conf.pbc_multiply(nx, ny, nz);
+        let valid_residues = conf.iter_valid_residues().collect::<Vec<_>>();
+
+        assert_eq!(conf.iter_residues().count(), 4);
+        assert_eq!(valid_residues.len(), 2);
+        assert!(Rc::ptr_eq(&valid_residues[0][0].residue, &residues[1]));
+        assert!(Rc::ptr_eq(&valid_residues[1][0].residue, &residues[0]));
+        assert_eq!(valid_residues[1].len(), 2);
+    }
+
+    #[test]
+    fn residue_extent_gives_min_and_max_atom_distances() {
+        let content = "\
+A title
+6
+    1TWO     A1    1   0.000   0.000   0.000
+    1TWO     A2    2   0.300   0.400   0.000
+    2ONE     B1    3   1.000   1.000   1.000
+    3THR     C1    4   0.000   0.000   0.000
+    3THR     C2    5   0.100   0.000   0.000
+    3THR     C3    6   0.000   0.500   0.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let extent = conf.residue_extent();
+
+        assert_eq!(extent.len(), 3);
+
+        assert_eq!(extent[0].0, 0);
+        assert!((extent[0].1 - 0.5).abs() < 1e-9);
+        assert!((extent[0].2 - 0.5).abs() < 1e-9);
+
+        assert_eq!(extent[1], (1, 0.0, 0.0));
+
+        assert_eq!(extent[2].0, 2);
+        assert!((extent[2].1 - 0.1).abs() < 1e-9);
+        assert!((extent[2].2 - 0.26f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn residue_centers_are_the_mean_of_atom_positions() {
+        let content = "\
+A title
+4
+    1TWO     A1    1   0.000   0.000   0.000
+    1TWO     A2    2   0.300   0.400   1.000
+    2ONE     B1    3   1.000   1.000   1.000
+    3TWO     A1    4   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let centers = conf.residue_centers();
+
+        // The last residue is incomplete
+        assert_eq!(centers.len(), 2);
+
+        assert!(Rc::ptr_eq(&centers[0].0, &conf.atoms[0].residue));
+        assert!(
+            centers[0].1.distance(&RVec {
+                x: 0.15,
+                y: 0.2,
+                z: 0.5,
+            }) < 1e-9
+        );
+
+        assert!(Rc::ptr_eq(&centers[1].0, &conf.atoms[2].residue));
+        assert_eq!(centers[1].1, conf.atoms[2].position);
+    }
+
+    #[test]
+    fn residue_velocities_are_the_sum_of_atom_velocities() {
+        let content = "\
+A title
+5
+    1TWO     A1    1   0.000   0.000   0.000  1.0000  2.0000 -3.0000
+    1TWO     A2    2   0.300   0.400   0.000  0.5000 -2.0000  1.0000
+    2ONE     B1    3   1.000   1.000   1.000
+    3TWO     A1    4   0.000   0.000   0.000  1.0000  0.0000  0.0000
+    3TWO     A2    5   0.100   0.000   0.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let velocities = conf.residue_velocities();
+
+        assert_eq!(velocities.len(), 3);
+
+        assert_eq!(
+            velocities[0],
+            (
+                0,
+                Some(RVec {
+                    x: 1.5,
+                    y: 0.0,
+                    z: -2.0,
+                })
+            )
+        );
+
+        // Residues with any atom which has no velocity have no summed velocity
+        assert_eq!(velocities[1], (1, None));
+        assert_eq!(velocities[2], (2, None));
+    }
+
+    #[test]
+    fn coarse_grain_residues_into_single_beads() {
+        let content = "\
+A title
+8
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.300   0.000   0.000
+    1SOL    HW2    3   0.000   0.300   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3SOL     OW    5   2.000   2.000   2.000
+    3SOL    HW1    6   2.300   2.000   2.000
+    3SOL    HW2    7   2.000   2.300   2.000
+    4SOL     OW    8   1.500   1.500   1.500
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let coarse = conf.coarse_grain();
+
+        // The last water molecule is incomplete
+        assert_eq!(coarse.atoms.len(), conf.iter_valid_residues().count());
+        assert_eq!(coarse.atoms.len(), 3);
+        assert_eq!(coarse.title, conf.title);
+        assert_eq!(coarse.size, conf.size);
+
+        assert_eq!(coarse.residues.len(), 2);
+        assert!(coarse.atoms[0].cmp_name("SOL"));
+        assert!(coarse.atoms[1].cmp_name("NA"));
+        assert!(Rc::ptr_eq(
+            &coarse.atoms[0].residue,
+            &coarse.atoms[2].residue
+        ));
+        assert!(!Rc::ptr_eq(
+            &coarse.atoms[0].residue,
+            &conf.atoms[0].residue
+        ));
+        assert!(coarse.iter_residues().all(|residue| residue.is_ok()));
+
+        assert!(
+            coarse.atoms[2].position.distance(&RVec {
+                x: 2.1,
+                y: 2.1,
+                z: 2.0,
+            }) < 1e-9
+        );
+    }
+
+    #[test]
+    fn find_atoms_outside_of_the_box() {
+        let content = "\
+A title
+5
+    1RES     A1    1   0.000   0.000   0.000
+    2RES     A1    2   1.000   2.000   2.900
+    3RES     A1    3   1.000   3.000   1.000
+    4RES     A1    4   2.900   2.900   2.900
+    5RES     A1    5  -0.100   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        assert_eq!(conf.atoms_outside_box(), vec![2, 4]);
+
+        // Dimensions of zero size are not checked
+        conf.size.x = 0.0;
+        assert_eq!(conf.atoms_outside_box(), vec![2]);
+
+        conf.origin.y = 1.0;
+        assert_eq!(conf.atoms_outside_box(), vec![0]);
+    }
+
+    #[test]
+    fn count_atoms_by_guessed_element() {
+        let content = "\
+A title
+8
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3CL      CL    5   2.000   2.000   2.000
+    4DUM     XX    6   0.000   0.000   0.000
+    5ILE     CD    7   0.000   0.000   0.000
+    5ILE    HG1    8   0.000   0.000   0.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let composition = conf.element_composition();
+
+        assert_eq!(composition.len(), 6);
+        assert_eq!(composition["O"], 1);
+        assert_eq!(composition["H"], 3);
+        assert_eq!(composition["C"], 1);
+        assert_eq!(composition["Na"], 1);
+        assert_eq!(composition["Cl"], 1);
+        assert_eq!(composition["?"], 1);
+    }
+
+    #[test]
+    fn temperature_from_atom_velocities() {
+        let content = "\
+A title
+3
+    1RES     A1    1   0.000   0.000   0.000  1.0000  0.0000  0.0000
+    2RES     A1    2   1.000   1.000   1.000  0.0000  0.0000 -1.0000
+    3RES     A1    3   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        // Kinetic energy is 2 * 0.5 * m * v^2 with N = 2 atoms
+        let mass = 2.0;
+        let kb = 0.5;
+        let expected = 2.0 * (mass * 1.0f64.powi(2)) / (3.0 * 2.0 * kb);
+
+        assert!((conf.temperature(|_| mass, kb) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn conf_from_filtered_atoms_collects_their_residues() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3CL      CL    5   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let atoms = conf
+            .atoms
+            .iter()
+            .filter(|atom| !atom.cmp_residue_name("NA"))
+            .cloned();
+        let filtered = Conf::from_atoms("Without ions".to_string(), conf.size, atoms);
+
+        assert_eq!(filtered.title, "Without ions");
+        assert_eq!(filtered.size, conf.size);
+        assert_eq!(filtered.origin, RVec::default());
+        assert_eq!(filtered.atoms.len(), 4);
+
+        assert_eq!(filtered.residues.len(), 2);
+        assert!(Rc::ptr_eq(&filtered.residues[0], &conf.residues[0]));
+        assert!(Rc::ptr_eq(&filtered.residues[1], &conf.residues[2]));
+        assert!(filtered.iter_residues().all(|residue| residue.is_ok()));
+
+        let empty = Conf::from_atoms(String::new(), RVec::default(), Vec::new());
+        assert!(empty.atoms.is_empty());
+        assert!(empty.residues.is_empty());
+    }
+
+    #[test]
+    fn remove_atoms_by_index_and_prune_their_residues() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3CL      CL    5   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let indices = [1, 3, 10].iter().cloned().collect::<HashSet<_>>();
+        conf.remove_atoms(&indices);
+
+        assert_eq!(conf.atoms.len(), 3);
+        assert!(conf.atoms[0].cmp_name("OW"));
+        assert!(conf.atoms[1].cmp_name("HW2"));
+        assert!(conf.atoms[2].cmp_name("CL"));
+
+        // The ion residue is pruned, while the incomplete water residue is kept
+        assert_eq!(conf.residues.len(), 2);
+        assert!(conf.residues[0].borrow().cmp_name("SOL"));
+        assert!(conf.residues[1].borrow().cmp_name("CL"));
+        assert!(conf.iter_residues().any(|residue| residue.is_err()));
+
+        conf.remove_atoms(&HashSet::new());
+        assert_eq!(conf.atoms.len(), 3);
+    }
+
+    #[test]
+    fn crop_conf_to_a_region_and_shift_it_to_the_origin() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.500   0.500   0.500
+    1SOL    HW1    2   1.500   0.500   0.500
+    1SOL    HW2    3   0.500   1.500   0.500
+    2NA      NA    4   1.000   1.000   1.000
+    3CL      CL    5   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let lower = RVec {
+            x: 0.5,
+            y: 0.0,
+            z: 0.0,
+        };
+        let upper = RVec {
+            x: 2.0,
+            y: 1.0,
+            z: 1.5,
+        };
+        let cropped = conf.crop(lower, upper);
+
+        // The lower bound is inclusive and the upper bound exclusive
+        assert_eq!(cropped.atoms.len(), 2);
+        assert!(cropped.atoms[0].cmp_name("OW"));
+        assert!(cropped.atoms[1].cmp_name("HW1"));
+        assert_eq!(cropped.residues.len(), 1);
 
+        assert_eq!(cropped.origin, RVec::default());
         assert_eq!(
-            multiplied_conf.size,
+            cropped.size,
             RVec {
-                x: 10.0 * (nx as f64),
-                y: 20.0 * (ny as f64),
-                z: 30.0 * (nz as f64),
+                x: 1.5,
+                y: 1.0,
+                z: 1.5,
+            }
+        );
+        assert_eq!(
+            cropped.atoms[1].position,
+            RVec {
+                x: 1.0,
+                y: 0.5,
+                z: 0.5,
             }
         );
-        assert_eq!(multiplied_conf.atoms.len(), conf.atoms.len() * nx * ny * nz);
 
-        // The final atom should be from the maximum (nx, ny, nz) image
-        assert!(Rc::ptr_eq(
-            &multiplied_conf.atoms.last().unwrap().name,
-            &conf.atoms.last().unwrap().name
-        ));
-        assert!(Rc::ptr_eq(
-            &multiplied_conf.atoms.last().unwrap().residue,
-            &conf.atoms.last().unwrap().residue
-        ));
+        let selected = conf.select_atoms(|atom| atom.cmp_residue_name("SOL"));
+        assert_eq!(selected.atoms.len(), 3);
+        assert_eq!(selected.residues.len(), 1);
+        assert_eq!(selected.size, conf.size);
+    }
+
+    #[test]
+    fn stride_atoms_keeps_every_nth_atom() {
+        let content = "\
+A title
+5
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3CL      CL    5   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let strided = conf.stride_atoms(2);
+        assert_eq!(strided.atoms.len(), 3);
+        assert!(strided.atoms[0].cmp_name("OW"));
+        assert!(strided.atoms[1].cmp_name("HW2"));
+        assert!(strided.atoms[2].cmp_name("CL"));
+
+        // The residue of the dropped ion is pruned
+        assert_eq!(strided.residues.len(), 2);
+        assert!(strided.residues[0].borrow().cmp_name("SOL"));
+        assert!(strided.residues[1].borrow().cmp_name("CL"));
+
+        assert_eq!(conf.stride_atoms(0).atoms.len(), 5);
+        assert_eq!(conf.stride_atoms(10).atoms.len(), 1);
+    }
+
+    #[test]
+    fn positions_and_velocities_to_flat_arrays_and_back() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.100   0.200   0.300  1.0000  2.0000 -3.0000
+    2RES     A1    2   1.000   2.000   3.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let positions = conf.positions_flat();
+        assert_eq!(positions, vec![0.1, 0.2, 0.3, 1.0, 2.0, 3.0]);
+        assert_eq!(conf.velocities_flat(), vec![1.0, 2.0, -3.0, 0.0, 0.0, 0.0]);
+
+        let shifted = positions.iter().map(|x| x + 1.0).collect::<Vec<_>>();
+        conf.set_positions_flat(&shifted).unwrap();
+        assert_eq!(conf.positions_flat(), shifted);
         assert_eq!(
-            multiplied_conf.atoms.last().unwrap().position,
-            conf.atoms.last().unwrap().position + conf.size.pbc_multiply(nx - 1, ny - 1, nz - 1)
+            conf.atoms[1].position,
+            RVec {
+                x: 2.0,
+                y: 3.0,
+                z: 4.0,
+            }
         );
+
+        assert!(conf.set_positions_flat(&positions[..5]).is_err());
+        assert!(conf.set_positions_flat(&[]).is_err());
+        assert_eq!(conf.positions_flat(), shifted);
+    }
+
+    #[test]
+    fn mutate_positions_and_velocities_through_iterators() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.100   0.200   0.300  1.0000  2.0000 -3.0000
+    2RES     A1    2   1.000   2.000   3.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let original = conf.clone();
+
+        let shift = RVec {
+            x: 1.0,
+            y: -1.0,
+            z: 0.5,
+        };
+
+        conf.positions_mut().for_each(|position| *position += shift);
+
+        for (atom, original_atom) in conf.atoms.iter().zip(original.atoms.iter()) {
+            assert_eq!(atom.position, original_atom.position + shift);
+            assert_eq!(atom.velocity, original_atom.velocity);
+        }
+
+        conf.velocities_mut()
+            .filter(|velocity| velocity.is_none())
+            .for_each(|velocity| *velocity = Some(RVec::default()));
+
+        assert_eq!(conf.atoms[0].velocity, original.atoms[0].velocity);
+        assert_eq!(conf.atoms[1].velocity, Some(RVec::default()));
+    }
+
+    #[test]
+    fn scale_velocities_of_atoms_which_have_them() {
+        let content = "\
+A title
+2
+    1RES     A1    1   0.000   0.000   0.000  1.0000  2.0000 -3.0000
+    2RES     A1    2   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        conf.scale_velocities(0.1);
+        let velocity = conf.atoms[0].velocity.unwrap();
+        assert!((velocity.x - 0.1).abs() < 1e-9);
+        assert!((velocity.y - 0.2).abs() < 1e-9);
+        assert!((velocity.z + 0.3).abs() < 1e-9);
+        assert_eq!(conf.atoms[1].velocity, None);
+
+        conf.scale_velocities(1.0 / NM_PER_PS_TO_ANGSTROM_PER_PS);
+        assert!((conf.atoms[0].velocity.unwrap().x - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn boost_velocities_into_rest_frame() {
+        let content = "\
+A title
+3
+    1RES     A1    1   0.000   0.000   0.000  1.0000  2.0000 -3.0000
+    2RES     A1    2   1.000   1.000   1.000  1.0000  2.0000 -3.0000
+    3RES     A1    3   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let frame_velocity = conf.atoms[0].velocity.unwrap();
+        conf.boost_velocities(frame_velocity);
+
+        assert_eq!(conf.atoms[0].velocity, Some(RVec::default()));
+        assert_eq!(conf.atoms[1].velocity, Some(RVec::default()));
+        assert_eq!(conf.atoms[2].velocity, None);
+    }
+
+    #[test]
+    fn density_profile_counts_atoms_in_slabs() {
+        let content = "\
+A title
+6
+    1RES     A1    1   0.000   0.000   0.100
+    2RES     A1    2   0.000   0.000   0.900
+    3RES     A1    3   0.000   0.000   1.100
+    4RES     A1    4   0.000   0.000   2.500
+    5RES     A1    5   0.000   0.000   3.500
+    6RES     A1    6   0.000   0.000  -0.500
+   1.00000   1.00000   3.00000
+";
+
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        // Atoms outside of the box are counted in the edge slabs
+        assert_eq!(conf.density_profile(Direction::Z, 3), vec![3, 1, 2]);
         assert_eq!(
-            multiplied_conf.atoms.last().unwrap().velocity,
-            conf.atoms.last().unwrap().velocity
+            conf.density_profile(Direction::Z, 6),
+            vec![2, 1, 1, 0, 0, 2]
         );
+        assert_eq!(conf.density_profile(Direction::X, 2), vec![6, 0]);
+        assert!(conf.density_profile(Direction::Z, 0).is_empty());
     }
 
     #[test]
-    fn assign_filtered_residues_to_configuration() {
-        // Two types of residues, we want to filter out the second.
-        let residues = vec![
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES1".to_string())),
-                atoms: vec![
-                    Rc::new(RefCell::new("AT1".to_string())),
-                    Rc::new(RefCell::new("At2".to_string())),
-                ],
-            })),
-            Rc::new(RefCell::new(Residue {
-                name: Rc::new(RefCell::new("RES2".to_string())),
-                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
-            })),
-        ];
+    fn bucket_atoms_by_grid_cell() {
+        let content = "\
+A title
+5
+    1RES     A1    1   1.000   1.000   1.000
+    2RES     A1    2   6.000   1.000   1.000
+    3RES     A1    3   6.000   6.000   9.000
+    4RES     A1    4   2.000   2.000   2.000
+    5RES     A1    5  12.000  -1.000   4.999
+  10.00000  10.00000  10.00000
+";
 
-        // This configuration contains 2 different residues, which we will filter to only get one.
-        let atoms = vec![
-            // Filter the next two objects residues
-            Atom {
-                name: residues[1].borrow().atoms[0].clone(),
-                residue: residues[1].clone(),
-                position: RVec {
-                    x: 12.0,
-                    y: 13.0,
-                    z: 14.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[1].borrow().atoms[0].clone(),
-                residue: residues[1].clone(),
-                position: RVec {
-                    x: 15.0,
-                    y: 16.0,
-                    z: 17.0,
-                },
-                velocity: None,
-            },
-            // Two residues of the type we want to keep (2 atoms per residue)
-            Atom {
-                name: residues[0].borrow().atoms[0].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 0.0,
-                    y: 1.0,
-                    z: 2.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[1].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 3.0,
-                    y: 4.0,
-                    z: 5.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[0].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 6.0,
-                    y: 7.0,
-                    z: 8.0,
-                },
-                velocity: None,
-            },
-            Atom {
-                name: residues[0].borrow().atoms[1].clone(),
-                residue: residues[0].clone(),
-                position: RVec {
-                    x: 9.0,
-                    y: 10.0,
-                    z: 11.0,
-                },
-                velocity: None,
-            },
-        ];
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let cells = conf.bucket_by_cell(2, 2, 2);
 
-        let mut conf = Conf {
-            title: "System".to_string(),
-            origin: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            size: RVec {
-                x: 1.0,
-                y: 2.0,
-                z: 3.0,
-            },
-            residues: residues.clone(),
-            atoms: atoms.clone(),
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[&(0, 0, 0)], vec![0, 3]);
+        assert_eq!(cells[&(1, 0, 0)], vec![1, 4]);
+        assert_eq!(cells[&(1, 1, 1)], vec![2]);
+        assert!(!cells.contains_key(&(0, 1, 0)));
+
+        assert_eq!(conf.bucket_by_cell(0, 1, 1)[&(0, 0, 0)].len(), 5);
+    }
+
+    #[test]
+    fn density_grid_counts_atoms_in_cells() {
+        let content = "\
+A title
+1
+    1RES     A1    1   0.050   0.050   0.050
+   0.10000   0.10000   0.10000
+";
+
+        let template = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut conf = Conf::from_template_lattice(&template, 4, 6, 8, template.size);
+
+        // The uniform lattice gives the same count in every cell
+        let grid = conf.density_grid(2, 3, 4);
+        assert_eq!(grid.len(), 24);
+        assert!(grid.iter().all(|&count| count == 8.0));
+        assert_eq!(grid.iter().sum::<f64>(), 192.0);
+
+        // A single atom moved to the far corner is counted in the last cell
+        conf.atoms[0].position = RVec {
+            x: 0.39,
+            y: 0.59,
+            z: 0.79,
         };
+        let grid = conf.density_grid(2, 3, 4);
+        assert_eq!(grid[0], 7.0);
+        assert_eq!(grid[23], 9.0);
 
-        let residues = conf.iter_residues()
-            .filter_map(|atoms| atoms.ok())
-            .filter(|atoms| {
-                let atom = atoms[0].clone();
-                let residue = atom.residue.clone();
+        // Cells along x are adjacent in the grid
+        conf.atoms[0].position.x = 0.05;
+        conf.atoms[0].position.y = 0.05;
+        let grid = conf.density_grid(2, 3, 4);
+        assert_eq!(grid[18], 9.0);
 
-                if &*residue.borrow().name.borrow() == "RES1" {
-                    true
-                } else {
-                    false
-                }
-            })
-            .collect::<Vec<_>>();
+        assert!(conf.density_grid(2, 0, 4).is_empty());
+    }
+
+    #[test]
+    fn insert_molecule_only_into_free_space() {
+        let content = "\
+A title
+2
+    1RES     A1    1   1.000   1.000   1.000
+    2RES     A1    2   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+        let molecule_content = "\
+A molecule
+2
+    1MOL     B1    1   0.000   0.000   0.000
+    1MOL     B2    2   0.200   0.000   0.000
+   0.00000   0.00000   0.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let molecule = gromos87::read_gromos87_conf(&mut molecule_content.as_bytes()).unwrap();
+
+        let position = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 1.0,
+        };
+        assert!(conf.try_insert(&molecule, 0.5, position).is_ok());
 
-        conf.assign_residues(residues.as_slice());
         assert_eq!(conf.atoms.len(), 4);
+        assert_eq!(conf.residues.len(), 2);
+        assert!((conf.atoms[2].position.x - 0.9).abs() < 1e-9);
+        assert!((conf.atoms[3].position.x - 1.1).abs() < 1e-9);
+        assert!((conf.atoms[3].position.y - 2.0).abs() < 1e-9);
+        assert!((conf.atoms[3].position.z - 1.0).abs() < 1e-9);
 
-        // Compare against the original list, with the first two should-be-filtered
-        // residues being skipped
-        for (atom1, atom2) in conf.atoms.iter().zip(atoms.iter().skip(2)) {
-            assert!(Rc::ptr_eq(&atom1.name, &atom2.name));
-            assert!(Rc::ptr_eq(&atom1.residue, &atom2.residue));
-            assert_eq!(atom1.position, atom2.position);
-            assert_eq!(atom1.velocity, atom2.velocity);
-        }
+        // Inserting on top of an existing atom fails without modifying the configuration
+        let position = RVec {
+            x: 2.0,
+            y: 2.0,
+            z: 2.0,
+        };
+        assert!(conf.try_insert(&molecule, 0.5, position).is_err());
+
+        assert_eq!(conf.atoms.len(), 4);
+        assert_eq!(conf.residues.len(), 2);
     }
 
     #[test]
-    fn compare_atom_name_and_residue_name_to_input() {
-        let residue = Rc::new(RefCell::new(Residue {
-            name: Rc::new(RefCell::new("RES1".to_string())),
-            atoms: vec![
-                Rc::new(RefCell::new("AT1".to_string())),
-                Rc::new(RefCell::new("AT2".to_string())),
-            ],
-        }));
+    fn convex_hull_volume_of_unit_cube_corners() {
+        let content = "\
+A title
+9
+    1RES     A1    1   0.000   0.000   0.000
+    2RES     A1    2   1.000   0.000   0.000
+    3RES     A1    3   0.000   1.000   0.000
+    4RES     A1    4   1.000   1.000   0.000
+    5RES     A1    5   0.500   0.500   0.500
+    6RES     A1    6   0.000   0.000   1.000
+    7RES     A1    7   1.000   0.000   1.000
+    8RES     A1    8   0.000   1.000   1.000
+    9RES     A1    9   1.000   1.000   1.000
+   3.00000   3.00000   3.00000
+";
 
-        let atom = Atom {
-            name: residue.borrow().atoms[0].clone(),
-            residue: residue.clone(),
-            position: RVec {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            velocity: None,
-        };
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        assert!((conf.convex_hull_volume() - 1.0).abs() < 1e-9);
 
-        assert!(residue.borrow().cmp_name("RES1"));
-        assert!(!residue.borrow().cmp_name("RES2"));
+        conf.atoms.truncate(4);
+        assert_eq!(conf.convex_hull_volume(), 0.0);
+    }
 
-        assert!(atom.cmp_name("AT1"));
-        assert!(!atom.cmp_name("AT2"));
+    #[test]
+    fn partition_conf_by_residue_names() {
+        let content = "\
+A title
+7
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2NA      NA    4   1.000   1.000   1.000
+    3SOL     OW    5   2.000   2.000   2.000
+    3SOL    HW1    6   2.100   2.000   2.000
+    3SOL    HW2    7   2.000   2.100   2.000
+   3.00000   3.00000   3.00000
+";
 
-        assert!(atom.cmp_residue_name("RES1"));
-        assert!(!atom.cmp_residue_name("RES2"));
+        let conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+
+        let names = ["SOL".to_string()].iter().cloned().collect::<HashSet<_>>();
+        let (solvent, rest) = conf.partition_by_names(&names);
+
+        assert_eq!(solvent.atoms.len(), 6);
+        assert_eq!(rest.atoms.len(), 1);
+        assert_eq!(solvent.atoms.len() + rest.atoms.len(), conf.atoms.len());
+
+        assert_eq!(solvent.residues.len(), 1);
+        assert!(solvent.residues[0].borrow().cmp_name("SOL"));
+        assert_eq!(rest.residues.len(), 1);
+        assert!(rest.residues[0].borrow().cmp_name("NA"));
+
+        assert_eq!(solvent.atoms[3].position, conf.atoms[4].position);
+        assert_eq!(rest.size, conf.size);
+    }
+
+    #[test]
+    fn normalize_velocities_fills_missing_velocities() {
+        let content = "\
+A title
+3
+    1RES     A1    1   0.000   0.000   0.000  1.0000  2.0000  3.0000
+    2RES     A1    2   1.000   1.000   1.000
+    3RES     A1    3   2.000   2.000   2.000
+   3.00000   3.00000   3.00000
+";
+
+        let mut conf = gromos87::read_gromos87_conf(&mut content.as_bytes()).unwrap();
+        let mut conf_without_velocities = conf.clone();
+        conf_without_velocities.atoms.remove(0);
+
+        conf.normalize_velocities(RVec::default());
+
+        assert_eq!(
+            conf.atoms[0].velocity,
+            Some(RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            })
+        );
+        assert_eq!(conf.atoms[1].velocity, Some(RVec::default()));
+        assert_eq!(conf.atoms[2].velocity, Some(RVec::default()));
+
+        conf_without_velocities.normalize_velocities(RVec::default());
+        assert!(conf_without_velocities
+            .atoms
+            .iter()
+            .all(|atom| atom.velocity.is_none()));
     }
 }
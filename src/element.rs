@@ -0,0 +1,83 @@
+/// Elements which are guessed from the first letter of an atom name.
+const ONE_LETTER_ELEMENTS: &[&str] = &["H", "B", "C", "N", "O", "F", "P", "S", "K", "I"];
+
+/// Elements which are guessed only for ions, whose atom and residue names are both
+/// exactly their symbol.
+const TWO_LETTER_ELEMENTS: &[&str] = &[
+    "He", "Li", "Ne", "Na", "Mg", "Al", "Si", "Cl", "Ar", "Ca", "Mn", "Fe", "Co", "Ni", "Cu", "Zn",
+    "Se", "Br", "Kr", "Rb", "Sr", "Cd", "Xe", "Cs", "Ba", "Hg",
+];
+
+/// Guess the element symbol of an atom from its name and the name of its residue.
+///
+/// Leading digits are skipped, as in `1HB`. Two-letter elements are only recognized
+/// for ions, where the atom name is exactly their symbol and the residue is named
+/// after it, as for `NA` in residue `NA` or `CL` in `CL-`. This keeps protein atoms
+/// such as `CA`, `CD1`, `NE2` and `HG1` from being read as metals or noble gases.
+/// Otherwise the element is taken from the first letter, so `OW` and `HW1` are
+/// oxygen and hydrogen.
+pub fn guess_element(atom_name: &str, residue_name: &str) -> Option<&'static str> {
+    let name = atom_name
+        .trim()
+        .trim_start_matches(|c: char| c.is_ascii_digit());
+
+    let is_ion = residue_name
+        .trim()
+        .trim_end_matches(&['+', '-'][..])
+        .eq_ignore_ascii_case(name);
+
+    if is_ion {
+        if let Some(element) = TWO_LETTER_ELEMENTS
+            .iter()
+            .find(|element| element.eq_ignore_ascii_case(name))
+        {
+            return Some(element);
+        }
+    }
+
+    let first = name
+        .chars()
+        .next()
+        .filter(char::is_ascii_alphabetic)?
+        .to_ascii_uppercase()
+        .to_string();
+
+    ONE_LETTER_ELEMENTS
+        .iter()
+        .find(|&&element| element == first)
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_elements_from_atom_names() {
+        assert_eq!(guess_element("OW", "SOL"), Some("O"));
+        assert_eq!(guess_element("HW1", "SOL"), Some("H"));
+        assert_eq!(guess_element("1HB", "ALA"), Some("H"));
+        assert_eq!(guess_element("CA", "ALA"), Some("C"));
+        assert_eq!(guess_element("CB", "ALA"), Some("C"));
+        assert_eq!(guess_element("CD", "ILE"), Some("C"));
+        assert_eq!(guess_element("CD1", "LEU"), Some("C"));
+        assert_eq!(guess_element("NE2", "HIS"), Some("N"));
+        assert_eq!(guess_element("HG1", "THR"), Some("H"));
+        assert_eq!(guess_element("NZ", "LYS"), Some("N"));
+        assert_eq!(guess_element("XX", "DUM"), None);
+        assert_eq!(guess_element("", "DUM"), None);
+        assert_eq!(guess_element("12", "DUM"), None);
+    }
+
+    #[test]
+    fn guess_two_letter_elements_of_ions() {
+        assert_eq!(guess_element("NA", "NA"), Some("Na"));
+        assert_eq!(guess_element("Cl", "CL-"), Some("Cl"));
+        assert_eq!(guess_element("CA", "CA"), Some("Ca"));
+        assert_eq!(guess_element(" ZN", "ZN+"), Some("Zn"));
+
+        // Names which are symbols outside of an ion residue are not ions
+        assert_eq!(guess_element("NA", "HEM"), Some("N"));
+        assert_eq!(guess_element("CD", "CD1"), Some("C"));
+    }
+}
@@ -1,13 +1,25 @@
+use charmm;
 use gromos87;
+use pdb;
+use xyz;
 
 use std::io;
+use std::string::FromUtf8Error;
 
 #[derive(Debug, Fail)]
 pub enum WriteError {
     #[fail(display = "Could not write GROMOS87 file ({})", _0)]
     Gromos87(gromos87::WriteError),
+    #[fail(display = "Could not write PDB file ({})", _0)]
+    Pdb(pdb::WriteError),
+    #[fail(display = "Could not write XYZ file ({})", _0)]
+    Xyz(xyz::WriteError),
     #[fail(display = "Could not open file for writing ({})", _0)]
     IoError(io::Error),
+    #[fail(display = "Unknown file format for '{}'", _0)]
+    UnknownFormat(String),
+    #[fail(display = "Written output was not valid UTF-8 ({})", _0)]
+    Utf8Error(FromUtf8Error),
 }
 
 impl From<io::Error> for WriteError {
@@ -20,8 +32,16 @@ impl From<io::Error> for WriteError {
 pub enum ReadError {
     #[fail(display = "Could not read GROMOS87 file ({})", _0)]
     Gromos87(gromos87::ReadError),
+    #[fail(display = "Could not read PDB file ({})", _0)]
+    Pdb(pdb::ReadError),
+    #[fail(display = "Could not read XYZ file ({})", _0)]
+    Xyz(xyz::ReadError),
+    #[fail(display = "Could not read CHARMM file ({})", _0)]
+    Charmm(charmm::ReadError),
     #[fail(display = "Could not open file for reading ({})", _0)]
     IoError(io::Error),
+    #[fail(display = "Unknown file format for '{}'", _0)]
+    UnknownFormat(String),
 }
 
 impl From<io::Error> for ReadError {
@@ -0,0 +1,261 @@
+use conf::Conf;
+use error::{ReadError, WriteError};
+use gromos87;
+use pdb;
+use xyz;
+
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+
+/// A file format which configurations can be read from and written to.
+pub trait ConfFormat {
+    /// Read a configuration from the reader.
+    fn read<R: Read>(reader: R) -> Result<Conf, ReadError>;
+
+    /// Write a configuration to the writer.
+    fn write<W: Write>(conf: &Conf, writer: &mut W) -> Result<(), WriteError>;
+}
+
+/// The GROMOS87 (`.gro`) format.
+pub struct Gromos87;
+
+/// The Protein Data Bank (`.pdb`) format.
+pub struct Pdb;
+
+/// The XYZ (`.xyz`) format.
+pub struct Xyz;
+
+/// A format detected from the content of a file by `sniff_format`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DetectedFormat {
+    Gromos87,
+    Pdb,
+    Xyz,
+}
+
+/// Number of lines which are inspected to detect a format.
+const NUM_SNIFF_LINES: usize = 5;
+
+/// Detect the format of a configuration from its first lines, or `None` if the
+/// format could not be recognized.
+///
+/// The lines are read from the reader, which is then sought back to where it started,
+/// so the reader can afterwards be used to read the configuration.
+///
+/// PDB files are recognized by `ATOM`, `HETATM` or `HEADER` records, unless the second
+/// line is a number of atoms since a GROMOS87 title may also begin with them. GROMOS87
+/// files are recognized by a title, number of atoms and fixed width atom line. XYZ
+/// files by a number of atoms, comment and `element x y z` line.
+pub fn sniff_format<R: BufRead + Seek>(reader: &mut R) -> Option<DetectedFormat> {
+    let start = reader.stream_position().ok()?;
+
+    let mut lines = Vec::new();
+    let mut buf = String::new();
+
+    while lines.len() < NUM_SNIFF_LINES {
+        buf.clear();
+
+        match reader.read_line(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => lines.push(buf.trim_end_matches(&['\n', '\r'][..]).to_string()),
+        }
+    }
+
+    reader.seek(SeekFrom::Start(start)).ok()?;
+
+    let is_pdb_record = |line: &String| {
+        ["ATOM", "HETATM", "HEADER"]
+            .iter()
+            .any(|record| line.starts_with(record))
+    };
+
+    let is_count = |i: usize| {
+        lines
+            .get(i)
+            .is_some_and(|line| line.trim().parse::<usize>().is_ok())
+    };
+
+    if !is_count(1) && lines.iter().any(is_pdb_record) {
+        return Some(DetectedFormat::Pdb);
+    }
+
+    let is_xyz_atom_line = |line: &String| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        fields.len() == 4 && fields[1..].iter().all(|value| value.parse::<f64>().is_ok())
+    };
+
+    let is_gromos87_atom_line = |line: &String| gromos87::is_gromos87_atom_line(line);
+
+    // GROMOS87 files are checked first, since one with a numeric title and joined
+    // atom line fields can also look like an XYZ file
+    if is_count(1) && lines.get(2).is_some_and(is_gromos87_atom_line) {
+        return Some(DetectedFormat::Gromos87);
+    }
+
+    if is_count(0) && lines.get(2).is_some_and(is_xyz_atom_line) {
+        return Some(DetectedFormat::Xyz);
+    }
+
+    None
+}
+
+impl ConfFormat for Gromos87 {
+    fn read<R: Read>(reader: R) -> Result<Conf, ReadError> {
+        gromos87::read_gromos87_conf(reader).map_err(ReadError::Gromos87)
+    }
+
+    fn write<W: Write>(conf: &Conf, writer: &mut W) -> Result<(), WriteError> {
+        gromos87::write_gromos87_conf(conf, writer).map_err(WriteError::Gromos87)
+    }
+}
+
+impl ConfFormat for Pdb {
+    fn read<R: Read>(reader: R) -> Result<Conf, ReadError> {
+        pdb::read_pdb_conf(reader).map_err(ReadError::Pdb)
+    }
+
+    fn write<W: Write>(conf: &Conf, writer: &mut W) -> Result<(), WriteError> {
+        pdb::write_pdb_conf(conf, writer).map_err(WriteError::Pdb)
+    }
+}
+
+impl ConfFormat for Xyz {
+    fn read<R: Read>(reader: R) -> Result<Conf, ReadError> {
+        xyz::read_xyz_conf(reader).map_err(ReadError::Xyz)
+    }
+
+    fn write<W: Write>(conf: &Conf, writer: &mut W) -> Result<(), WriteError> {
+        xyz::write_xyz_conf(conf, writer).map_err(WriteError::Xyz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+    use std::io::{BufReader, Cursor};
+
+    const GROMOS87_CONTENT: &str = "\
+A title
+3
+    1RES1   AT1    1   0.000   1.000   2.000  0.0000  0.1000  0.3000
+    1RES1   AT2    2   3.000   4.000   5.000  0.3000  0.4000  0.5000
+    2RES2   AT3    3   6.000   7.000   8.000  0.6000  0.7000  0.8000
+  10.00000  11.00000  12.00000
+";
+
+    fn assert_confs_equal(conf1: &Conf, conf2: &Conf) {
+        assert_eq!(conf1.title, conf2.title);
+        assert_eq!(conf1.size, conf2.size);
+        assert_eq!(conf1.residues.len(), conf2.residues.len());
+        assert_eq!(conf1.atoms.len(), conf2.atoms.len());
+
+        for (atom1, atom2) in conf1.atoms.iter().zip(conf2.atoms.iter()) {
+            assert_eq!(*atom1.name.borrow(), *atom2.name.borrow());
+            assert_eq!(*atom1.residue.borrow().name, *atom2.residue.borrow().name);
+            assert_eq!(atom1.position, atom2.position);
+            assert_eq!(atom1.velocity, atom2.velocity);
+        }
+    }
+
+    #[test]
+    fn read_gromos87_through_format_trait() {
+        let direct = gromos87::read_gromos87_conf(GROMOS87_CONTENT.as_bytes()).unwrap();
+        let conf = Gromos87::read(GROMOS87_CONTENT.as_bytes()).unwrap();
+
+        assert_confs_equal(&conf, &direct);
+
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        Gromos87::write(&conf, &mut buf).unwrap();
+
+        buf.set_position(0);
+        assert_confs_equal(&Gromos87::read(buf).unwrap(), &direct);
+    }
+
+    #[test]
+    fn sniff_format_of_content_without_consuming_it() {
+        let mut reader = BufReader::new(Cursor::new(GROMOS87_CONTENT.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Gromos87));
+        assert_confs_equal(
+            &Gromos87::read(reader).unwrap(),
+            &Gromos87::read(GROMOS87_CONTENT.as_bytes()).unwrap(),
+        );
+
+        let pdb_content = "\
+HEADER    A title
+CRYST1   10.000   11.000   12.000  90.00  90.00  90.00 P 1           1
+ATOM      1  AT1 RES1    1       0.000  10.000  20.000  1.00  0.00
+";
+        let mut reader = BufReader::new(Cursor::new(pdb_content.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Pdb));
+
+        let xyz_content = "\
+2
+A comment
+O      0.00000      1.00000      2.00000
+H      1.00000      1.00000      2.00000
+";
+        let mut reader = BufReader::new(Cursor::new(xyz_content.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Xyz));
+        assert_eq!(Xyz::read(reader).unwrap().atoms.len(), 2);
+
+        let content = "Some text\nwhich is not a configuration\n";
+        let mut reader = BufReader::new(Cursor::new(content.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), None);
+
+        let mut reader = BufReader::new(Cursor::new("".as_bytes()));
+        assert_eq!(sniff_format(&mut reader), None);
+    }
+
+    #[test]
+    fn sniff_format_reads_past_a_short_buffer() {
+        let mut reader = BufReader::with_capacity(8, Cursor::new(GROMOS87_CONTENT.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Gromos87));
+        assert_confs_equal(
+            &Gromos87::read(reader).unwrap(),
+            &Gromos87::read(GROMOS87_CONTENT.as_bytes()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn sniff_format_of_gromos87_content_with_pdb_record_title() {
+        for title in &["HEADER of a title", "ATOM positions"] {
+            let content = GROMOS87_CONTENT.replacen("A title", title, 1);
+
+            let mut reader = Cursor::new(content.as_bytes());
+            assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Gromos87));
+        }
+    }
+
+    #[test]
+    fn sniff_format_of_gromos87_content_with_numeric_title() {
+        // With a five digit atom number the atom line has an element and three values
+        let content = "\
+12345
+1
+    1WATERATOM110000   1.000   2.000   3.000
+   1.00000   1.00000   1.00000
+";
+        let mut reader = BufReader::new(Cursor::new(content.as_bytes()));
+        assert_eq!(sniff_format(&mut reader), Some(DetectedFormat::Gromos87));
+
+        let conf = Gromos87::read(reader).unwrap();
+        assert_eq!(conf.title, "12345");
+        assert_eq!(conf.atoms.len(), 1);
+    }
+
+    #[test]
+    fn read_and_write_conf_files_with_format_type() {
+        let conf = Gromos87::read(GROMOS87_CONTENT.as_bytes()).unwrap();
+
+        let mut path = temp_dir();
+        path.push("_mdio_test_read_and_write_format.gro");
+
+        conf.write_format::<Gromos87>(&path).unwrap();
+        let read_conf = Conf::read_format::<Gromos87>(&path).unwrap();
+        remove_file(&path).unwrap();
+
+        assert_confs_equal(&read_conf, &conf);
+    }
+}
@@ -1,35 +1,217 @@
-use conf::{get_or_insert_atom_and_residue, Atom, Conf};
+use conf::{get_or_insert_atom_and_residue, get_residue_ranges, Atom, Conf, Residue};
 use rvec::{ParseRVecError, RVec};
 
+use std::cell::RefCell;
 use std::io;
 use std::io::{BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+
+/// Width of the residue and atom number columns in standard GROMOS87 files.
+const DEFAULT_INDEX_WIDTH: usize = 5;
+
+/// Width of the position and velocity columns in standard GROMOS87 files.
+const DEFAULT_COORD_WIDTH: usize = 8;
+
+/// Options for writing GROMOS87 files.
+#[derive(Clone, Copy, Debug)]
+pub struct Gromos87WriteOptions {
+    /// Skip incomplete residues instead of returning an error. The number of atoms
+    /// in the header is then the number of atoms which were written.
+    pub skip_bad_residues: bool,
+    /// Width of the residue and atom number columns. With the standard width of 5
+    /// the numbers wrap at 100_000, with larger widths they are written in full.
+    /// Smaller widths are treated as 5.
+    pub index_width: usize,
+    /// Return an error instead of writing a configuration whose box size is zero
+    /// or negative along any axis, which is invalid for simulations. Configurations
+    /// read from formats without a box, like XYZ, must have a box set or this
+    /// option disabled to be written.
+    pub check_box_size: bool,
+    /// Write the residue and atom numbers stored in the atoms, for example by
+    /// `Conf::renumber_from`, instead of numbering them from 1. Atoms without stored
+    /// numbers get the running numbers. The stored numbers are copied along with
+    /// the atoms when a configuration is derived from another, so they are only
+    /// correct if the configuration was renumbered after any such change.
+    pub write_stored_numbers: bool,
+}
 
-pub fn write_gromos87_conf<W: Write>(conf: &Conf, mut writer: &mut W) -> Result<(), WriteError> {
-    write!(&mut writer, "{}\n{}\n", conf.title, conf.atoms.len())?;
+impl Default for Gromos87WriteOptions {
+    fn default() -> Gromos87WriteOptions {
+        Gromos87WriteOptions {
+            skip_bad_residues: false,
+            index_width: DEFAULT_INDEX_WIDTH,
+            check_box_size: true,
+            write_stored_numbers: false,
+        }
+    }
+}
 
-    let mut atom_num = 0;
+/// Options for reading GROMOS87 files.
+#[derive(Clone, Copy, Debug)]
+pub struct Gromos87ReadOptions {
+    /// Width of the residue and atom number columns, which must match the width
+    /// the file was written with. Smaller widths than 5 are treated as 5.
+    pub index_width: usize,
+    /// Detect the width of the position and velocity columns from the first atom
+    /// line, instead of using the standard width of 8. The columns after the atom
+    /// number are divided evenly into six fields of at least width 8 if possible,
+    /// otherwise into three fields. The detected width is used for all atom lines.
+    pub detect_coord_width: bool,
+    /// Start a new residue object whenever the residue number changes between atom
+    /// lines, instead of identifying residues by their name only. This keeps residues
+    /// which share a name but have different atoms apart, at the cost of one residue
+    /// object per residue in the configuration.
+    pub split_residues_by_number: bool,
+}
+
+impl Default for Gromos87ReadOptions {
+    fn default() -> Gromos87ReadOptions {
+        Gromos87ReadOptions {
+            index_width: DEFAULT_INDEX_WIDTH,
+            detect_coord_width: false,
+            split_residues_by_number: false,
+        }
+    }
+}
+
+/// Write a configuration in the GROMOS87 format.
+///
+/// The box size is checked as with the default `Gromos87WriteOptions`, so a configuration
+/// without a box, as read from an XYZ file or a PDB file without a `CRYST1` record,
+/// gives an `InvalidBox` error unless one is set first.
+pub fn write_gromos87_conf<W: Write>(conf: &Conf, writer: &mut W) -> Result<(), WriteError> {
+    write_gromos87_conf_with_options(conf, writer, &Gromos87WriteOptions::default())
+}
+
+/// Write a configuration in the GROMOS87 format using the input options.
+pub fn write_gromos87_conf_with_options<W: Write>(
+    conf: &Conf,
+    writer: &mut W,
+    options: &Gromos87WriteOptions,
+) -> Result<(), WriteError> {
+    check_box_size(conf.size, options)?;
 
-    for (res_num, residue) in conf.iter_residues().enumerate() {
-        // GROMOS-87 wraps indices at 5 digits, ie. at 100_000
-        let res_num_wrapped = (res_num + 1) % 100_000;
+    // Collect the residues before writing, since the number of atoms is written first
+    let mut residues = Vec::new();
+
+    for (res_num, range) in get_residue_ranges(conf).into_iter().enumerate() {
+        match range {
+            Ok(range) => residues.push(conf.atoms[range].iter().collect()),
+            Err(_) if options.skip_bad_residues => (),
+            Err(_) => return Err(WriteError::BadResidue(res_num + 1)),
+        }
+    }
 
-        for atom in residue
-            .map_err(|_| WriteError::BadResidue(res_num + 1))?
+    write_residues(conf, &residues, writer, options)
+}
+
+/// Write a configuration in the GROMOS87 format with only the atoms of the input
+/// indices, in their given order.
+///
+/// Consecutive atoms which belong to the same residue in the configuration are
+/// written as a residue, and the residues and atoms are numbered from 1.
+pub fn write_gromos87_subset<W: Write>(
+    conf: &Conf,
+    indices: &[usize],
+    writer: &mut W,
+) -> Result<(), WriteError> {
+    let options = Gromos87WriteOptions::default();
+    check_box_size(conf.size, &options)?;
+
+    // The position of an atom in the list of atoms of its residue
+    let get_position_in_residue = |atom: &Atom| {
+        atom.residue
+            .borrow()
+            .atoms
             .iter()
-        {
+            .position(|name| Rc::ptr_eq(name, &atom.name))
+    };
+
+    let mut residues: Vec<Vec<&Atom>> = Vec::new();
+    let mut previous: Option<(usize, Option<usize>)> = None;
+
+    for &i in indices {
+        let atom = conf.atoms.get(i).ok_or(WriteError::BadIndex(i))?;
+        let position = get_position_in_residue(atom);
+
+        // Atoms are in the same residue if their distance in the configuration is the
+        // same as in the residue, ie. if no other residue is between them
+        let is_same_residue = match (previous, residues.last()) {
+            (Some((j, Some(prev_position))), Some(residue)) => {
+                Rc::ptr_eq(&atom.residue, &residue[0].residue)
+                    && position.is_some_and(|position| {
+                        i > j && position > prev_position && i - j == position - prev_position
+                    })
+            }
+            _ => false,
+        };
+
+        if is_same_residue {
+            residues.last_mut().unwrap().push(atom);
+        } else {
+            residues.push(vec![atom]);
+        }
+
+        previous = Some((i, position));
+    }
+
+    write_residues(conf, &residues, writer, &options)
+}
+
+fn check_box_size(size: RVec, options: &Gromos87WriteOptions) -> Result<(), WriteError> {
+    if options.check_box_size && (size.x <= 0.0 || size.y <= 0.0 || size.z <= 0.0) {
+        Err(WriteError::InvalidBox(size))
+    } else {
+        Ok(())
+    }
+}
+
+/// Write the title and box of a configuration with the atoms of the input residues.
+fn write_residues<W: Write>(
+    conf: &Conf,
+    residues: &[Vec<&Atom>],
+    mut writer: &mut W,
+    options: &Gromos87WriteOptions,
+) -> Result<(), WriteError> {
+    let num_atoms: usize = residues.iter().map(|atoms| atoms.len()).sum();
+    write!(&mut writer, "{}\n{}\n", conf.title, num_atoms)?;
+
+    // GROMOS-87 wraps indices at 5 digits, ie. at 100_000, but wider columns are not wrapped
+    let width = options.index_width.max(DEFAULT_INDEX_WIDTH);
+    let wrap = |num: usize| {
+        if width == DEFAULT_INDEX_WIDTH {
+            num % 100_000
+        } else {
+            num
+        }
+    };
+
+    let mut atom_num = 0;
+
+    for (res_num, residue) in residues.iter().enumerate() {
+        let stored_res_num = residue
+            .first()
+            .and_then(|atom| atom.residue_number)
+            .filter(|_| options.write_stored_numbers);
+        let res_num_wrapped = wrap(stored_res_num.unwrap_or(res_num + 1));
+
+        for atom in residue.iter() {
             atom_num += 1;
-            let atom_num_wrapped = atom_num % 100_000;
+
+            let stored_atom_num = atom.atom_number.filter(|_| options.write_stored_numbers);
+            let atom_num_wrapped = wrap(stored_atom_num.unwrap_or(atom_num));
 
             write!(
                 &mut writer,
-                "{:>5}{:<5}{:>5}{:>5}{:>8.3}{:>8.3}{:>8.3}",
+                "{:>width$}{:<5}{:>5}{:>width$}{:>8.3}{:>8.3}{:>8.3}",
                 res_num_wrapped,
                 atom.residue.borrow().name.borrow(),
                 *atom.name.borrow(),
                 atom_num_wrapped,
                 atom.position.x,
                 atom.position.y,
-                atom.position.z
+                atom.position.z,
+                width = width
             )?;
 
             if let Some(velocity) = atom.velocity {
@@ -53,8 +235,88 @@ pub fn write_gromos87_conf<W: Write>(conf: &Conf, mut writer: &mut W) -> Result<
     Ok(())
 }
 
+/// Write configurations as consecutive frames of a GROMOS87 trajectory.
+///
+/// Each frame is written as soon as it is given, so trajectories can be written
+/// without keeping all frames in memory. The frames can be read with
+/// `Gromos87FrameReader`.
+pub struct Gromos87FrameWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Gromos87FrameWriter<W> {
+    pub fn new(writer: W) -> Gromos87FrameWriter<W> {
+        Gromos87FrameWriter { writer }
+    }
+
+    /// Write a configuration as the next frame.
+    pub fn write_frame(&mut self, conf: &Conf) -> Result<(), WriteError> {
+        write_gromos87_conf(conf, &mut self.writer)
+    }
+}
+
+/// Read the consecutive frames of a GROMOS87 trajectory, one at a time.
+///
+/// Iteration stops at the end of the input or after the first frame which could
+/// not be read, which is yielded as an error. Blank lines after the last frame
+/// are ignored.
+pub struct Gromos87FrameReader<R: Read> {
+    reader: BufReader<R>,
+    done: bool,
+}
+
+impl<R: Read> Gromos87FrameReader<R> {
+    pub fn new(reader: R) -> Gromos87FrameReader<R> {
+        Gromos87FrameReader {
+            reader: BufReader::new(reader),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Gromos87FrameReader<R> {
+    type Item = Result<Conf, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        // Blank lines after the last frame do not begin another frame. Since blank lines
+        // before a frame may hold its empty title, the read lines are given back to
+        // the frame reader once a line which is not blank is found.
+        let mut leading = String::new();
+
+        loop {
+            let start = leading.len();
+
+            match self.reader.read_line(&mut leading) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(_) if leading[start..].trim().is_empty() => (),
+                Ok(_) => break,
+                Err(_) => {
+                    self.done = true;
+                    return Some(Err(ReadError::Utf8Error(leading.lines().count() + 1)));
+                }
+            }
+        }
+
+        let mut reader = leading.as_bytes().chain(&mut self.reader);
+        let result =
+            read_conf(&mut reader, false, &Gromos87ReadOptions::default()).map(|(conf, _)| conf);
+        self.done = result.is_err();
+
+        Some(result)
+    }
+}
+
 struct Line<'a> {
-    // residue_number: usize,
+    /// The residue number as written, which is not parsed since it is only compared
+    /// between lines.
+    residue_number: &'a str,
     residue_name: &'a str,
     atom_name: &'a str,
     // atom_number: usize,
@@ -68,6 +330,10 @@ pub enum WriteError {
     IoError(io::Error),
     #[fail(display = "Error writing residue {}, which was incomplete", _0)]
     BadResidue(usize),
+    #[fail(display = "Error writing configuration with invalid box size {:?}", _0)]
+    InvalidBox(RVec),
+    #[fail(display = "Error writing atom with index {}, which does not exist", _0)]
+    BadIndex(usize),
 }
 
 impl From<io::Error> for WriteError {
@@ -97,54 +363,147 @@ pub enum ReadError {
 }
 
 pub fn read_gromos87_conf<R: Read>(reader: R) -> Result<Conf, ReadError> {
-    let mut buf_reader = BufReader::new(reader);
-    let mut buf = String::new();
+    read_gromos87_conf_with_options(reader, &Gromos87ReadOptions::default())
+}
 
-    buf_reader
-        .read_line(&mut buf)
-        .map_err(|_| ReadError::Utf8Error(1))?;
-    let title = buf.trim().to_string();
-    buf.clear();
+/// Read a configuration in the GROMOS87 format using the input options.
+pub fn read_gromos87_conf_with_options<R: Read>(
+    reader: R,
+    options: &Gromos87ReadOptions,
+) -> Result<Conf, ReadError> {
+    read_conf(&mut BufReader::new(reader), false, options).map(|(conf, _)| conf)
+}
 
-    buf_reader
-        .read_line(&mut buf)
-        .map_err(|_| ReadError::Utf8Error(1))?;
-    let num_atoms = buf.trim()
-        .parse::<usize>()
-        .map_err(|_| ReadError::NumAtomsError)?;
-    buf.clear();
+/// Read a configuration in the GROMOS87 format, salvaging what can be salvaged
+/// from malformed lines instead of returning an error.
+///
+/// Descriptions of the recovered issues are returned along with the configuration,
+/// eg. `"line 42: velocity field truncated, treated as absent"`. Errors which cannot
+/// be recovered from, such as a missing atom or box size line, are still returned.
+pub fn read_gromos87_conf_lenient<R: Read>(reader: R) -> Result<(Conf, Vec<String>), ReadError> {
+    read_conf(
+        &mut BufReader::new(reader),
+        true,
+        &Gromos87ReadOptions::default(),
+    )
+}
+
+/// Read every `stride`th frame of a GROMOS87 trajectory, starting with the first.
+///
+/// The skipped frames are still read and must be valid. A stride of 0 is treated
+/// as 1, returning all frames.
+pub fn read_gromos87_frames_strided<R: Read>(
+    reader: R,
+    stride: usize,
+) -> Result<Vec<Conf>, ReadError> {
+    let stride = stride.max(1);
+    let mut frames = Vec::new();
+
+    for (i, frame) in Gromos87FrameReader::new(reader).enumerate() {
+        let conf = frame?;
+
+        if i % stride == 0 {
+            frames.push(conf);
+        }
+    }
+
+    Ok(frames)
+}
+
+/// Read a single configuration, leaving the reader after its box size line.
+fn read_conf<R: BufRead>(
+    buf_reader: &mut R,
+    lenient: bool,
+    options: &Gromos87ReadOptions,
+) -> Result<(Conf, Vec<String>), ReadError> {
+    let index_width = options.index_width.max(DEFAULT_INDEX_WIDTH);
+    let mut coord_width = DEFAULT_COORD_WIDTH;
+
+    let mut buf = String::new();
+
+    let (title, num_atoms, num_blank) = read_header(buf_reader)?;
 
     let mut residues = Vec::new();
     let mut atoms = Vec::new();
+    let mut warnings = Vec::new();
+
+    let mut current_residue: Option<(String, Rc<RefCell<Residue>>)> = None;
 
     for i in 0..num_atoms {
-        buf_reader
-            .read_line(&mut buf)
-            .map_err(|_| ReadError::Utf8Error(2 + i))?;
+        let line_num = 2 + num_blank + i;
+        read_line(buf_reader, &mut buf).map_err(|_| ReadError::Utf8Error(line_num))?;
+
+        if i == 0 && options.detect_coord_width {
+            coord_width = detect_coord_width(&buf, index_width);
+        }
+
+        let atom_line = if lenient {
+            let (atom_line, warning) =
+                parse_atom_line_lenient_with_widths(&buf, index_width, coord_width)
+                    .map_err(|_| ReadError::LineError(line_num))?;
+
+            // Warnings refer to the line number as counted from the first line
+            if let Some(warning) = warning {
+                warnings.push(format!("line {}: {}", line_num + 1, warning));
+            }
 
-        let atom_line = parse_atom_line(&buf).map_err(|_| ReadError::LineError(2 + i))?;
-        let (residue, atom) = get_or_insert_atom_and_residue(
-            atom_line.residue_name,
-            atom_line.atom_name,
-            &mut residues,
-        ).map_err(|_| ReadError::LineError(2 + i))?;
+            atom_line
+        } else {
+            parse_atom_line_with_widths(&buf, index_width, coord_width)
+                .map_err(|_| ReadError::LineError(line_num))?
+        };
+
+        let (residue, atom) = if options.split_residues_by_number {
+            let is_same_residue = match current_residue {
+                Some((ref number, ref residue)) => {
+                    number == atom_line.residue_number
+                        && residue.borrow().cmp_name(atom_line.residue_name)
+                }
+                None => false,
+            };
+
+            if !is_same_residue {
+                let residue = Rc::new(RefCell::new(Residue {
+                    name: Rc::new(RefCell::new(atom_line.residue_name.to_string())),
+                    atoms: Vec::new(),
+                }));
+
+                residues.push(residue.clone());
+                current_residue = Some((atom_line.residue_number.to_string(), residue));
+            }
+
+            let residue = current_residue.as_ref().unwrap().1.clone();
+            let atom = residue.borrow_mut().get_or_insert_atom(atom_line.atom_name);
+
+            (residue, atom)
+        } else {
+            get_or_insert_atom_and_residue(
+                atom_line.residue_name,
+                atom_line.atom_name,
+                &mut residues,
+            ).map_err(|_| ReadError::LineError(line_num))?
+        };
 
         atoms.push(Atom {
             name: atom,
             residue,
             position: atom_line.position,
             velocity: atom_line.velocity,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
         });
 
         buf.clear();
     }
 
-    buf_reader
-        .read_line(&mut buf)
-        .map_err(|_| ReadError::Utf8Error(3 + num_atoms))?;
-    let size = RVec::from_whitespace(&buf).expect("could not read box size");
+    let line_num = 3 + num_blank + num_atoms;
+    read_line(buf_reader, &mut buf).map_err(|_| ReadError::Utf8Error(line_num))?;
+    let size = parse_box_size(&buf).map_err(|_| ReadError::BoxSizeError(line_num))?;
 
-    Ok(Conf {
+    let conf = Conf {
         title,
         origin: RVec {
             x: 0.0,
@@ -154,33 +513,224 @@ pub fn read_gromos87_conf<R: Read>(reader: R) -> Result<Conf, ReadError> {
         size,
         residues,
         atoms,
-    })
+    };
+
+    Ok((conf, warnings))
+}
+
+/// Read the title and number of atoms of a GROMOS87 file, leaving the reader
+/// at the first atom line.
+///
+/// This is useful for cheaply indexing the frames of large trajectories.
+pub fn read_gromos87_title_and_count<R: BufRead>(
+    reader: &mut R,
+) -> Result<(String, usize), ReadError> {
+    let (title, num_atoms, _) = read_header(reader)?;
+
+    Ok((title, num_atoms))
+}
+
+/// Read the title and number of atoms, along with the number of skipped blank
+/// lines before the title.
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(String, usize, usize), ReadError> {
+    let mut buf = String::new();
+
+    // Skip blank lines before the title, counting them to offset the line numbers
+    let mut num_blank = 0;
+    read_line(reader, &mut buf).map_err(|_| ReadError::Utf8Error(1))?;
+
+    while buf.trim().is_empty() {
+        buf.clear();
+
+        let num_bytes =
+            read_line(reader, &mut buf).map_err(|_| ReadError::Utf8Error(1 + num_blank))?;
+
+        if num_bytes == 0 {
+            break;
+        }
+
+        num_blank += 1;
+    }
+
+    // If the blank lines are followed by the number of atoms, the last of them
+    // was an intentionally empty title
+    let title = if num_blank > 0 && buf.trim().parse::<usize>().is_ok() {
+        num_blank -= 1;
+        String::new()
+    } else {
+        let title = buf.trim().to_string();
+        buf.clear();
+
+        read_line(reader, &mut buf).map_err(|_| ReadError::Utf8Error(1 + num_blank))?;
+        title
+    };
+
+    let num_atoms = buf
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| ReadError::NumAtomsError)?;
+
+    Ok((title, num_atoms, num_blank))
+}
+
+/// Parse the box size line, where a single value denotes a cubic box.
+fn parse_box_size(line: &str) -> Result<RVec, ParseRVecError> {
+    let mut values = line.split_whitespace();
+
+    match (values.next(), values.next()) {
+        (Some(value), None) => {
+            let size = value
+                .parse::<f64>()
+                .map_err(|_| ParseRVecError::ParseFloatError)?;
+
+            Ok(RVec {
+                x: size,
+                y: size,
+                z: size,
+            })
+        }
+        _ => RVec::from_whitespace(line),
+    }
+}
+
+/// Parse a box line with 3 or 9 values into a matrix whose rows are the box vectors.
+///
+/// Three values give a rectangular box with the values on the diagonal. Nine values
+/// are in the GROMACS order `xx yy zz xy xz yx yz zx zy`, where for example `xy`
+/// is the y component of the first box vector. Other numbers of values give
+/// a `WrongNumberOfValues` error, or `MissingValues` for an empty line.
+///
+/// Unlike when reading a configuration, a single value is not accepted as a cubic box.
+pub fn parse_box_line(line: &str) -> Result<[[f64; 3]; 3], ParseRVecError> {
+    let values = line
+        .split_whitespace()
+        .map(|value| value.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ParseRVecError::ParseFloatError)?;
+
+    match values.len() {
+        3 => Ok([
+            [values[0], 0.0, 0.0],
+            [0.0, values[1], 0.0],
+            [0.0, 0.0, values[2]],
+        ]),
+        9 => Ok([
+            [values[0], values[3], values[4]],
+            [values[5], values[1], values[6]],
+            [values[7], values[8], values[2]],
+        ]),
+        0 => Err(ParseRVecError::MissingValues),
+        num => Err(ParseRVecError::WrongNumberOfValues(num)),
+    }
+}
+
+/// Read a line into the buffer, stripping the trailing `\n` or `\r\n`.
+fn read_line<R: BufRead>(reader: &mut R, buf: &mut String) -> io::Result<usize> {
+    let num_bytes = reader.read_line(buf)?;
+
+    if buf.ends_with('\n') {
+        buf.pop();
+
+        if buf.ends_with('\r') {
+            buf.pop();
+        }
+    }
+
+    Ok(num_bytes)
 }
 
 #[derive(Debug, Fail)]
 #[fail(display = "Could not parse a line")]
 struct ParseLineError;
 
-fn parse_atom_line(line: &str) -> Result<Line, ParseLineError> {
-    const GRO_MINLINELEN: usize = 44;
-    if line.len() < GRO_MINLINELEN {
+/// Parse an atom line with residue and atom number columns, and position and
+/// velocity columns, of the input widths.
+fn parse_atom_line_with_widths(
+    line: &str,
+    index_width: usize,
+    coord_width: usize,
+) -> Result<Line<'_>, ParseLineError> {
+    parse_atom_line_fixed_with_widths(line, index_width, coord_width)
+        .or_else(|_| parse_atom_line_whitespace(line))
+}
+
+/// Return whether a line is a fixed width atom line with columns of the standard widths.
+pub fn is_gromos87_atom_line(line: &str) -> bool {
+    parse_atom_line_fixed(line).is_ok()
+}
+
+/// Return the column at which the positions begin, for number columns of the input width.
+fn get_position_start(index_width: usize) -> usize {
+    2 * index_width + 10
+}
+
+/// Return the column at which the velocity begins, for number columns and position
+/// columns of the input widths.
+fn get_velocity_start(index_width: usize, coord_width: usize) -> usize {
+    get_position_start(index_width) + 3 * coord_width
+}
+
+/// Detect the width of the position and velocity columns of an atom line by dividing
+/// the columns after the atom number evenly into six fields of at least the standard
+/// width, or else into three fields. The standard width is returned if neither works.
+fn detect_coord_width(line: &str, index_width: usize) -> usize {
+    let length = line
+        .get(get_position_start(index_width)..)
+        .map(|fields| fields.trim_end().len())
+        .unwrap_or(0);
+
+    if length % 6 == 0 && length / 6 >= DEFAULT_COORD_WIDTH {
+        length / 6
+    } else if length % 3 == 0 && length / 3 >= DEFAULT_COORD_WIDTH {
+        length / 3
+    } else {
+        DEFAULT_COORD_WIDTH
+    }
+}
+
+fn parse_atom_line_fixed(line: &str) -> Result<Line<'_>, ParseLineError> {
+    parse_atom_line_fixed_with_widths(line, DEFAULT_INDEX_WIDTH, DEFAULT_COORD_WIDTH)
+}
+
+fn parse_atom_line_fixed_with_widths(
+    line: &str,
+    index_width: usize,
+    coord_width: usize,
+) -> Result<Line<'_>, ParseLineError> {
+    let min_line_len = get_velocity_start(index_width, coord_width);
+    if line.len() < min_line_len {
         return Err(ParseLineError);
     }
 
-    // let residue_number = line[0..5].trim().parse::<usize>().map_err(|_| ParseLineError)?;
-    let residue_name = line[5..10].trim();
-    let atom_name = line[10..15].trim();
+    let residue_name_start = index_width;
+    let atom_name_start = residue_name_start + 5;
+    let position_start = get_position_start(index_width);
+
+    // Slice with `get` since a multibyte character in a name column would otherwise
+    // make us index into the middle of a character and panic
+    let residue_number = line.get(..residue_name_start).ok_or(ParseLineError)?.trim();
+    let residue_name = line
+        .get(residue_name_start..atom_name_start)
+        .ok_or(ParseLineError)?
+        .trim();
+    let atom_name = line
+        .get(atom_name_start..atom_name_start + 5)
+        .ok_or(ParseLineError)?
+        .trim();
     // let atom_number = line[15..20].trim().parse::<usize>().map_err(|_| ParseLineError)?;
 
-    let position = RVec::from_fixed(&line[20..], 8).map_err(|_| ParseLineError)?;
-    let velocity = match RVec::from_fixed(&line[44..], 8) {
+    let position_str = line.get(position_start..).ok_or(ParseLineError)?;
+    let velocity_str = line.get(min_line_len..).ok_or(ParseLineError)?;
+
+    let position = RVec::from_fixed(position_str, coord_width).map_err(|_| ParseLineError)?;
+    let velocity = match RVec::from_fixed(velocity_str, coord_width) {
         Ok(rvec) => Some(rvec),
         Err(ParseRVecError::MissingValues) => None,
         _ => return Err(ParseLineError),
     };
 
     Ok(Line {
-        // residue_number,
+        residue_number,
         residue_name,
         atom_name,
         // atom_number,
@@ -189,6 +739,87 @@ fn parse_atom_line(line: &str) -> Result<Line, ParseLineError> {
     })
 }
 
+/// Parse an atom line, treating a velocity field which is truncated or cannot be parsed
+/// as absent. A description of the discarded field is returned along with the line.
+fn parse_atom_line_lenient_with_widths(
+    line: &str,
+    index_width: usize,
+    coord_width: usize,
+) -> Result<(Line<'_>, Option<&'static str>), ParseLineError> {
+    let velocity_start = get_velocity_start(index_width, coord_width);
+    let velocity_field = line.get(velocity_start..).unwrap_or("").trim();
+
+    if let Ok(atom_line) = parse_atom_line_fixed_with_widths(line, index_width, coord_width) {
+        let warning = if atom_line.velocity.is_none() && !velocity_field.is_empty() {
+            Some("velocity field truncated, treated as absent")
+        } else {
+            None
+        };
+
+        return Ok((atom_line, warning));
+    }
+
+    if let Ok(atom_line) = parse_atom_line_whitespace(line) {
+        return Ok((atom_line, None));
+    }
+
+    let position_fields = line.get(..velocity_start).ok_or(ParseLineError)?;
+    let atom_line = parse_atom_line_fixed_with_widths(position_fields, index_width, coord_width)?;
+
+    Ok((
+        atom_line,
+        Some("velocity field could not be parsed, treated as absent"),
+    ))
+}
+
+/// Parse an atom line with whitespace separated fields, as written by some tools.
+///
+/// The fields are the residue number, residue name, atom name and atom number, followed
+/// by the position and optionally the velocity. As in the fixed format the residue number
+/// may be joined with the residue name (eg. `1SOL`).
+fn parse_atom_line_whitespace(line: &str) -> Result<Line<'_>, ParseLineError> {
+    let fields = line.split_whitespace().collect::<Vec<_>>();
+
+    let (residue_number, residue_name, num_index_fields) = match fields.len() {
+        7 | 10 => {
+            fields[0].parse::<usize>().map_err(|_| ParseLineError)?;
+            (fields[0], fields[1], 4)
+        }
+        6 | 9 => {
+            let residue_name = fields[0].trim_start_matches(|c: char| c.is_ascii_digit());
+            let residue_number = &fields[0][..fields[0].len() - residue_name.len()];
+
+            (residue_number, residue_name, 3)
+        }
+        _ => return Err(ParseLineError),
+    };
+
+    let atom_name = fields[num_index_fields - 2];
+    fields[num_index_fields - 1]
+        .parse::<usize>()
+        .map_err(|_| ParseLineError)?;
+
+    if residue_name.is_empty() {
+        return Err(ParseLineError);
+    }
+
+    let values = &fields[num_index_fields..];
+    let position = RVec::from_whitespace(&values[..3].join(" ")).map_err(|_| ParseLineError)?;
+    let velocity = if values.len() == 6 {
+        Some(RVec::from_whitespace(&values[3..].join(" ")).map_err(|_| ParseLineError)?)
+    } else {
+        None
+    };
+
+    Ok(Line {
+        residue_number,
+        residue_name,
+        atom_name,
+        position,
+        velocity,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,6 +828,10 @@ mod tests {
     use std::io::Cursor;
     use std::rc::Rc;
 
+    fn parse_atom_line(line: &str) -> Result<Line<'_>, ParseLineError> {
+        parse_atom_line_with_widths(line, DEFAULT_INDEX_WIDTH, DEFAULT_COORD_WIDTH)
+    }
+
     #[test]
     fn parse_atom_line_errors() {
         // Too-short strings
@@ -219,7 +854,7 @@ mod tests {
     fn parse_correct_atom_lines() {
         let s = "    1RES   ATOM1    1000.0012000.0023000.003";
         let line = parse_atom_line(s).unwrap();
-        // assert_eq!(line.residue_number, 1);
+        assert_eq!(line.residue_number, "1");
         // assert_eq!(line.atom_number, 1);
         assert_eq!(line.residue_name, "RES");
         assert_eq!(line.atom_name, "ATOM");
@@ -235,7 +870,7 @@ mod tests {
 
         let s = "    12RES12ATO150001 100.01  200.02  300.03  400.04  500.05  600.06 ";
         let line = parse_atom_line(s).unwrap();
-        // assert_eq!(line.residue_number, 1);
+        assert_eq!(line.residue_number, "1");
         // assert_eq!(line.atom_number, 50001);
         assert_eq!(line.residue_name, "2RES1");
         assert_eq!(line.atom_name, "2ATO1");
@@ -492,6 +1127,11 @@ mod tests {
                         y: 0.1,
                         z: 0.2,
                     }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 },
                 // Residue 1
                 Atom {
@@ -507,6 +1147,11 @@ mod tests {
                         y: 0.4,
                         z: 0.5,
                     }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 },
             ],
         };
@@ -611,6 +1256,11 @@ mod tests {
                         z: 2.0,
                     },
                     velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 };
                 100_000
             ],
@@ -649,7 +1299,11 @@ mod tests {
         let conf = Conf {
             title: "A title".to_string(),
             origin: RVec::default(),
-            size: RVec::default(),
+            size: RVec {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
             residues: residues.clone(),
             atoms: vec![
                 Atom {
@@ -665,6 +1319,11 @@ mod tests {
                         y: 0.1,
                         z: 0.2,
                     }),
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
                 },
             ],
         };
@@ -696,4 +1355,679 @@ mod tests {
             assert_eq!(parts[1].len(), 4);
         }
     }
+
+    #[test]
+    fn read_file_with_crlf_line_endings() {
+        let content = "\
+A title
+3
+    1RES1   AT1    1   0.000   1.000   2.000  0.0000  0.1000  0.3000
+    1RES1   AT2    2   3.000   4.000   5.000
+    2RES2   AT3    3   6.000   7.000   8.000  0.6000  0.7000  0.8000
+  10.00000  11.00000  12.00000
+";
+        let content_crlf = content.replace("\n", "\r\n");
+
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+        let conf_crlf = read_gromos87_conf(content_crlf.as_bytes()).unwrap();
+
+        assert!(conf.atoms[0].velocity.is_some());
+        assert!(conf.atoms[1].velocity.is_none());
+
+        assert_eq!(conf_crlf.title, conf.title);
+        assert_eq!(conf_crlf.size, conf.size);
+        assert_eq!(conf_crlf.residues.len(), conf.residues.len());
+        assert_eq!(conf_crlf.atoms.len(), conf.atoms.len());
+
+        for (atom_crlf, atom) in conf_crlf.atoms.iter().zip(conf.atoms.iter()) {
+            assert_eq!(*atom_crlf.name.borrow(), *atom.name.borrow());
+            assert_eq!(
+                *atom_crlf.residue.borrow().name,
+                *atom.residue.borrow().name
+            );
+            assert_eq!(atom_crlf.position, atom.position);
+            assert_eq!(atom_crlf.velocity, atom.velocity);
+        }
+    }
+
+    #[test]
+    fn parse_whitespace_separated_atom_lines() {
+        let fixed = format!(
+            "{:>5}{:<5}{:>5}{:>5}{:>8.3}{:>8.3}{:>8.3}{:>8.4}{:>8.4}{:>8.4}",
+            1, "RES", "AT1", 1, 1.0, 2.0, 3.0, 0.1, 0.2, 0.3
+        );
+        let expected = parse_atom_line(&fixed).unwrap();
+
+        for s in &[
+            "1 RES AT1 1 1.000 2.000 3.000 0.1000 0.2000 0.3000",
+            "1RES AT1 1 1.0 2.0 3.0 0.1 0.2 0.3",
+            "\t1  RES\tAT1  1    1.0  2.0  3.0   0.1  0.2  0.3",
+        ] {
+            let line = parse_atom_line(s).unwrap();
+            assert_eq!(line.residue_name, expected.residue_name);
+            assert_eq!(line.atom_name, expected.atom_name);
+            assert_eq!(line.position, expected.position);
+            assert_eq!(line.velocity, expected.velocity);
+        }
+
+        let line = parse_atom_line("1 RES AT1 1 1.0 2.0 3.0").unwrap();
+        assert_eq!(line.residue_name, "RES");
+        assert_eq!(line.atom_name, "AT1");
+        assert_eq!(line.position, expected.position);
+        assert_eq!(line.velocity, None);
+
+        // Wrong number of fields or bad values
+        assert!(parse_atom_line("1 RES AT1 1 1.0 2.0").is_err());
+        assert!(parse_atom_line("1 RES AT1 1 1.0 2.0 3.0 0.1").is_err());
+        assert!(parse_atom_line("1 RES AT1 1 1.0 s 3.0").is_err());
+        assert!(parse_atom_line("s RES AT1 1 1.0 2.0 3.0").is_err());
+        assert!(parse_atom_line("1 AT1 1 1.0 2.0 3.0").is_err());
+    }
+
+    #[test]
+    fn read_file_with_leading_blank_lines() {
+        let content = "\n\n\
+A title
+2
+    1RES1   AT1    1   0.000   1.000   2.000
+    2RES2   AT2    2   3.000   4.000   5.000
+  10.00000  11.00000  12.00000
+";
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.title, "A title");
+        assert_eq!(conf.atoms.len(), 2);
+        assert_eq!(
+            conf.size,
+            RVec {
+                x: 10.0,
+                y: 11.0,
+                z: 12.0,
+            }
+        );
+    }
+
+    #[test]
+    fn read_file_with_empty_title() {
+        let content = "\n\
+2
+    1RES1   AT1    1   0.000   1.000   2.000
+    2RES2   AT2    2   3.000   4.000   5.000
+  10.00000  11.00000  12.00000
+";
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.title, "");
+        assert_eq!(conf.atoms.len(), 2);
+    }
+
+    #[test]
+    fn read_box_size_line_with_a_single_value_as_a_cube() {
+        let atom_line = "    1RES1   AT1    1   0.000   1.000   2.000";
+
+        let content = format!("{}\n{}\n{}\n{}\n", "A cube", 1, atom_line, "   5.00000");
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+        assert_eq!(
+            conf.size,
+            RVec {
+                x: 5.0,
+                y: 5.0,
+                z: 5.0,
+            }
+        );
+
+        let content = format!("{}\n{}\n{}\n{}\n", "Two values", 1, atom_line, "5.0 6.0");
+        assert!(read_gromos87_conf(content.as_bytes()).is_err());
+
+        let content = format!("{}\n{}\n{}\n{}\n", "Bad value", 1, atom_line, "5.s");
+        assert!(read_gromos87_conf(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_box_line_with_three_values_into_diagonal_matrix() {
+        assert_eq!(
+            parse_box_line("   1.00000   2.00000   3.00000").unwrap(),
+            [[1.0, 0.0, 0.0], [0.0, 2.0, 0.0], [0.0, 0.0, 3.0]]
+        );
+    }
+
+    #[test]
+    fn parse_box_line_with_nine_values_into_full_matrix() {
+        let line = "1.0 2.0 3.0 0.1 0.2 0.3 0.4 0.5 0.6";
+
+        assert_eq!(
+            parse_box_line(line).unwrap(),
+            [[1.0, 0.1, 0.2], [0.3, 2.0, 0.4], [0.5, 0.6, 3.0]]
+        );
+    }
+
+    #[test]
+    fn parse_box_line_with_bad_number_of_values_gives_error() {
+        assert_eq!(
+            parse_box_line("5.0"),
+            Err(ParseRVecError::WrongNumberOfValues(1))
+        );
+        assert_eq!(
+            parse_box_line("1.0 2.0 3.0 4.0"),
+            Err(ParseRVecError::WrongNumberOfValues(4))
+        );
+        assert_eq!(parse_box_line(""), Err(ParseRVecError::MissingValues));
+        assert_eq!(
+            parse_box_line("1.0 2.0 3.s"),
+            Err(ParseRVecError::ParseFloatError)
+        );
+    }
+
+    #[test]
+    fn read_conf_with_detected_coordinate_width() {
+        let standard = "\
+A title
+2
+    1SOL     OW    1   1.250   2.500  -3.125  0.1000 -0.2000  0.3000
+    1SOL    HW1    2   0.100   0.000   0.000  0.0000  0.0000  0.0000
+   3.00000   3.00000   3.00000
+";
+        let wide = "\
+A title
+2
+    1SOL     OW    1    1.2500    2.5000   -3.1250    0.1000   -0.2000    0.3000
+    1SOL    HW1    2    0.1000    0.0000    0.0000    0.0000    0.0000    0.0000
+   3.00000   3.00000   3.00000
+";
+        let wide_without_velocities = "\
+A title
+2
+    1SOL     OW    1    1.2500    2.5000   -3.1250
+    1SOL    HW1    2    0.1000    0.0000    0.0000
+   3.00000   3.00000   3.00000
+";
+
+        let options = Gromos87ReadOptions {
+            detect_coord_width: true,
+            ..Gromos87ReadOptions::default()
+        };
+
+        let expected = read_gromos87_conf(standard.as_bytes()).unwrap();
+
+        for content in &[standard, wide, wide_without_velocities] {
+            let conf = read_gromos87_conf_with_options(content.as_bytes(), &options).unwrap();
+            assert_eq!(conf.atoms.len(), 2);
+
+            for (atom, expected_atom) in conf.atoms.iter().zip(expected.atoms.iter()) {
+                assert_eq!(atom.position, expected_atom.position);
+            }
+        }
+
+        let conf = read_gromos87_conf_with_options(wide.as_bytes(), &options).unwrap();
+        assert_eq!(conf.atoms[0].velocity, expected.atoms[0].velocity);
+
+        let line = wide.lines().nth(2).unwrap();
+        assert_eq!(detect_coord_width(line, 5), 10);
+        assert_eq!(
+            parse_atom_line_fixed_with_widths(line, 5, 10)
+                .unwrap()
+                .position,
+            expected.atoms[0].position
+        );
+
+        let line = wide_without_velocities.lines().nth(2).unwrap();
+        assert_eq!(detect_coord_width(line, 5), 10);
+
+        assert_eq!(detect_coord_width(standard.lines().nth(2).unwrap(), 5), 8);
+        assert_eq!(detect_coord_width("    1SOL     OW    1   1.2", 5), 8);
+    }
+
+    #[test]
+    fn read_conf_splitting_residues_by_number() {
+        let content = "\
+Two residues with the same name
+4
+    1MOL      A    1   0.000   0.000   0.000
+    1MOL      B    2   0.100   0.000   0.000
+    2MOL      C    3   0.200   0.000   0.000
+    2MOL      D    4   0.300   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+        assert_eq!(conf.residues.len(), 1);
+        assert_eq!(conf.residues[0].borrow().atoms.len(), 4);
+
+        let options = Gromos87ReadOptions {
+            split_residues_by_number: true,
+            ..Gromos87ReadOptions::default()
+        };
+
+        let conf = read_gromos87_conf_with_options(content.as_bytes(), &options).unwrap();
+        assert_eq!(conf.residues.len(), 2);
+        assert!(Rc::ptr_eq(&conf.atoms[0].residue, &conf.residues[0]));
+        assert!(Rc::ptr_eq(&conf.atoms[1].residue, &conf.residues[0]));
+        assert!(Rc::ptr_eq(&conf.atoms[2].residue, &conf.residues[1]));
+        assert!(Rc::ptr_eq(&conf.atoms[3].residue, &conf.residues[1]));
+
+        for (residue, names) in conf.residues.iter().zip(&[["A", "B"], ["C", "D"]]) {
+            assert!(residue.borrow().cmp_name("MOL"));
+
+            let atom_names = residue
+                .borrow()
+                .atoms
+                .iter()
+                .map(|name| name.borrow().clone())
+                .collect::<Vec<_>>();
+            assert_eq!(atom_names, names);
+        }
+
+        assert!(conf.iter_residues().all(|residue| residue.is_ok()));
+    }
+
+    #[test]
+    fn write_subset_of_atoms_to_conf() {
+        let content = "\
+A title
+7
+    1SOL     OW    1   0.000   0.000   0.000
+    1SOL    HW1    2   0.100   0.000   0.000
+    1SOL    HW2    3   0.000   0.100   0.000
+    2SOL     OW    4   1.000   0.000   0.000
+    2SOL    HW1    5   1.100   0.000   0.000
+    2SOL    HW2    6   1.000   0.100   0.000
+    3NA      NA    7   2.000   0.000   0.000
+   3.00000   3.00000   3.00000
+";
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        let mut buf = Vec::new();
+        write_gromos87_subset(&conf, &[6, 2], &mut buf).unwrap();
+
+        let subset = read_gromos87_conf(buf.as_slice()).unwrap();
+        assert_eq!(subset.title, conf.title);
+        assert_eq!(subset.size, conf.size);
+        assert_eq!(subset.atoms.len(), 2);
+        assert!(subset.atoms[0].cmp_name("NA"));
+        assert_eq!(subset.atoms[0].position, conf.atoms[6].position);
+        assert!(subset.atoms[1].cmp_name("HW2"));
+        assert_eq!(subset.atoms[1].position, conf.atoms[2].position);
+
+        let output = String::from_utf8(buf).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(&lines[2][..20], "    1NA      NA    1");
+        assert_eq!(&lines[3][..20], "    2SOL    HW2    2");
+
+        // Atoms of the same residue are grouped, but not atoms of separate residues
+        let mut buf = Vec::new();
+        write_gromos87_subset(&conf, &[0, 2, 5], &mut buf).unwrap();
+
+        let output = String::from_utf8(buf).unwrap();
+        let residue_numbers = output
+            .lines()
+            .skip(2)
+            .take(3)
+            .map(|line| line[..5].trim().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(residue_numbers, vec!["1", "1", "2"]);
+
+        match write_gromos87_subset(&conf, &[0, 7], &mut Vec::new()) {
+            Err(WriteError::BadIndex(7)) => (),
+            _ => panic!("expected a bad index error"),
+        }
+    }
+
+    #[test]
+    fn write_conf_with_invalid_box_size_gives_error_unless_unchecked() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        let mut conf = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        let mut buf = Vec::new();
+        assert!(write_gromos87_conf(&conf, &mut buf).is_ok());
+
+        conf.size.y = 0.0;
+
+        let mut buf = Vec::new();
+        match write_gromos87_conf(&conf, &mut buf) {
+            Err(WriteError::InvalidBox(size)) => assert_eq!(size, conf.size),
+            _ => panic!("expected an invalid box error"),
+        }
+        assert!(buf.is_empty());
+
+        conf.size.y = -1.0;
+        assert!(write_gromos87_conf(&conf, &mut Vec::new()).is_err());
+
+        let options = Gromos87WriteOptions {
+            check_box_size: false,
+            ..Gromos87WriteOptions::default()
+        };
+        assert!(write_gromos87_conf_with_options(&conf, &mut Vec::new(), &options).is_ok());
+    }
+
+    #[test]
+    fn write_conf_skipping_bad_residues() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("AT2".to_string())),
+                ],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
+        ];
+
+        let get_atom = |residue: usize, atom: usize, x: f64| Atom {
+            name: Rc::clone(&residues[residue].borrow().atoms[atom]),
+            residue: Rc::clone(&residues[residue]),
+            position: RVec { x, y: 0.0, z: 0.0 },
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        };
+
+        // The first residue is interleaved with the second
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                get_atom(0, 0, 0.0),
+                get_atom(1, 0, 1.0),
+                get_atom(0, 1, 2.0),
+                get_atom(0, 0, 3.0),
+                get_atom(0, 1, 4.0),
+            ],
+        };
+
+        let mut buf = Vec::new();
+        assert!(write_gromos87_conf(&conf, &mut buf).is_err());
+
+        let options = Gromos87WriteOptions {
+            skip_bad_residues: true,
+            ..Gromos87WriteOptions::default()
+        };
+
+        let mut buf = Vec::new();
+        write_gromos87_conf_with_options(&conf, &mut buf, &options).unwrap();
+
+        let read_conf = read_gromos87_conf(buf.as_slice()).unwrap();
+
+        assert_eq!(read_conf.atoms.len(), 3);
+        assert!(read_conf.atoms[0].cmp_residue_name("RES2"));
+        assert_eq!(read_conf.atoms[0].position.x, 1.0);
+        assert!(read_conf.atoms[1].cmp_name("AT1"));
+        assert_eq!(read_conf.atoms[1].position.x, 3.0);
+        assert!(read_conf.atoms[2].cmp_name("AT2"));
+        assert_eq!(read_conf.atoms[2].position.x, 4.0);
+        assert!(read_conf.iter_residues().all(|residue| residue.is_ok()));
+    }
+
+    #[test]
+    fn read_title_and_count_leaves_reader_at_first_atom() {
+        let content = "\
+A title
+2
+    1RES1   AT1    1   0.000   1.000   2.000
+    2RES2   AT2    2   3.000   4.000   5.000
+  10.00000  11.00000  12.00000
+";
+        let mut reader = content.as_bytes();
+
+        let (title, num_atoms) = read_gromos87_title_and_count(&mut reader).unwrap();
+        assert_eq!(title, "A title");
+        assert_eq!(num_atoms, 2);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "    1RES1   AT1    1   0.000   1.000   2.000\n");
+
+        assert!(read_gromos87_title_and_count(&mut "A title\nnot a count\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn multibyte_character_in_name_columns_gives_error_instead_of_panic() {
+        // The two-byte character straddles the boundary between the residue and atom name
+        let line = "    1SOLWÑOW    1   0.000   0.000   0.000";
+        assert!(parse_atom_line(line).is_err());
+
+        let content = format!("A title\n1\n{}\n   1.00000   1.00000   1.00000\n", line);
+        assert!(read_gromos87_conf(&mut content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_file_leniently_returns_warnings_for_bad_velocities() {
+        let content = "\
+A title
+3
+    1SOL     OW    1   0.000   0.000   0.000  1.0000  2.0000  3.0000
+    1SOL    HW1    2   0.100   0.000   0.000  1.0000  2.0000
+    1SOL    HW2    3   0.000   0.100   0.000  1.0000  2.00s0  3.0000
+   3.00000   3.00000   3.00000
+";
+
+        assert!(read_gromos87_conf(content.as_bytes()).is_err());
+
+        let (conf, warnings) = read_gromos87_conf_lenient(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.atoms.len(), 3);
+        assert!(conf.atoms[0].velocity.is_some());
+        assert!(conf.atoms[1].velocity.is_none());
+        assert!(conf.atoms[2].velocity.is_none());
+        assert_eq!(
+            conf.atoms[2].position,
+            RVec {
+                x: 0.0,
+                y: 0.1,
+                z: 0.0,
+            }
+        );
+
+        assert_eq!(
+            warnings,
+            vec![
+                "line 4: velocity field truncated, treated as absent".to_string(),
+                "line 5: velocity field could not be parsed, treated as absent".to_string(),
+            ]
+        );
+
+        // A file without any issues gives no warnings
+        let content = "A title\n1\n    1SOL     OW    1   0.000   0.000   0.000\n 1.0 1.0 1.0\n";
+        let (_, warnings) = read_gromos87_conf_lenient(content.as_bytes()).unwrap();
+        assert!(warnings.is_empty());
+
+        // Missing the box size line cannot be recovered from
+        let content = "A title\n1\n    1SOL     OW    1   0.000   0.000   0.000\n";
+        assert!(read_gromos87_conf_lenient(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn write_and_read_frames_of_a_trajectory() {
+        let content = "\
+A title
+2
+    1RES1   AT1    1   0.000   1.000   2.000  0.1000  0.2000  0.3000
+    2RES2   AT2    2   3.000   4.000   5.000
+  10.00000  11.00000  12.00000
+";
+        let frame1 = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        let mut frame2 = frame1.clone();
+        frame2.title = "Another title".to_string();
+        frame2.atoms[1].position.x += 1.0;
+
+        let mut buf = Vec::new();
+
+        {
+            let mut writer = Gromos87FrameWriter::new(&mut buf);
+            writer.write_frame(&frame1).unwrap();
+            writer.write_frame(&frame2).unwrap();
+        }
+
+        let frames = Gromos87FrameReader::new(buf.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(frames.len(), 2);
+
+        for (frame, expected) in frames.iter().zip([frame1, frame2].iter()) {
+            assert_eq!(frame.title, expected.title);
+            assert_eq!(frame.size, expected.size);
+            assert_eq!(frame.atoms.len(), expected.atoms.len());
+
+            for (atom, expected_atom) in frame.atoms.iter().zip(expected.atoms.iter()) {
+                assert_eq!(*atom.name.borrow(), *expected_atom.name.borrow());
+                assert!(atom.position.distance(&expected_atom.position) < 1e-9);
+                assert_eq!(atom.velocity, expected_atom.velocity);
+            }
+        }
+
+        // A bad frame ends the iteration after yielding its error
+        let content = format!("{}A title\nnot a count\n", content);
+        let mut reader = Gromos87FrameReader::new(content.as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn trailing_blank_lines_after_the_last_frame_are_not_read_as_a_frame() {
+        let frame = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        // The second frame has an empty title, which must not be skipped as blank
+        let untitled = format!("\n{}", &frame[8..]);
+
+        for trailing in &["\n", "   \n\n", "\n  "] {
+            let content = format!("{}{}{}", frame, untitled, trailing);
+
+            let frames = Gromos87FrameReader::new(content.as_bytes())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].title, "A title");
+            assert_eq!(frames[1].title, "");
+            assert_eq!(frames[1].atoms.len(), 1);
+        }
+    }
+
+    #[test]
+    fn read_every_second_frame_of_a_trajectory() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000
+   1.00000   1.00000   1.00000
+";
+        let conf = read_gromos87_conf(content.as_bytes()).unwrap();
+
+        let mut buf = Vec::new();
+
+        {
+            let mut writer = Gromos87FrameWriter::new(&mut buf);
+
+            for i in 0..4 {
+                let mut frame = conf.clone();
+                frame.title = format!("Frame {}", i);
+                writer.write_frame(&frame).unwrap();
+            }
+        }
+
+        let frames = read_gromos87_frames_strided(buf.as_slice(), 2).unwrap();
+        let titles = frames
+            .iter()
+            .map(|conf| conf.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, vec!["Frame 0", "Frame 2"]);
+
+        let frames = read_gromos87_frames_strided(buf.as_slice(), 0).unwrap();
+        assert_eq!(frames.len(), 4);
+
+        // Skipped frames must also be valid
+        let content = format!("{}A title\nnot a count\n", content);
+        assert!(read_gromos87_frames_strided(content.as_bytes(), 2).is_err());
+    }
+
+    #[test]
+    fn write_and_read_conf_with_wide_index_columns_without_wrapping() {
+        let content = "\
+A title
+1
+    1SOL     OW    1   0.000   0.000   0.000  0.1000  0.2000  0.3000
+   0.10000   0.10000   0.10000
+";
+        let template = read_gromos87_conf(content.as_bytes()).unwrap();
+        let conf = Conf::from_template_lattice(&template, 50, 50, 60, template.size);
+        assert_eq!(conf.atoms.len(), 150_000);
+
+        let write_options = Gromos87WriteOptions {
+            index_width: 7,
+            ..Gromos87WriteOptions::default()
+        };
+
+        let mut buf = Vec::new();
+        write_gromos87_conf_with_options(&conf, &mut buf, &write_options).unwrap();
+
+        let output = String::from_utf8(buf).unwrap();
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.nth(2).unwrap(),
+            "      1SOL     OW      1   0.000   0.000   0.000  0.1000  0.2000  0.3000"
+        );
+        assert_eq!(
+            lines.nth(149_998).unwrap(),
+            " 150000SOL     OW 150000   4.900   4.900   5.900  0.1000  0.2000  0.3000"
+        );
+
+        let read_options = Gromos87ReadOptions {
+            index_width: 7,
+            ..Gromos87ReadOptions::default()
+        };
+        let read_conf = read_gromos87_conf_with_options(output.as_bytes(), &read_options).unwrap();
+
+        assert_eq!(read_conf.atoms.len(), conf.atoms.len());
+        assert_eq!(read_conf.residues.len(), 1);
+        assert!(read_conf.atoms[149_999].cmp_name("OW"));
+        assert!(read_conf.atoms[149_999].cmp_residue_name("SOL"));
+
+        let (atom, expected) = (&read_conf.atoms[149_999], &conf.atoms[149_999]);
+        assert!(atom.position.distance(&expected.position) < 1e-9);
+        assert_eq!(atom.velocity, expected.velocity);
+    }
+
+    #[test]
+    fn parse_atom_line_with_wide_index_columns() {
+        let line = "      1RES  ATOM1      1   1.000   2.000   3.000  0.1000  0.2000  0.3000";
+        let atom_line = parse_atom_line_fixed_with_widths(line, 7, 8).unwrap();
+
+        assert_eq!(atom_line.residue_name, "RES");
+        assert_eq!(atom_line.atom_name, "ATOM1");
+        assert_eq!(
+            atom_line.position,
+            RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            }
+        );
+        assert!(atom_line.velocity.is_some());
+
+        // The standard columns do not match the wide layout
+        assert!(parse_atom_line_fixed(line).is_err());
+    }
 }
@@ -0,0 +1,201 @@
+use rvec::RVec;
+
+/// Relative tolerance for deciding whether points are coplanar.
+const TOLERANCE: f64 = 1e-10;
+
+/// Return the volume of the convex hull of a set of points.
+///
+/// The hull is constructed with the incremental algorithm, starting from a
+/// tetrahedron of four non-coplanar points. If there are no such points the
+/// volume is zero.
+pub fn convex_hull_volume(points: &[RVec]) -> f64 {
+    let first = match points.first() {
+        Some(&point) => point,
+        None => return 0.0,
+    };
+
+    let scale = points
+        .iter()
+        .map(|point| point.distance(&first))
+        .fold(0.0, f64::max);
+    let eps = TOLERANCE * scale.powi(3);
+
+    let (i0, i1, i2, i3) = match find_tetrahedron(points, scale) {
+        Some(indices) => indices,
+        None => return 0.0,
+    };
+
+    let interior = (points[i0] + points[i1] + points[i2] + points[i3]) / 4.0;
+
+    // Faces are oriented such that their normals point out of the hull
+    let orient = |a: usize, b: usize, c: usize| {
+        if normal(points, a, b, c).dot(&(interior - points[a])) > 0.0 {
+            (a, c, b)
+        } else {
+            (a, b, c)
+        }
+    };
+
+    let mut faces = vec![
+        orient(i0, i1, i2),
+        orient(i0, i1, i3),
+        orient(i0, i2, i3),
+        orient(i1, i2, i3),
+    ];
+
+    for (i, point) in points.iter().enumerate() {
+        let (visible, hidden): (Vec<_>, Vec<_>) = faces
+            .iter()
+            .partition(|&&(a, b, c)| normal(points, a, b, c).dot(&(*point - points[a])) > eps);
+
+        if visible.is_empty() {
+            continue;
+        }
+
+        // The horizon consists of the edges of visible faces which are not shared
+        // with another visible face
+        let edges = visible
+            .iter()
+            .flat_map(|&(a, b, c)| vec![(a, b), (b, c), (c, a)])
+            .collect::<Vec<_>>();
+
+        faces = hidden;
+
+        for &(a, b) in &edges {
+            if !edges.contains(&(b, a)) {
+                faces.push((a, b, i));
+            }
+        }
+    }
+
+    faces
+        .iter()
+        .map(|&(a, b, c)| {
+            let (ra, rb, rc) = (
+                points[a] - interior,
+                points[b] - interior,
+                points[c] - interior,
+            );
+
+            ra.dot(&rb.cross(&rc)) / 6.0
+        })
+        .sum()
+}
+
+/// Return the (unnormalized) normal of the triangle with the given corners.
+fn normal(points: &[RVec], a: usize, b: usize, c: usize) -> RVec {
+    (points[b] - points[a]).cross(&(points[c] - points[a]))
+}
+
+/// Find the indices of four points which span a tetrahedron of non-zero volume,
+/// relative to the length scale of the points.
+fn find_tetrahedron(points: &[RVec], scale: f64) -> Option<(usize, usize, usize, usize)> {
+    let i0 = 0;
+    let (i1, _) = argmax(points, |point| point.distance(&points[i0]));
+
+    let edge = points[i1] - points[i0];
+    let (i2, area) = argmax(points, |point| edge.cross(&(*point - points[i0])).norm());
+
+    let face_normal = normal(points, i0, i1, i2);
+    let (i3, volume) = argmax(points, |point| {
+        face_normal.dot(&(*point - points[i0])).abs()
+    });
+
+    if area <= TOLERANCE * scale.powi(2) || volume <= TOLERANCE * scale.powi(3) {
+        None
+    } else {
+        Some((i0, i1, i2, i3))
+    }
+}
+
+/// Return the index of the point which maximizes a function, along with the value.
+fn argmax(points: &[RVec], func: impl Fn(&RVec) -> f64) -> (usize, f64) {
+    let mut max = (0, 0.0);
+
+    for (i, point) in points.iter().enumerate() {
+        let value = func(point);
+
+        if value > max.1 {
+            max = (i, value);
+        }
+    }
+
+    max
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn volume_of_tetrahedron_with_interior_and_coplanar_points() {
+        let mut points = vec![
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+        ];
+
+        assert!((convex_hull_volume(&points) - 1.0 / 6.0).abs() < 1e-9);
+
+        // Points inside the hull or on its faces do not change the volume
+        points.push(RVec {
+            x: 0.1,
+            y: 0.1,
+            z: 0.1,
+        });
+        points.push(RVec {
+            x: 0.5,
+            y: 0.5,
+            z: 0.0,
+        });
+
+        assert!((convex_hull_volume(&points) - 1.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn volume_of_coplanar_points_is_zero() {
+        let points = vec![
+            RVec {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            RVec {
+                x: 1.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ];
+
+        assert_eq!(convex_hull_volume(&points), 0.0);
+        assert_eq!(convex_hull_volume(&points[..2]), 0.0);
+        assert_eq!(convex_hull_volume(&[]), 0.0);
+    }
+}
@@ -3,11 +3,40 @@
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
+extern crate flate2;
+#[cfg(feature = "nalgebra")]
+extern crate nalgebra;
+#[cfg(feature = "ndarray")]
+extern crate ndarray;
+#[cfg(feature = "rand")]
+extern crate rand;
 
+mod cells;
+mod charmm;
 mod conf;
+mod element;
 mod error;
+mod format;
 mod gromos87;
+mod hull;
+mod linalg;
+mod pdb;
 mod rvec;
+mod xyz;
 
-pub use conf::{get_or_insert_atom_and_residue, Atom, Conf, Residue, ResidueIter};
-pub use rvec::RVec;
+pub use charmm::read_charmm_crd;
+pub use conf::{
+    box_is_orthorhombic, box_volume, count_atoms_gromos87, get_or_insert_atom_and_residue, Atom,
+    Conf, Residue, ResidueIter, NM_PER_PS_TO_ANGSTROM_PER_FS, NM_PER_PS_TO_ANGSTROM_PER_PS,
+    NM_PER_PS_TO_M_PER_S,
+};
+pub use element::guess_element;
+pub use error::{ReadError, WriteError};
+pub use format::{sniff_format, ConfFormat, DetectedFormat, Gromos87, Pdb, Xyz};
+pub use gromos87::{
+    parse_box_line, read_gromos87_conf_lenient, read_gromos87_conf_with_options,
+    read_gromos87_frames_strided, read_gromos87_title_and_count, write_gromos87_conf_with_options,
+    write_gromos87_subset, Gromos87FrameReader, Gromos87FrameWriter, Gromos87ReadOptions,
+    Gromos87WriteOptions,
+};
+pub use rvec::{Direction, ParseRVecError, RVec};
@@ -0,0 +1,107 @@
+/// Maximum number of sweeps over the off-diagonal elements in the Jacobi method.
+const MAX_SWEEPS: usize = 50;
+
+/// Compute the eigenvalues and eigenvectors of a symmetric 3x3 matrix using the
+/// cyclic Jacobi method.
+///
+/// The eigenvalues are returned in increasing order, with their normalized
+/// eigenvectors as the corresponding columns of the returned matrix. A matrix
+/// with NaN elements gives NaN eigenvalues, which are ordered by `f64::total_cmp`.
+pub fn symmetric_eigen(matrix: &[[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut a = *matrix;
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    let norm: f64 = a.iter().flat_map(|row| row.iter()).map(|x| x.powi(2)).sum();
+
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal = a[0][1].powi(2) + a[0][2].powi(2) + a[1][2].powi(2);
+
+        if off_diagonal <= f64::EPSILON.powi(2) * norm {
+            break;
+        }
+
+        for &(p, q) in &[(0, 1), (0, 2), (1, 2)] {
+            if a[p][q] == 0.0 {
+                continue;
+            }
+
+            // Rotate the matrix to eliminate the element at (p, q)
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta.powi(2) + 1.0).sqrt());
+            let c = 1.0 / (t.powi(2) + 1.0).sqrt();
+            let s = t * c;
+
+            for k in 0..3 {
+                let (akp, akq) = (a[k][p], a[k][q]);
+                a[k][p] = c * akp - s * akq;
+                a[k][q] = s * akp + c * akq;
+
+                let (vkp, vkq) = (v[k][p], v[k][q]);
+                v[k][p] = c * vkp - s * vkq;
+                v[k][q] = s * vkp + c * vkq;
+            }
+
+            let (row_p, row_q) = (a[p], a[q]);
+
+            for (k, (apk, aqk)) in row_p.iter().zip(row_q.iter()).enumerate() {
+                a[p][k] = c * apk - s * aqk;
+                a[q][k] = s * apk + c * aqk;
+            }
+        }
+    }
+
+    let mut order = [0, 1, 2];
+    order.sort_by(|&i, &j| a[i][i].total_cmp(&a[j][j]));
+
+    let mut values = [0.0; 3];
+    let mut vectors = [[0.0; 3]; 3];
+
+    for (i, &j) in order.iter().enumerate() {
+        values[i] = a[j][j];
+
+        for k in 0..3 {
+            vectors[k][i] = v[k][j];
+        }
+    }
+
+    (values, vectors)
+}
+
+/// Return the determinant of a 3x3 matrix.
+pub fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eigen_decomposition_of_symmetric_matrix() {
+        let matrix = [[2.0, 1.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.0, 5.0]];
+
+        let (values, vectors) = symmetric_eigen(&matrix);
+
+        assert!((values[0] - 1.0).abs() < 1e-9);
+        assert!((values[1] - 3.0).abs() < 1e-9);
+        assert!((values[2] - 5.0).abs() < 1e-9);
+
+        // Every column satisfies A v = λ v
+        for i in 0..3 {
+            for k in 0..3 {
+                let av: f64 = (0..3).map(|j| matrix[k][j] * vectors[j][i]).sum();
+                assert!((av - values[i] * vectors[k][i]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn eigen_decomposition_of_matrix_with_nan_does_not_panic() {
+        let matrix = [[f64::NAN, 1.0, 0.0], [1.0, 2.0, 0.0], [0.0, 0.0, 5.0]];
+
+        let (values, _) = symmetric_eigen(&matrix);
+        assert!(values.iter().any(|value| value.is_nan()));
+    }
+}
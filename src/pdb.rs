@@ -0,0 +1,373 @@
+use conf::{get_or_insert_atom_and_residue, Atom, Conf};
+use rvec::RVec;
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Range;
+
+/// PDB files use Ångström, we use nm.
+const ANGSTROM_PER_NM: f64 = 10.0;
+
+pub fn write_pdb_conf<W: Write>(conf: &Conf, mut writer: &mut W) -> Result<(), WriteError> {
+    writeln!(&mut writer, "TITLE     {}", conf.title)?;
+
+    let size = conf.size * ANGSTROM_PER_NM;
+    writeln!(
+        &mut writer,
+        "CRYST1{:9.3}{:9.3}{:9.3}{:7.2}{:7.2}{:7.2} P 1           1",
+        size.x, size.y, size.z, 90.0, 90.0, 90.0
+    )?;
+
+    let mut atom_num = 0;
+
+    for (res_num, residue) in conf.iter_residues().enumerate() {
+        // PDB wraps residue indices at 4 digits and atom indices at 5 digits
+        let res_num_wrapped = (res_num + 1) % 10_000;
+
+        for atom in residue
+            .map_err(|_| WriteError::BadResidue(res_num + 1))?
+            .iter()
+        {
+            atom_num += 1;
+            let atom_num_wrapped = atom_num % 100_000;
+
+            let position = atom.position * ANGSTROM_PER_NM;
+
+            writeln!(
+                &mut writer,
+                "ATOM  {:>5} {:<4} {:<4} {:>4}    {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}",
+                atom_num_wrapped,
+                get_atom_name_field(&atom.name.borrow()),
+                atom.residue.borrow().name.borrow(),
+                res_num_wrapped,
+                position.x,
+                position.y,
+                position.z,
+                atom.occupancy.unwrap_or(1.0),
+                atom.b_factor.unwrap_or(0.0)
+            )?;
+        }
+    }
+
+    writeln!(&mut writer, "END")?;
+
+    Ok(())
+}
+
+/// Atom names shorter than four characters begin in the second column of the field.
+fn get_atom_name_field(name: &str) -> String {
+    if name.len() < 4 {
+        format!(" {}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum WriteError {
+    #[fail(display = "Error writing configuration ({})", _0)]
+    IoError(io::Error),
+    #[fail(display = "Error writing residue {}, which was incomplete", _0)]
+    BadResidue(usize),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::IoError(err)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum ReadError {
+    #[fail(display = "Could not read line {}: invalid UTF-8", _0)]
+    Utf8Error(usize),
+    #[fail(display = "Could not parse ATOM or HETATM record at line {}", _0)]
+    BadAtomRecord(usize),
+    #[fail(display = "Could not parse CRYST1 record at line {}", _0)]
+    BadCryst1Record(usize),
+}
+
+/// Read a configuration from the ATOM and HETATM records of a PDB file.
+///
+/// The title is read from the TITLE records and the box size from the CRYST1 record,
+/// whose angles are assumed to be 90 degrees. Reading stops at the first END or
+/// ENDMDL record, so only the first model of the file is read.
+pub fn read_pdb_conf<R: Read>(reader: R) -> Result<Conf, ReadError> {
+    let buf_reader = BufReader::new(reader);
+
+    let mut titles = Vec::new();
+    let mut size = RVec::default();
+    let mut residues = Vec::new();
+    let mut atoms = Vec::new();
+
+    for (i, line) in buf_reader.lines().enumerate() {
+        let line = line.map_err(|_| ReadError::Utf8Error(i + 1))?;
+
+        if line.starts_with("ATOM") || line.starts_with("HETATM") {
+            let record = parse_atom_record(&line).ok_or(ReadError::BadAtomRecord(i + 1))?;
+
+            let (residue, atom) = get_or_insert_atom_and_residue(
+                record.residue_name,
+                record.atom_name,
+                &mut residues,
+            )
+            .map_err(|_| ReadError::BadAtomRecord(i + 1))?;
+
+            atoms.push(Atom {
+                name: atom,
+                residue,
+                position: record.position,
+                velocity: None,
+                charge: None,
+                occupancy: record.occupancy,
+                b_factor: record.b_factor,
+                residue_number: None,
+                atom_number: None,
+            });
+        } else if line.starts_with("CRYST1") {
+            size = line
+                .get(6..33)
+                .and_then(|s| RVec::from_fixed(s, 9).ok())
+                .ok_or(ReadError::BadCryst1Record(i + 1))?
+                / ANGSTROM_PER_NM;
+        } else if line.starts_with("TITLE") {
+            titles.push(line.get(10..).unwrap_or("").trim().to_string());
+        } else if line.starts_with("END") {
+            break;
+        }
+    }
+
+    Ok(Conf {
+        title: titles.join(" "),
+        origin: RVec::default(),
+        size,
+        residues,
+        atoms,
+    })
+}
+
+struct AtomRecord<'a> {
+    residue_name: &'a str,
+    atom_name: &'a str,
+    position: RVec,
+    occupancy: Option<f64>,
+    b_factor: Option<f64>,
+}
+
+fn parse_atom_record(line: &str) -> Option<AtomRecord<'_>> {
+    let atom_name = line.get(12..16)?.trim();
+    let residue_name = line.get(17..21)?.trim();
+    let position = RVec::from_fixed(line.get(30..54)?, 8).ok()?;
+
+    Some(AtomRecord {
+        residue_name,
+        atom_name,
+        position: position / ANGSTROM_PER_NM,
+        occupancy: parse_optional_field(line, 54..60)?,
+        b_factor: parse_optional_field(line, 60..66)?,
+    })
+}
+
+/// Parse a value from an optional column range, which may be missing or blank.
+///
+/// Returns `None` if the value is present but could not be parsed.
+fn parse_optional_field(line: &str, range: Range<usize>) -> Option<Option<f64>> {
+    let end = range.end.min(line.len());
+    let field = line.get(range.start.min(end)..end)?.trim();
+
+    if field.is_empty() {
+        Some(None)
+    } else {
+        field.parse::<f64>().ok().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use conf::Residue;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[test]
+    fn read_pdb_file_with_two_residues() {
+        let content = "\
+TITLE     A title
+CRYST1   10.000   20.000   30.000  90.00  90.00  90.00 P 1           1
+ATOM      1  OW  SOL     1       1.000   2.000   3.000  1.00  0.00
+ATOM      2  HW1 SOL     1       4.000   5.000   6.000  1.00  0.00
+HETATM    3 NA    NA     2       7.000   8.000   9.000  1.00  0.00
+END
+ATOM      4  OW  SOL     3       1.000   2.000   3.000  1.00  0.00
+";
+
+        let conf = read_pdb_conf(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.title, "A title");
+        assert_eq!(
+            conf.size,
+            RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            }
+        );
+
+        // Atoms after the END record are not read
+        assert_eq!(conf.atoms.len(), 3);
+
+        assert_eq!(conf.residues.len(), 2);
+        assert_eq!(*conf.residues[0].borrow().name.borrow(), "SOL");
+        assert_eq!(*conf.residues[1].borrow().name.borrow(), "NA");
+        assert_eq!(conf.residues[0].borrow().atoms.len(), 2);
+        assert_eq!(&*conf.residues[0].borrow().atoms[1].borrow(), "HW1");
+
+        assert!(Rc::ptr_eq(&conf.atoms[2].residue, &conf.residues[1]));
+        assert_eq!(&*conf.atoms[2].name.borrow(), "NA");
+
+        let position = conf.atoms[1].position;
+        assert!((position.x - 0.4).abs() < 1e-9);
+        assert!((position.y - 0.5).abs() < 1e-9);
+        assert!((position.z - 0.6).abs() < 1e-9);
+        assert_eq!(conf.atoms[1].velocity, None);
+    }
+
+    #[test]
+    fn read_bad_atom_record_returns_error() {
+        let content = "ATOM      1  OW  SOL     1       1.000   2.0s0   3.000  1.00  0.00\n";
+        assert!(read_pdb_conf(content.as_bytes()).is_err());
+
+        let content = "ATOM      1  OW  SOL     1       1.000   2.000\n";
+        assert!(read_pdb_conf(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn write_and_read_pdb_conf() {
+        let residues = vec![
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES1".to_string())),
+                atoms: vec![
+                    Rc::new(RefCell::new("AT1".to_string())),
+                    Rc::new(RefCell::new("ATOM".to_string())),
+                ],
+            })),
+            Rc::new(RefCell::new(Residue {
+                name: Rc::new(RefCell::new("RES2".to_string())),
+                atoms: vec![Rc::new(RefCell::new("AT3".to_string()))],
+            })),
+        ];
+
+        let conf = Conf {
+            title: "A title".to_string(),
+            origin: RVec::default(),
+            size: RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            },
+            residues: residues.clone(),
+            atoms: vec![
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.1,
+                        y: 0.2,
+                        z: 0.3,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[0].borrow().atoms[1]),
+                    residue: Rc::clone(&residues[0]),
+                    position: RVec {
+                        x: 0.4,
+                        y: 0.5,
+                        z: 0.6,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+                Atom {
+                    name: Rc::clone(&residues[1].borrow().atoms[0]),
+                    residue: Rc::clone(&residues[1]),
+                    position: RVec {
+                        x: 0.7,
+                        y: 0.8,
+                        z: 0.9,
+                    },
+                    velocity: None,
+                    charge: None,
+                    occupancy: None,
+                    b_factor: None,
+                    residue_number: None,
+                    atom_number: None,
+                },
+            ],
+        };
+
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        assert!(write_pdb_conf(&conf, &mut buf).is_ok());
+
+        buf.set_position(0);
+        let read_conf = read_pdb_conf(buf).unwrap();
+
+        assert_eq!(read_conf.title, conf.title);
+        assert!(read_conf.size.distance(&conf.size) < 1e-9);
+        assert_eq!(read_conf.residues.len(), 2);
+        assert_eq!(read_conf.atoms.len(), conf.atoms.len());
+
+        for (read_atom, atom) in read_conf.atoms.iter().zip(conf.atoms.iter()) {
+            assert_eq!(*read_atom.name.borrow(), *atom.name.borrow());
+            assert_eq!(
+                *read_atom.residue.borrow().name,
+                *atom.residue.borrow().name
+            );
+            assert!(read_atom.position.distance(&atom.position) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn occupancy_and_b_factor_are_read_and_written() {
+        let content = "\
+ATOM      1  OW  SOL     1       1.000   2.000   3.000  0.50 12.34
+ATOM      2  HW1 SOL     1       4.000   5.000   6.000
+ATOM      3  HW2 SOL     1       7.000   8.000   9.000  1.00 xx.xx
+";
+        assert!(read_pdb_conf(content.as_bytes()).is_err());
+
+        let content = "\
+ATOM      1  OW  SOL     1       1.000   2.000   3.000  0.50 12.34
+ATOM      2  HW1 SOL     1       4.000   5.000   6.000
+";
+        let conf = read_pdb_conf(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.atoms[0].occupancy, Some(0.5));
+        assert_eq!(conf.atoms[0].b_factor, Some(12.34));
+        assert_eq!(conf.atoms[1].occupancy, None);
+        assert_eq!(conf.atoms[1].b_factor, None);
+
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        assert!(write_pdb_conf(&conf, &mut buf).is_ok());
+
+        buf.set_position(0);
+        let read_conf = read_pdb_conf(buf).unwrap();
+
+        assert_eq!(read_conf.atoms[0].occupancy, Some(0.5));
+        assert_eq!(read_conf.atoms[0].b_factor, Some(12.34));
+
+        // Missing values are written as the defaults
+        assert_eq!(read_conf.atoms[1].occupancy, Some(1.0));
+        assert_eq!(read_conf.atoms[1].b_factor, Some(0.0));
+    }
+}
@@ -1,14 +1,23 @@
+use std::cmp::Ordering;
+#[cfg(feature = "ndarray")]
+use std::convert::TryFrom;
 use std::default::Default;
+use std::error::Error;
 use std::f64;
-use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
 
 /// Directions in a carthesian 3-dimensional system.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Direction {
     X,
     Y,
     Z,
 }
 
+/// Maximum number of bits per component in Morton keys, to fit all three in a `u64`.
+const MAX_MORTON_BITS: u32 = 21;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 /// Shorthand for a 3-vector.
 pub struct RVec {
@@ -21,9 +30,99 @@ pub struct RVec {
 pub enum ParseRVecError {
     MissingValues,
     ParseFloatError,
+    /// The input had a number of values which is not accepted, given here.
+    WrongNumberOfValues(usize),
+}
+
+impl fmt::Display for ParseRVecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseRVecError::MissingValues => write!(f, "missing vector values"),
+            ParseRVecError::ParseFloatError => write!(f, "could not parse float"),
+            ParseRVecError::WrongNumberOfValues(num) => {
+                write!(f, "wrong number of vector values ({})", num)
+            }
+        }
+    }
 }
 
+impl Error for ParseRVecError {}
+
 impl RVec {
+    /// Return the vector rounded to a grid of the input resolution, as integer grid
+    /// indices. Unlike the vector itself this can be used as a key in hash maps.
+    pub fn quantized(&self, resolution: f64) -> (i64, i64, i64) {
+        let quantize = |x: f64| (x / resolution).round() as i64;
+
+        (quantize(self.x), quantize(self.y), quantize(self.z))
+    }
+
+    /// Return the vector component along a `Direction`.
+    pub fn get(&self, dir: Direction) -> f64 {
+        match dir {
+            Direction::X => self.x,
+            Direction::Y => self.y,
+            Direction::Z => self.z,
+        }
+    }
+
+    /// Return a mutable reference to the vector component along a `Direction`.
+    pub fn get_mut(&mut self, dir: Direction) -> &mut f64 {
+        match dir {
+            Direction::X => &mut self.x,
+            Direction::Y => &mut self.y,
+            Direction::Z => &mut self.z,
+        }
+    }
+
+    /// Return the dot product of two vectors.
+    pub fn dot(&self, other: &RVec) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Return the angle between two vectors in radians, in the range 0 to π.
+    /// The angle is 0 if either vector is zero.
+    pub fn angle(&self, other: &RVec) -> f64 {
+        let norms = self.norm() * other.norm();
+
+        if norms == 0.0 {
+            return 0.0;
+        }
+
+        (self.dot(other) / norms).clamp(-1.0, 1.0).acos()
+    }
+
+    /// Return the vector rotated by an angle in radians around an axis through
+    /// the origin, counter-clockwise when looking against the axis. The vector is
+    /// not rotated if the axis is zero.
+    pub fn rotate(&self, axis: &RVec, angle: f64) -> RVec {
+        let norm = axis.norm();
+
+        if norm == 0.0 {
+            return *self;
+        }
+
+        // Rodrigues' rotation formula
+        let k = *axis / norm;
+        let (sin, cos) = angle.sin_cos();
+
+        *self * cos + k.cross(self) * sin + k * (k.dot(self) * (1.0 - cos))
+    }
+
+    /// Return the cross product of two vectors.
+    pub fn cross(&self, other: &RVec) -> RVec {
+        RVec {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    /// Return the length of the vector.
+    pub fn norm(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
     /// Return the absolute distance between two vectors.
     pub fn distance(&self, other: &RVec) -> f64 {
         f64::sqrt(
@@ -31,6 +130,11 @@ impl RVec {
         )
     }
 
+    /// Return the squared distance between two vectors.
+    pub fn distance_squared(&self, other: &RVec) -> f64 {
+        (self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)
+    }
+
     /// Return the cylindrical distance between two vectors and along an input `Direction`
     /// as a (dr, dh) tuple. For the height difference, the second value (the other)
     /// is subtracted from the first (self).
@@ -44,27 +148,78 @@ impl RVec {
         }
     }
 
+    /// Return the displacement vector from the other vector to this one, using
+    /// the minimum image convention in a periodic box of the input size.
+    ///
+    /// Dimensions with a non-positive box size are not treated as periodic.
+    pub fn displacement_pbc(&self, other: &RVec, box_size: &RVec) -> RVec {
+        let min_image = |dx: f64, size: f64| {
+            if size > 0.0 {
+                dx - size * (dx / size).round()
+            } else {
+                dx
+            }
+        };
+
+        RVec {
+            x: min_image(self.x - other.x, box_size.x),
+            y: min_image(self.y - other.y, box_size.y),
+            z: min_image(self.z - other.z, box_size.z),
+        }
+    }
+
+    /// Return the periodic image of a point which is closest to a reference point,
+    /// by shifting the point with multiples of the input box size.
+    ///
+    /// Dimensions with a non-positive box size are not treated as periodic.
+    pub fn closest_image(point: RVec, reference: RVec, box_size: &RVec) -> RVec {
+        reference + point.displacement_pbc(&reference, box_size)
+    }
+
+    /// Return the absolute distance between two vectors, using the minimum image
+    /// convention in a periodic box of the input size.
+    pub fn distance_pbc(&self, other: &RVec, box_size: &RVec) -> f64 {
+        let dr = self.displacement_pbc(other, box_size);
+
+        f64::sqrt(dr.x.powi(2) + dr.y.powi(2) + dr.z.powi(2))
+    }
+
     pub fn from_fixed(input: &str, length: usize) -> Result<RVec, ParseRVecError> {
+        RVec::from_fixed_widths(input, [length; 3])
+    }
+
+    /// Parse a vector from fixed width fields, with the field widths given for each value.
+    pub fn from_fixed_widths(input: &str, widths: [usize; 3]) -> Result<RVec, ParseRVecError> {
         use std::str::from_utf8;
 
         if input.trim().is_empty() {
             return Err(ParseRVecError::MissingValues);
         }
 
-        let mut iter = input
-            .as_bytes()
-            .chunks(length)
-            .map(|chunk| from_utf8(chunk).map_err(|_| ParseRVecError::ParseFloatError))
-            .map(|s| {
-                s?.trim()
-                    .parse::<f64>()
-                    .map_err(|_| ParseRVecError::ParseFloatError)
-            });
+        let bytes = input.as_bytes();
+        let mut start = 0;
+        let mut values = [0.0; 3];
+
+        for (value, width) in values.iter_mut().zip(widths.iter()) {
+            if start >= bytes.len() {
+                return Err(ParseRVecError::MissingValues);
+            }
+
+            let end = (start + width).min(bytes.len());
+
+            *value = from_utf8(&bytes[start..end])
+                .map_err(|_| ParseRVecError::ParseFloatError)?
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| ParseRVecError::ParseFloatError)?;
+
+            start = end;
+        }
 
         Ok(RVec {
-            x: iter.next().ok_or(ParseRVecError::MissingValues)??,
-            y: iter.next().ok_or(ParseRVecError::MissingValues)??,
-            z: iter.next().ok_or(ParseRVecError::MissingValues)??,
+            x: values[0],
+            y: values[1],
+            z: values[2],
         })
     }
 
@@ -92,6 +247,49 @@ impl RVec {
     pub fn to_tuple(&self) -> (f64, f64, f64) {
         (self.x, self.y, self.z)
     }
+
+    /// Compare two vectors by their x, then y, then z components. The components are
+    /// compared with `f64::total_cmp`, which gives a total order also for NaN values.
+    pub fn lexicographic_cmp(&self, other: &RVec) -> Ordering {
+        self.x
+            .total_cmp(&other.x)
+            .then_with(|| self.y.total_cmp(&other.y))
+            .then_with(|| self.z.total_cmp(&other.z))
+    }
+
+    /// Return the Morton (Z-order) key of the vector inside a box spanning from zero
+    /// to the input size. Sorting points by their keys places points which are close
+    /// in space close to each other.
+    ///
+    /// Every component is quantized into `bits` bits, at most 21, and the bits of
+    /// the components are interleaved with x as the least significant. Components
+    /// outside of the box are clamped to its edges and directions with a non-positive
+    /// box size quantize to zero.
+    pub fn morton_key(&self, box_size: &RVec, bits: u32) -> u64 {
+        let bits = bits.min(MAX_MORTON_BITS);
+        let max_index = (1u64 << bits) - 1;
+
+        let quantize = |x: f64, size: f64| {
+            if size > 0.0 {
+                // Negative values and NaN saturate to zero in the cast
+                ((x / size * (1u64 << bits) as f64) as u64).min(max_index)
+            } else {
+                0
+            }
+        };
+
+        let indices = [
+            quantize(self.x, box_size.x),
+            quantize(self.y, box_size.y),
+            quantize(self.z, box_size.z),
+        ];
+
+        (0..bits).fold(0, |key, bit| {
+            indices.iter().enumerate().fold(key, |key, (i, index)| {
+                key | ((index >> bit) & 1) << (3 * bit + i as u32)
+            })
+        })
+    }
 }
 
 impl Default for RVec {
@@ -144,6 +342,30 @@ impl SubAssign for RVec {
     }
 }
 
+impl Mul<f64> for RVec {
+    type Output = RVec;
+
+    fn mul(self, factor: f64) -> Self::Output {
+        RVec {
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+        }
+    }
+}
+
+impl Div<f64> for RVec {
+    type Output = RVec;
+
+    fn div(self, denominator: f64) -> Self::Output {
+        RVec {
+            x: self.x / denominator,
+            y: self.y / denominator,
+            z: self.z / denominator,
+        }
+    }
+}
+
 impl Neg for RVec {
     type Output = RVec;
 
@@ -156,9 +378,161 @@ impl Neg for RVec {
     }
 }
 
+#[cfg(feature = "nalgebra")]
+impl From<RVec> for nalgebra::Vector3<f64> {
+    fn from(rvec: RVec) -> Self {
+        nalgebra::Vector3::new(rvec.x, rvec.y, rvec.z)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl From<nalgebra::Vector3<f64>> for RVec {
+    fn from(vector: nalgebra::Vector3<f64>) -> Self {
+        RVec {
+            x: vector.x,
+            y: vector.y,
+            z: vector.z,
+        }
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl From<RVec> for ndarray::Array1<f64> {
+    fn from(rvec: RVec) -> Self {
+        ndarray::arr1(&[rvec.x, rvec.y, rvec.z])
+    }
+}
+
+/// Convert an array of three elements into a vector. Arrays of other lengths
+/// are returned as the error.
+#[cfg(feature = "ndarray")]
+impl TryFrom<ndarray::Array1<f64>> for RVec {
+    type Error = ndarray::Array1<f64>;
+
+    fn try_from(array: ndarray::Array1<f64>) -> Result<Self, Self::Error> {
+        if array.len() != 3 {
+            return Err(array);
+        }
+
+        Ok(RVec {
+            x: array[0],
+            y: array[1],
+            z: array[2],
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn lexicographic_ordering_of_vectors() {
+        let rvec = |x, y, z| RVec { x, y, z };
+
+        let mut rvecs = [
+            rvec(1.0, 0.0, 0.0),
+            rvec(0.0, 2.0, 1.0),
+            rvec(0.0, 2.0, 0.0),
+            rvec(f64::NAN, 0.0, 0.0),
+            rvec(-1.0, 5.0, 5.0),
+        ];
+
+        rvecs.sort_by(|a, b| a.lexicographic_cmp(b));
+
+        assert_eq!(rvecs[0], rvec(-1.0, 5.0, 5.0));
+        assert_eq!(rvecs[1], rvec(0.0, 2.0, 0.0));
+        assert_eq!(rvecs[2], rvec(0.0, 2.0, 1.0));
+        assert_eq!(rvecs[3], rvec(1.0, 0.0, 0.0));
+        assert!(rvecs[4].x.is_nan());
+
+        assert_eq!(
+            rvec(1.0, 2.0, 3.0).lexicographic_cmp(&rvec(1.0, 2.0, 3.0)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn morton_keys_interleave_quantized_components() {
+        let box_size = RVec {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        };
+
+        let rvec = |x, y, z| RVec { x, y, z };
+
+        assert_eq!(rvec(0.0, 0.0, 0.0).morton_key(&box_size, 1), 0);
+        assert_eq!(rvec(0.75, 0.25, 0.75).morton_key(&box_size, 1), 0b101);
+        assert_eq!(rvec(0.75, 0.0, 0.5).morton_key(&box_size, 2), 0b101_001);
+        assert_eq!(rvec(0.25, 0.5, 0.0).morton_key(&box_size, 2), 0b010_001);
+
+        // Values outside of the box are clamped to its edges
+        assert_eq!(rvec(-1.0, 2.0, 1.0).morton_key(&box_size, 1), 0b110);
+    }
+
+    #[test]
+    fn morton_keys_preserve_locality_of_nearby_points() {
+        let box_size = RVec {
+            x: 10.0,
+            y: 10.0,
+            z: 10.0,
+        };
+
+        let point = RVec {
+            x: 2.0,
+            y: 3.0,
+            z: 4.0,
+        };
+        let nearby = RVec {
+            x: 2.01,
+            y: 3.01,
+            z: 4.01,
+        };
+        let distant = RVec {
+            x: 8.0,
+            y: 7.0,
+            z: 9.0,
+        };
+
+        let key = |rvec: RVec| rvec.morton_key(&box_size, 10) as i64;
+
+        assert!((key(point) - key(nearby)).abs() < (key(point) - key(distant)).abs());
+        // The nearby points share the most significant bits of their keys, which
+        // are those of the coarsest grid levels
+        assert_eq!(key(point) >> 21, key(nearby) >> 21);
+    }
+
+    #[cfg(feature = "nalgebra")]
+    #[test]
+    fn convert_rvec_to_and_from_nalgebra_vector() {
+        let rvec = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+
+        let vector: nalgebra::Vector3<f64> = rvec.into();
+        assert_eq!(vector, nalgebra::Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(RVec::from(vector), rvec);
+    }
+
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn convert_rvec_to_and_from_ndarray_array() {
+        let rvec = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+
+        let array: ndarray::Array1<f64> = rvec.into();
+        assert_eq!(array, ndarray::arr1(&[1.0, 2.0, 3.0]));
+        assert_eq!(RVec::try_from(array).unwrap(), rvec);
+
+        assert!(RVec::try_from(ndarray::arr1(&[1.0, 2.0])).is_err());
+    }
 
     #[test]
     fn parse_rvec_from_fixed_string() {
@@ -238,6 +612,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_rvec_from_fixed_string_with_different_widths() {
+        assert_eq!(
+            RVec::from_fixed_widths("  1.000  2.0000  3.00000", [7, 8, 9]),
+            Ok(RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            })
+        );
+        assert_eq!(
+            RVec::from_fixed_widths("1.02.0003.0", [3, 5, 3]),
+            Ok(RVec {
+                x: 1.0,
+                y: 2.0,
+                z: 3.0,
+            })
+        );
+
+        assert_eq!(
+            RVec::from_fixed_widths("  1.000  2.0000", [7, 8, 9]),
+            Err(ParseRVecError::MissingValues)
+        );
+        assert_eq!(
+            RVec::from_fixed_widths("  1.000  2.0s00  3.00000", [7, 8, 9]),
+            Err(ParseRVecError::ParseFloatError)
+        );
+    }
+
     #[test]
     fn parse_rvec_from_whitespace_separated_string() {
         assert_eq!(
@@ -390,6 +793,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn mul_and_div_rvec_with_f64() {
+        let r = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        assert_eq!(
+            r * 2.0,
+            RVec {
+                x: 2.0,
+                y: 4.0,
+                z: 6.0,
+            }
+        );
+        assert_eq!(
+            r / 2.0,
+            RVec {
+                x: 0.5,
+                y: 1.0,
+                z: 1.5,
+            }
+        );
+    }
+
     #[test]
     fn neg_rvec_operator() {
         let (x, y, z) = (1.0, 2.0, 3.0);
@@ -419,6 +847,7 @@ mod tests {
         let dr2 = (r1.x - r2.x).powi(2) + (r1.y - r2.y).powi(2) + (r1.z - r2.z).powi(2);
 
         assert_eq!(r1.distance(&r2), dr2.sqrt());
+        assert_eq!(r1.distance_squared(&r2), dr2);
     }
 
     #[test]
@@ -457,4 +886,211 @@ mod tests {
 
         assert_eq!(origo, RVec::default());
     }
+
+    #[test]
+    fn displacement_pbc_points_the_short_way_across_the_box() {
+        let box_size = RVec {
+            x: 10.0,
+            y: 10.0,
+            z: 10.0,
+        };
+        let r1 = RVec {
+            x: 9.5,
+            y: 1.0,
+            z: 2.0,
+        };
+        let r2 = RVec {
+            x: 0.5,
+            y: 2.0,
+            z: 2.0,
+        };
+
+        let dr = r1.displacement_pbc(&r2, &box_size);
+        assert!((dr.x - (-1.0)).abs() < 1e-9);
+        assert!((dr.y - (-1.0)).abs() < 1e-9);
+        assert_eq!(dr.z, 0.0);
+
+        let dr_norm = (dr.x.powi(2) + dr.y.powi(2) + dr.z.powi(2)).sqrt();
+        assert_eq!(dr_norm, r1.distance_pbc(&r2, &box_size));
+        assert!((r1.distance_pbc(&r2, &box_size) - 2.0f64.sqrt()).abs() < 1e-9);
+
+        // Dimensions without a box size are not periodic
+        let dr = r1.displacement_pbc(&r2, &RVec::default());
+        assert_eq!(dr, r1 - r2);
+    }
+
+    #[test]
+    fn closest_image_is_adjacent_to_the_reference() {
+        let box_size = RVec {
+            x: 2.0,
+            y: 3.0,
+            z: 0.0,
+        };
+        let reference = RVec {
+            x: 0.5,
+            y: 0.5,
+            z: 0.5,
+        };
+        let point = RVec {
+            x: 2.25,
+            y: 0.25,
+            z: 5.0,
+        };
+
+        assert_eq!(
+            RVec::closest_image(point, reference, &box_size),
+            RVec {
+                x: 0.25,
+                y: 0.25,
+                z: 5.0,
+            }
+        );
+
+        let point = RVec {
+            x: 0.5,
+            y: -2.25,
+            z: 0.5,
+        };
+
+        assert_eq!(
+            RVec::closest_image(point, reference, &box_size),
+            RVec {
+                x: 0.5,
+                y: 0.75,
+                z: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn get_rvec_component_along_direction() {
+        let r = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+
+        assert_eq!(r.get(Direction::X), 1.0);
+        assert_eq!(r.get(Direction::Y), 2.0);
+        assert_eq!(r.get(Direction::Z), 3.0);
+    }
+
+    #[test]
+    fn set_rvec_component_along_direction() {
+        let mut r = RVec::default();
+
+        *r.get_mut(Direction::Y) = 2.0;
+        *r.get_mut(Direction::Z) -= 3.0;
+
+        assert_eq!(
+            r,
+            RVec {
+                x: 0.0,
+                y: 2.0,
+                z: -3.0,
+            }
+        );
+    }
+
+    #[test]
+    fn quantize_nearby_rvecs_to_grid_indices() {
+        let r1 = RVec {
+            x: 1.001,
+            y: -2.002,
+            z: 3.0,
+        };
+        let r2 = RVec {
+            x: 1.003,
+            y: -2.001,
+            z: 3.004,
+        };
+
+        assert_eq!(r1.quantized(0.1), (10, -20, 30));
+        assert_eq!(r1.quantized(0.1), r2.quantized(0.1));
+        assert_ne!(r1.quantized(0.001), r2.quantized(0.001));
+    }
+
+    #[test]
+    fn dot_and_cross_products_of_rvecs() {
+        let r1 = RVec {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+        };
+        let r2 = RVec {
+            x: 4.0,
+            y: 5.0,
+            z: 6.0,
+        };
+
+        assert_eq!(r1.dot(&r2), 32.0);
+        assert_eq!(r1.norm(), 14.0f64.sqrt());
+        assert_eq!(
+            r1.cross(&r2),
+            RVec {
+                x: -3.0,
+                y: 6.0,
+                z: -3.0,
+            }
+        );
+        assert_eq!(r1.cross(&r2).dot(&r1), 0.0);
+    }
+
+    #[test]
+    fn angle_between_and_rotation_of_rvecs() {
+        let x = RVec {
+            x: 2.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        let y = RVec {
+            x: 0.0,
+            y: 3.0,
+            z: 0.0,
+        };
+        let z = x.cross(&y);
+
+        assert!((x.angle(&y) - PI / 2.0).abs() < 1e-12);
+        assert!((x.angle(&-x) - PI).abs() < 1e-12);
+        assert_eq!(x.angle(&x), 0.0);
+        assert_eq!(x.angle(&RVec::default()), 0.0);
+
+        let expected = RVec {
+            x: 0.0,
+            y: 2.0,
+            z: 0.0,
+        };
+        assert!(x.rotate(&z, PI / 2.0).distance(&expected) < 1e-12);
+
+        // Components along the axis are unchanged
+        let r = RVec {
+            x: 1.0,
+            y: 0.0,
+            z: 5.0,
+        };
+        let expected = RVec {
+            x: -1.0,
+            y: 0.0,
+            z: 5.0,
+        };
+        assert!(r.rotate(&z, PI).distance(&expected) < 1e-12);
+
+        assert_eq!(r.rotate(&RVec::default(), 1.0), r);
+    }
+
+    #[test]
+    fn display_parse_errors() {
+        assert_eq!(
+            format!("{}", ParseRVecError::MissingValues),
+            "missing vector values"
+        );
+        assert_eq!(
+            format!("{}", ParseRVecError::ParseFloatError),
+            "could not parse float"
+        );
+        assert_eq!(
+            format!("{}", ParseRVecError::WrongNumberOfValues(4)),
+            "wrong number of vector values (4)"
+        );
+    }
 }
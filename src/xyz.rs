@@ -0,0 +1,193 @@
+use conf::{get_or_insert_atom_and_residue, Atom, Conf};
+use rvec::RVec;
+
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// XYZ files use Ångström, we use nm.
+const ANGSTROM_PER_NM: f64 = 10.0;
+
+pub fn write_xyz_conf<W: Write>(conf: &Conf, mut writer: &mut W) -> Result<(), WriteError> {
+    writeln!(&mut writer, "{}\n{}", conf.atoms.len(), conf.title)?;
+
+    for atom in &conf.atoms {
+        let position = atom.position * ANGSTROM_PER_NM;
+
+        writeln!(
+            &mut writer,
+            "{:<5} {:12.5} {:12.5} {:12.5}",
+            *atom.name.borrow(),
+            position.x,
+            position.y,
+            position.z
+        )?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Fail)]
+pub enum WriteError {
+    #[fail(display = "Error writing configuration ({})", _0)]
+    IoError(io::Error),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> WriteError {
+        WriteError::IoError(err)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum ReadError {
+    #[fail(display = "Could not read line {}: invalid UTF-8", _0)]
+    Utf8Error(usize),
+    #[fail(display = "Could not parse number of atoms entry at line 1")]
+    NumAtomsError,
+    #[fail(display = "Expected an atom entry at line {}", _0)]
+    MissingAtomLine(usize),
+    #[fail(display = "Could not parse atom entry at line {}", _0)]
+    LineError(usize),
+}
+
+/// Read a configuration from an XYZ file.
+///
+/// The format has no residues, so every atom is put in a residue of its own name.
+/// It also has no box, which is left empty.
+pub fn read_xyz_conf<R: Read>(reader: R) -> Result<Conf, ReadError> {
+    let mut lines = BufReader::new(reader).lines();
+
+    let num_atoms = lines
+        .next()
+        .ok_or(ReadError::NumAtomsError)?
+        .map_err(|_| ReadError::Utf8Error(1))?
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| ReadError::NumAtomsError)?;
+
+    let title = match lines.next() {
+        Some(line) => line
+            .map_err(|_| ReadError::Utf8Error(2))?
+            .trim()
+            .to_string(),
+        None => String::new(),
+    };
+
+    let mut residues = Vec::new();
+    let mut atoms = Vec::new();
+
+    for i in 0..num_atoms {
+        let line_num = 3 + i;
+
+        let line = lines
+            .next()
+            .ok_or(ReadError::MissingAtomLine(line_num))?
+            .map_err(|_| ReadError::Utf8Error(line_num))?;
+
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let name = fields.next().unwrap_or("").trim();
+        let position = fields
+            .next()
+            .and_then(|s| RVec::from_whitespace(s.trim()).ok())
+            .ok_or(ReadError::LineError(line_num))?;
+
+        if name.is_empty() {
+            return Err(ReadError::LineError(line_num));
+        }
+
+        let (residue, atom) = get_or_insert_atom_and_residue(name, name, &mut residues)
+            .map_err(|_| ReadError::LineError(line_num))?;
+
+        atoms.push(Atom {
+            name: atom,
+            residue,
+            position: position / ANGSTROM_PER_NM,
+            velocity: None,
+            charge: None,
+            occupancy: None,
+            b_factor: None,
+            residue_number: None,
+            atom_number: None,
+        });
+    }
+
+    Ok(Conf {
+        title,
+        origin: RVec::default(),
+        size: RVec::default(),
+        residues,
+        atoms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[test]
+    fn read_xyz_file_puts_each_atom_in_its_own_residue() {
+        let content = "\
+3
+A water molecule
+O      0.00000      0.00000      0.00000
+H      1.00000      0.00000      0.00000
+H      0.00000      1.00000      0.00000
+";
+
+        let conf = read_xyz_conf(content.as_bytes()).unwrap();
+
+        assert_eq!(conf.title, "A water molecule");
+        assert_eq!(conf.size, RVec::default());
+        assert_eq!(conf.atoms.len(), 3);
+
+        assert_eq!(conf.residues.len(), 2);
+        assert_eq!(*conf.residues[0].borrow().name.borrow(), "O");
+        assert_eq!(*conf.residues[1].borrow().name.borrow(), "H");
+        assert!(Rc::ptr_eq(&conf.atoms[1].residue, &conf.atoms[2].residue));
+        assert!(conf.iter_residues().all(|residue| residue.is_ok()));
+
+        assert_eq!(
+            conf.atoms[1].position,
+            RVec {
+                x: 0.1,
+                y: 0.0,
+                z: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn read_incorrect_xyz_file_returns_error() {
+        assert!(read_xyz_conf("".as_bytes()).is_err());
+        assert!(read_xyz_conf("s\ntitle\n".as_bytes()).is_err());
+        assert!(read_xyz_conf("2\ntitle\nO 0.0 0.0 0.0\n".as_bytes()).is_err());
+        assert!(read_xyz_conf("1\ntitle\nO 0.0 0.0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn write_and_read_xyz_conf() {
+        let content = "\
+2
+A title
+O      1.00000      2.00000      3.00000
+H      4.00000      5.00000      6.00000
+";
+        let conf = read_xyz_conf(content.as_bytes()).unwrap();
+
+        let mut buf = Cursor::new(Vec::<u8>::new());
+        assert!(write_xyz_conf(&conf, &mut buf).is_ok());
+
+        buf.set_position(0);
+        let read_conf = read_xyz_conf(buf).unwrap();
+
+        assert_eq!(read_conf.title, conf.title);
+        assert_eq!(read_conf.atoms.len(), conf.atoms.len());
+
+        for (read_atom, atom) in read_conf.atoms.iter().zip(conf.atoms.iter()) {
+            assert_eq!(*read_atom.name.borrow(), *atom.name.borrow());
+            assert!(read_atom.position.distance(&atom.position) < 1e-9);
+        }
+    }
+}